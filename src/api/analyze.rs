@@ -0,0 +1,73 @@
+use rocket::State;
+use rocket::data::{Data, Limits};
+use rocket::http::ContentType;
+use rocket::serde::json::Json;
+use rocket_apitoken::Authorized;
+
+use crate::api::multipart::MultipartUpload;
+use crate::api::upload::{AudioBody, raw_body};
+use crate::audio::{Analysis, wav_read_analyze};
+use crate::bridge::read_blocking;
+use crate::config::Config;
+use crate::error::WaveemapiError;
+
+/// Upload chunks buffered before the upload waits for the meters.
+const CHUNKS_BUFFERED: usize = 16;
+
+pub fn routes() -> Vec<rocket::Route> {
+    routes![analyze_raw, analyze_upload]
+}
+
+#[post("/", data = "<data>", rank = 1)]
+async fn analyze_raw(
+    _wav: AudioBody,
+    _auth: Authorized,
+    data: Data<'_>,
+    limits: &Limits,
+    config: &State<Config>,
+) -> Result<Json<Analysis>, WaveemapiError> {
+    let max_duration = config.max_duration();
+    read_blocking(raw_body(data, limits), CHUNKS_BUFFERED, move |reader| {
+        wav_read_analyze(reader, max_duration)
+    })
+    .await
+    .map(Json)
+}
+
+#[post("/", data = "<data>", rank = 2)]
+async fn analyze_upload(
+    _auth: Authorized,
+    content_type: Option<&ContentType>,
+    data: Data<'_>,
+    limits: &Limits,
+    config: &State<Config>,
+) -> Result<Json<Analysis>, WaveemapiError> {
+    let upload: MultipartUpload =
+        MultipartUpload::read(content_type, data, limits, config.cover_max_bytes()).await?;
+    let max_duration = config.max_duration();
+    read_blocking(upload.wav, CHUNKS_BUFFERED, move |reader| {
+        wav_read_analyze(reader, max_duration)
+    })
+    .await
+    .map(Json)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::rocket;
+    use rocket::http::{ContentType, Status};
+    use rocket::local::blocking::Client;
+
+    #[test]
+    fn test_analyze_auth_no_head() {
+        let client = Client::tracked(rocket()).expect("valid `Rocket`");
+        let response = client.post("/api/analyze").dispatch();
+        assert_eq!(response.status(), Status::Unauthorized);
+        let response = client
+            .post("/api/analyze")
+            .header(ContentType::new("audio", "flac"))
+            .body("fLaC")
+            .dispatch();
+        assert_eq!(response.status(), Status::Unauthorized);
+    }
+}
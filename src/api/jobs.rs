@@ -0,0 +1,161 @@
+use rocket::State;
+use rocket::data::{Data, Limits};
+use rocket::fs::NamedFile;
+use rocket::http::ContentType;
+use rocket::response::status::Accepted;
+use rocket::serde::{Serialize, json::Json};
+use rocket_apitoken::Authorized;
+use std::time::Duration;
+
+use crate::api::multipart::MultipartUpload;
+use crate::api::token::BearerToken;
+use crate::api::upload::{EncodeRequest, PreparedUpload};
+use crate::config::Config;
+use crate::error::WaveemapiError;
+use crate::jobs::{Job, JobState, Jobs};
+use crate::webhook::{Webhook, check_callback_url};
+
+const WEBHOOK_BACKOFF: Duration = Duration::from_secs(2);
+
+pub fn routes() -> Vec<rocket::Route> {
+    routes![submit, status, result]
+}
+
+#[post("/", data = "<data>")]
+async fn submit(
+    _auth: Authorized,
+    token: BearerToken,
+    content_type: Option<&ContentType>,
+    data: Data<'_>,
+    limits: &Limits,
+    config: &State<Config>,
+    jobs: &State<Jobs>,
+) -> Result<Accepted<Json<JobResp>>, WaveemapiError> {
+    let upload: MultipartUpload =
+        MultipartUpload::read(content_type, data, limits, config.cover_max_bytes()).await?;
+    if upload.options.stream.unwrap_or(false) {
+        return Err(WaveemapiError::InvalidOptions(
+            "stream is only supported by /api/upload".to_string(),
+        ));
+    }
+    let callback_url = match upload.options.callback_url.as_deref() {
+        Some(url) if !url.trim().is_empty() => {
+            Some(check_callback_url(url, &config.webhook_allowed_hosts).await?)
+        }
+        _ => None,
+    };
+    let request = EncodeRequest::new(&upload.options, upload.cover, config)?;
+    let mut prepared = PreparedUpload::new(upload.wav, request, config.data_path.clone()).await?;
+    if upload.options.concat.unwrap_or(false) {
+        prepared = prepared.with_parts(upload.more).await?;
+    }
+    let webhook = callback_url.map(|url| Webhook {
+        url,
+        secret: token
+            .0
+            .as_ref()
+            .and_then(|token| config.webhook_secrets.get(token).cloned()),
+        public_url: config.public_url.clone(),
+        retries: config.webhook_retries,
+        backoff: WEBHOOK_BACKOFF,
+    });
+    let job = jobs.submit(
+        token.0,
+        prepared.files(),
+        move |progress| {
+            prepared
+                .join()
+                .and_then(PreparedUpload::normalize)
+                .and_then(|(prepared, _)| prepared.encode(progress))
+                .map(|(path, _)| path)
+        },
+        webhook,
+    );
+    Ok(Accepted(Json(JobResp::from(job.as_ref()))))
+}
+
+#[get("/<id>")]
+fn status(
+    _auth: Authorized,
+    token: BearerToken,
+    id: &str,
+    jobs: &State<Jobs>,
+) -> Result<Json<JobResp>, WaveemapiError> {
+    let job = jobs.get(id, token.0.as_deref())?;
+    Ok(Json(JobResp::from(job.as_ref())))
+}
+
+#[get("/<id>/result")]
+async fn result(
+    _auth: Authorized,
+    token: BearerToken,
+    id: &str,
+    jobs: &State<Jobs>,
+) -> Result<NamedFile, WaveemapiError> {
+    match jobs.get(id, token.0.as_deref())?.state() {
+        JobState::Done(path) => NamedFile::open(&path).await.map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => {
+                WaveemapiError::NotFound("job result has expired".to_string())
+            }
+            _ => WaveemapiError::Io(e),
+        }),
+        JobState::Failed(error) => Err(WaveemapiError::NotReady(format!("job failed: {}", error))),
+        _ => Err(WaveemapiError::NotReady("job has not finished".to_string())),
+    }
+}
+
+#[derive(Serialize)]
+pub(crate) struct JobResp {
+    pub id: String,
+    pub status: String,
+    pub progress: u8,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl From<&Job> for JobResp {
+    fn from(job: &Job) -> Self {
+        let state = job.state();
+        let error = match &state {
+            JobState::Failed(error) => Some(error.clone()),
+            _ => None,
+        };
+        JobResp {
+            id: job.id.to_string(),
+            status: state.name().to_string(),
+            progress: job.progress.get(),
+            error,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::api::jobs::JobResp;
+    use crate::rocket;
+    use rocket::local::blocking::Client;
+    use rocket::serde::json;
+
+    #[test]
+    fn test_jobs_auth_no_head() {
+        let client = Client::tracked(rocket()).expect("valid `Rocket`");
+        let response = client.post("/api/jobs").dispatch();
+        assert_eq!(response.status(), rocket::http::Status::Unauthorized);
+        let response = client
+            .get("/api/jobs/00000000-0000-0000-0000-000000000000")
+            .dispatch();
+        assert_eq!(response.status(), rocket::http::Status::Unauthorized);
+    }
+
+    #[test]
+    fn test_job_resp_serialization() {
+        let resp = JobResp {
+            id: "id".to_string(),
+            status: "running".to_string(),
+            progress: 42,
+            error: None,
+        };
+        let jsonser = json::to_string(&resp).unwrap();
+        assert_eq!(jsonser, r#"{"id":"id","status":"running","progress":42}"#);
+    }
+}
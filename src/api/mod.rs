@@ -1,8 +1,15 @@
+mod analyze;
 mod catcher;
+mod jobs;
+mod multipart;
+mod probe;
 mod status;
+mod token;
 mod upload;
+mod waveform;
 
 pub use crate::api::{
-    catcher::DefaultErrorResp, catcher::catchers, status::routes as status_routes,
-    upload::routes as upload_routes,
+    analyze::routes as analyze_routes, catcher::DefaultErrorResp, catcher::catchers,
+    jobs::routes as jobs_routes, probe::routes as probe_routes, status::routes as status_routes,
+    upload::routes as upload_routes, waveform::routes as waveform_routes,
 };
@@ -0,0 +1,185 @@
+use std::sync::{Arc, Mutex};
+
+use multer::{Constraints, Field, Multipart, SizeLimit};
+use rocket::data::{Data, Limits};
+use rocket::form::{Form, FromForm, ValueField};
+use rocket::futures::stream::{self, BoxStream, StreamExt};
+use rocket::http::ContentType;
+
+use crate::api::upload::UploadOptions;
+use crate::bridge::{Chunk, read_chunks};
+use crate::error::WaveemapiError;
+use crate::id3::Cover;
+
+/// The request body is read in chunks of this size.
+const BODY_CHUNK_BYTES: usize = 64 * 1024;
+
+/// A `multipart/form-data` upload read up to its `wav` field, which is left
+/// unread so it can be decoded as it arrives. Further `wav` fields can be
+/// read from `more`. Any other field sent after a `wav` fails the `wav`
+/// stream, since the options are needed before decoding starts. Fields `T`
+/// does not know are ignored.
+pub(crate) struct MultipartUpload<'r, T = UploadOptions> {
+    pub options: T,
+    pub cover: Option<Cover>,
+    pub wav: BoxStream<'r, Chunk>,
+    pub more: WavParts<'r>,
+}
+
+/// The body and the field following a `wav`, left by its stream once read
+/// to the end.
+type Rest<'r> = Arc<Mutex<Option<(Multipart<'r>, Option<Field<'r>>)>>>;
+
+/// The rest of a multipart body after its first `wav` field.
+pub(crate) struct WavParts<'r>(Rest<'r>);
+
+impl<'r> WavParts<'r> {
+    /// The next `wav` field. The previous one must have been read to the end,
+    /// there are no more parts otherwise.
+    pub(crate) async fn next(&mut self) -> Result<Option<BoxStream<'r, Chunk>>, WaveemapiError> {
+        let Some((mut multipart, field)) = self.0.lock().unwrap().take() else {
+            return Ok(None);
+        };
+        let field = match field {
+            Some(field) => Some(field),
+            None => multipart.next_field().await?,
+        };
+        Ok(field.map(|field| wav_stream(field, multipart, self.0.clone())))
+    }
+}
+
+/// The chunks of a `wav` field. Once it ends, the next field is read, to
+/// fail on options sent too late, and left in `rest` with the body.
+fn wav_stream<'r>(
+    field: Field<'r>,
+    multipart: Multipart<'r>,
+    rest: Rest<'r>,
+) -> BoxStream<'r, Chunk> {
+    stream::unfold(Some((field, multipart, rest)), |state| async move {
+        let (mut field, mut multipart, rest) = state?;
+        match field.chunk().await {
+            Ok(Some(chunk)) => return Some((Ok(chunk.to_vec()), Some((field, multipart, rest)))),
+            Ok(None) => drop(field),
+            Err(e) => return Some((Err(e.into()), None)),
+        }
+        // Unnamed fields are skipped like before the `wav`.
+        loop {
+            match multipart.next_field().await {
+                Ok(Some(next)) if next.name().is_none() => continue,
+                Ok(Some(next)) if next.name() != Some("wav") => {
+                    let name = next.name().unwrap_or_default();
+                    return Some((Err(late_field(name)), None));
+                }
+                Ok(next) => {
+                    *rest.lock().unwrap() = Some((multipart, next));
+                    return None;
+                }
+                Err(e) => return Some((Err(e.into()), None)),
+            }
+        }
+    })
+    .boxed()
+}
+
+fn late_field(name: &str) -> WaveemapiError {
+    WaveemapiError::InvalidOptions(format!(
+        "{} was sent after wav, fields must come before the wav file",
+        name
+    ))
+}
+
+impl<'r, T: for<'a> FromForm<'a>> MultipartUpload<'r, T> {
+    pub(crate) async fn read(
+        content_type: Option<&ContentType>,
+        data: Data<'r>,
+        limits: &Limits,
+        cover_max_bytes: u64,
+    ) -> Result<Self, WaveemapiError> {
+        let boundary = content_type
+            .filter(|ct| ct.is_form_data())
+            .and_then(|ct| multer::parse_boundary(ct.to_string()).ok())
+            .ok_or_else(|| {
+                WaveemapiError::UnsupportedMediaType(
+                    "expected a multipart/form-data body".to_string(),
+                )
+            })?;
+        let body_limit = limits.get("data-form").unwrap_or(Limits::DATA_FORM);
+        let constraints = Constraints::new().size_limit(
+            SizeLimit::new()
+                .whole_stream(body_limit.as_u64())
+                .per_field(limits.get("string").unwrap_or(Limits::STRING).as_u64())
+                .for_field("wav", limits.get("file").unwrap_or(Limits::FILE).as_u64())
+                .for_field("cover", body_limit.as_u64()),
+        );
+        let body = read_chunks(Box::pin(data.open(body_limit)), BODY_CHUNK_BYTES);
+        let mut multipart = Multipart::with_constraints(body, boundary, constraints);
+
+        let mut fields = Vec::new();
+        let mut cover = None;
+        while let Some(field) = multipart.next_field().await? {
+            match field.name() {
+                Some("wav") => {
+                    let options = Form::<T>::parse_iter(fields.iter().map(
+                        |(name, value): &(String, String)| {
+                            ValueField::from((name.as_str(), value.as_str()))
+                        },
+                    ))
+                    .map_err(|e| WaveemapiError::InvalidOptions(e.to_string()))?;
+                    let rest = Rest::default();
+                    return Ok(MultipartUpload {
+                        options,
+                        cover,
+                        wav: wav_stream(field, multipart, rest.clone()),
+                        more: WavParts(rest),
+                    });
+                }
+                Some("cover") => cover = read_cover(field, cover_max_bytes).await?,
+                Some(name) => {
+                    let name = name.to_string();
+                    fields.push((name, field.text().await?));
+                }
+                None => {}
+            }
+        }
+        Err(WaveemapiError::InvalidOptions(
+            "missing wav field".to_string(),
+        ))
+    }
+}
+
+/// An empty cover field counts as no cover.
+async fn read_cover(mut field: Field<'_>, max_bytes: u64) -> Result<Option<Cover>, WaveemapiError> {
+    let mut data = Vec::new();
+    while let Some(chunk) = field.chunk().await? {
+        data.extend_from_slice(&chunk);
+        if data.len() as u64 > max_bytes {
+            return Err(WaveemapiError::InvalidOptions(format!(
+                "cover exceeds the {} byte limit",
+                max_bytes
+            )));
+        }
+    }
+    if data.is_empty() {
+        return Ok(None);
+    }
+    Cover::new(data).map(Some)
+}
+
+/// A `multipart/form-data` body with `fields` in order.
+#[cfg(test)]
+pub(crate) fn form_data(fields: &[(&str, &[u8])]) -> (ContentType, Vec<u8>) {
+    const BOUNDARY: &str = "waveemapi-test-boundary";
+    let mut body = Vec::new();
+    for (name, value) in fields {
+        body.extend_from_slice(format!("--{}\r\n", BOUNDARY).as_bytes());
+        body.extend_from_slice(
+            format!("Content-Disposition: form-data; name=\"{}\"\r\n\r\n", name).as_bytes(),
+        );
+        body.extend_from_slice(value);
+        body.extend_from_slice(b"\r\n");
+    }
+    body.extend_from_slice(format!("--{}--\r\n", BOUNDARY).as_bytes());
+    let content_type =
+        ContentType::new("multipart", "form-data").with_params(("boundary", BOUNDARY));
+    (content_type, body)
+}
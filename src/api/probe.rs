@@ -0,0 +1,66 @@
+use rocket::State;
+use rocket::data::{Data, Limits};
+use rocket::http::ContentType;
+use rocket::serde::json::Json;
+use rocket_apitoken::Authorized;
+
+use crate::api::multipart::MultipartUpload;
+use crate::api::upload::{AudioBody, raw_body};
+use crate::audio::{Probe, probe};
+use crate::bridge::read_blocking;
+use crate::config::Config;
+use crate::error::WaveemapiError;
+
+/// Upload chunks buffered before the upload waits for the probe.
+const CHUNKS_BUFFERED: usize = 16;
+
+pub fn routes() -> Vec<rocket::Route> {
+    routes![probe_raw, probe_upload]
+}
+
+#[post("/", data = "<data>", rank = 1)]
+async fn probe_raw(
+    _wav: AudioBody,
+    _auth: Authorized,
+    data: Data<'_>,
+    limits: &Limits,
+) -> Result<Json<Probe>, WaveemapiError> {
+    read_blocking(raw_body(data, limits), CHUNKS_BUFFERED, probe)
+        .await
+        .map(Json)
+}
+
+#[post("/", data = "<data>", rank = 2)]
+async fn probe_upload(
+    _auth: Authorized,
+    content_type: Option<&ContentType>,
+    data: Data<'_>,
+    limits: &Limits,
+    config: &State<Config>,
+) -> Result<Json<Probe>, WaveemapiError> {
+    let upload: MultipartUpload =
+        MultipartUpload::read(content_type, data, limits, config.cover_max_bytes()).await?;
+    read_blocking(upload.wav, CHUNKS_BUFFERED, probe)
+        .await
+        .map(Json)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::rocket;
+    use rocket::http::{ContentType, Status};
+    use rocket::local::blocking::Client;
+
+    #[test]
+    fn test_probe_auth_no_head() {
+        let client = Client::tracked(rocket()).expect("valid `Rocket`");
+        let response = client.post("/api/probe").dispatch();
+        assert_eq!(response.status(), Status::Unauthorized);
+        let response = client
+            .post("/api/probe")
+            .header(ContentType::new("audio", "wav"))
+            .body("RIFF")
+            .dispatch();
+        assert_eq!(response.status(), Status::Unauthorized);
+    }
+}
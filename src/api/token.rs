@@ -0,0 +1,20 @@
+use rocket::Request;
+use rocket::request::{FromRequest, Outcome};
+
+/// The bearer token a request was made with, if any. Authentication itself
+/// is left to `Authorized`, this only identifies the caller.
+pub(crate) struct BearerToken(pub Option<String>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for BearerToken {
+    type Error = std::convert::Infallible;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let token = request
+            .headers()
+            .get_one("Authorization")
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .map(|token| token.trim().to_string());
+        Outcome::Success(BearerToken(token))
+    }
+}
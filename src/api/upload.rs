@@ -1,44 +1,626 @@
+use rocket::data::{ByteUnit, Data, Limits};
 use rocket::fs::NamedFile;
+use rocket::futures::stream::{self, BoxStream, StreamExt};
+use rocket::http::ContentType;
+use rocket::request::{FromRequest, Outcome};
+use rocket::response::stream::ByteStream;
+use rocket::response::{self, Responder};
+use rocket::{Either, Request};
+use std::io::{BufWriter, Read, Write};
 
-use rocket::tokio::fs;
+use rocket::tokio::io::AsyncWriteExt;
 use rocket::{State, tokio};
 
-use rocket::{form::Form, fs::TempFile};
 use rocket_apitoken::Authorized;
 
-use crate::audio::wav_decode;
+use crate::api::multipart::{MultipartUpload, WavParts};
+use crate::audio::{
+    EncodeOptions, MixSpec, ProcessOptions, ProcessReport, Progress, SilenceSpec, SplitSpec,
+    TrimSpec, check_crossfade, check_normalize_lufs, check_sample_rate, wav_file_decode,
+    wav_file_encode, wav_file_normalize, wav_file_split, wav_files_concat, wav_read_encode,
+    wav_reader_decode,
+};
+use crate::bridge::{ChannelReader, Chunk, Pipeline, read_chunks};
 use crate::config::Config;
 use crate::error::WaveemapiError;
 use crate::helpers::{check_data_path, wav_path};
+use crate::id3::{Cover, TrackTags};
+
+/// Encoded bytes are sent on to the response in chunks of this size.
+const STREAM_CHUNK_BYTES: usize = 16 * 1024;
+/// Chunks buffered before the encoder waits for a slow client, and before
+/// the upload waits for a slow encoder.
+const STREAM_CHUNKS_BUFFERED: usize = 16;
+/// `audio/` media types accepted as a raw request body.
+const RAW_SUBTYPES: [&str; 6] = ["wav", "x-wav", "aiff", "x-aiff", "flac", "x-flac"];
+/// Raw request bodies are read in chunks of this size.
+const BODY_CHUNK_BYTES: usize = 64 * 1024;
+/// Response header with the gain applied by `normalize_lufs`, in dB.
+const GAIN_HEADER: &str = "X-Waveemapi-Gain";
+/// Response headers with the seconds removed by `trim_silence`.
+const SILENCE_START_HEADER: &str = "X-Waveemapi-Silence-Trimmed-Start";
+const SILENCE_END_HEADER: &str = "X-Waveemapi-Silence-Trimmed-End";
 
 pub fn routes() -> Vec<rocket::Route> {
-    routes![upload]
+    routes![upload_raw, upload]
+}
+
+/// Matches bodies sent with one of `RAW_SUBTYPES` under `audio/`, forwarding
+/// anything else to the multipart route. The actual format is still detected
+/// from the data.
+pub(crate) struct AudioBody;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for AudioBody {
+    type Error = std::convert::Infallible;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        match request.content_type() {
+            Some(ct) if ct.top() == "audio" && RAW_SUBTYPES.iter().any(|sub| ct.sub() == *sub) => {
+                Outcome::Success(AudioBody)
+            }
+            _ => Outcome::Forward(rocket::http::Status::UnsupportedMediaType),
+        }
+    }
 }
 
+/// A response with what processing did to the audio in its headers.
+pub(crate) struct WithReport<R>(R, ProcessReport);
+
+impl<'r, 'o: 'r, R: Responder<'r, 'o>> Responder<'r, 'o> for WithReport<R> {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'o> {
+        let mut response = self.0.respond_to(request)?;
+        if let Some(gain) = self.1.gain_db {
+            response.set_raw_header(GAIN_HEADER, format!("{:.2}", gain));
+        }
+        if let Some((start, end)) = self.1.silence_trimmed {
+            response.set_raw_header(SILENCE_START_HEADER, format!("{:.3}", start));
+            response.set_raw_header(SILENCE_END_HEADER, format!("{:.3}", end));
+        }
+        Ok(response)
+    }
+}
+
+/// The text fields of an upload, everything but `wav` and `cover`.
 #[derive(FromForm)]
-struct Upload<'r> {
-    wav: TempFile<'r>,
+pub(crate) struct UploadOptions {
+    bitrate: Option<u16>,
+    mode: Option<String>,
+    vbr_quality: Option<u8>,
+    quality: Option<u8>,
+    downmix: Option<String>,
+    channels: Option<String>,
+    matrix: Option<String>,
+    sample_rate: Option<u32>,
+    start: Option<String>,
+    end: Option<String>,
+    fade_in: Option<String>,
+    fade_out: Option<String>,
+    trim_silence: Option<bool>,
+    silence_threshold: Option<f64>,
+    silence_min_duration: Option<f64>,
+    normalize_lufs: Option<f64>,
+    split: Option<String>,
+    split_threshold: Option<f64>,
+    split_min_gap: Option<f64>,
+    pub(crate) concat: Option<bool>,
+    crossfade: Option<f64>,
+    intro: Option<String>,
+    outro: Option<String>,
+    title: Option<String>,
+    artist: Option<String>,
+    album: Option<String>,
+    year: Option<String>,
+    comment: Option<String>,
+    track: Option<String>,
+    genre: Option<String>,
+    pub(crate) callback_url: Option<String>,
+    pub(crate) stream: Option<bool>,
+}
+
+#[post("/?<options..>", data = "<data>", rank = 1)]
+async fn upload_raw<'r>(
+    _wav: AudioBody,
+    _auth: Authorized,
+    options: UploadOptions,
+    data: Data<'r>,
+    limits: &Limits,
+    config: &State<Config>,
+) -> Result<WithReport<Either<NamedFile, (ContentType, ByteStream![Vec<u8> + 'r])>>, WaveemapiError>
+{
+    let wav = raw_body(data, limits);
+    encode_upload(wav, None, options, None, config).await
+}
+
+/// A raw request body, limited by the `file` limit.
+pub(crate) fn raw_body<'r>(data: Data<'r>, limits: &Limits) -> BoxStream<'r, Chunk> {
+    let limit = limits.get("file").unwrap_or(Limits::FILE).as_u64();
+    // One byte over the limit is read to tell a full body from a cut off one.
+    let body = read_chunks(
+        Box::pin(data.open(ByteUnit::from(limit + 1))),
+        BODY_CHUNK_BYTES,
+    );
+    let mut received = 0u64;
+    body.map(move |chunk| {
+        let chunk = chunk?;
+        received += chunk.len() as u64;
+        if received > limit {
+            return Err(WaveemapiError::TooLarge(
+                "wav exceeds the upload limit".to_string(),
+            ));
+        }
+        Ok(chunk)
+    })
+    .boxed()
 }
 
-#[post("/", data = "<upload>")]
-async fn upload(
+#[post("/", data = "<data>", rank = 2)]
+async fn upload<'r>(
     _auth: Authorized,
-    mut upload: Form<Upload<'_>>,
+    content_type: Option<&ContentType>,
+    data: Data<'r>,
+    limits: &Limits,
     config: &State<Config>,
-) -> Result<NamedFile, WaveemapiError> {
+) -> Result<WithReport<Either<NamedFile, (ContentType, ByteStream![Vec<u8> + 'r])>>, WaveemapiError>
+{
+    let upload =
+        MultipartUpload::read(content_type, data, limits, config.cover_max_bytes()).await?;
+    encode_upload(
+        upload.wav,
+        Some(upload.more),
+        upload.options,
+        upload.cover,
+        config,
+    )
+    .await
+}
+
+/// Decodes `wav` as it arrives, answering with the MP3 file or a stream, or
+/// with a ZIP of MP3s when splitting. With `concat`, the parts in `more` are
+/// joined to it first.
+async fn encode_upload<'r>(
+    wav: BoxStream<'r, Chunk>,
+    more: Option<WavParts<'r>>,
+    options: UploadOptions,
+    cover: Option<Cover>,
+    config: &Config,
+) -> Result<WithReport<Either<NamedFile, (ContentType, ByteStream![Vec<u8> + 'r])>>, WaveemapiError>
+{
+    if options
+        .callback_url
+        .as_deref()
+        .is_some_and(|url| !url.trim().is_empty())
+    {
+        return Err(WaveemapiError::InvalidOptions(
+            "callback_url is only supported by /api/jobs".to_string(),
+        ));
+    }
+    let request = EncodeRequest::new(&options, cover, config)?;
     let data_path = config.data_path.clone();
+    let stream = options.stream.unwrap_or(false);
+    if stream && request.process.split.is_some() {
+        return Err(WaveemapiError::InvalidOptions(
+            "split cannot be streamed".to_string(),
+        ));
+    }
+    let concat = options.concat.unwrap_or(false);
+    if request.process.normalize_lufs.is_some()
+        || request.process.split.is_some()
+        || concat
+        || request.has_assets()
+        || (stream && more.is_some())
+    {
+        // The whole upload is measured before the gain can be applied, cue
+        // points may follow the samples, and parts and assets are joined
+        // before encoding. A form is read to the end before a stream starts,
+        // so fields sent after the wav are still answered with an error.
+        let mut prepared = PreparedUpload::new(wav, request, data_path).await?;
+        if let Some(more) = more.filter(|_| concat) {
+            prepared = prepared.with_parts(more).await?;
+        }
+        let (prepared, gain) =
+            tokio::task::spawn_blocking(move || prepared.join()?.normalize()).await??;
+        if stream {
+            let report = ProcessReport {
+                gain_db: gain,
+                ..prepared.report
+            };
+            let mp3 = stream_mp3(
+                stream::empty().boxed(),
+                Box::new(move |_, out| prepared.encode_to(out, &Progress::default())),
+            )
+            .await?;
+            return Ok(WithReport(Either::Right(mp3), report));
+        }
+        let (path, report) =
+            tokio::task::spawn_blocking(move || prepared.encode(&Progress::default())).await??;
+        return open_mp3(&path).await.map(|mp3| WithReport(mp3, report));
+    }
+    if stream {
+        let mp3 = stream_mp3(
+            wav,
+            Box::new(move |reader, out| request.encode_to(reader, out, &Progress::default())),
+        )
+        .await?;
+        return Ok(WithReport(Either::Right(mp3), ProcessReport::default()));
+    }
     check_data_path(&data_path)?;
-    let uploadp = wav_path(&data_path);
-    upload.wav.persist_to(&uploadp).await?;
-    let uploadpc = uploadp.clone();
-    let resultp = tokio::task::spawn_blocking(move || {
-        let reader = hound::WavReader::open(&uploadp).map_err(WaveemapiError::Hound)?;
-        wav_decode(reader, &data_path)
-    })
-    .await?;
-    fs::remove_file(&uploadpc).await?; // remove wav after mp3 encode
-    let val = resultp?;
-    NamedFile::open(&val).await.map_err(WaveemapiError::Io)
+    let (mut pipeline, reader, writer) = Pipeline::new(wav, STREAM_CHUNKS_BUFFERED);
+    let task = tokio::task::spawn_blocking(move || {
+        // Held until the encode is done, which ends the pipeline.
+        let _writer = writer;
+        request.decode(reader, &data_path, &Progress::default())
+    });
+    while let Some(chunk) = pipeline.next().await {
+        chunk?;
+    }
+    let (path, report) = task.await??;
+    open_mp3(&path).await.map(|mp3| WithReport(mp3, report))
+}
+
+async fn open_mp3<S>(path: &str) -> Result<Either<NamedFile, S>, WaveemapiError> {
+    NamedFile::open(path)
+        .await
+        .map(Either::Left)
+        .map_err(WaveemapiError::Io)
+}
+
+/// Encodes into the writer it is given, reading the upload if it needs it.
+type StreamEncode =
+    Box<dyn FnOnce(ChannelReader, &mut dyn Write) -> Result<ProcessReport, WaveemapiError> + Send>;
+
+/// Streams the MP3 as it is encoded. Errors before the first chunk become a
+/// regular error response, later ones can only cut the stream short.
+async fn stream_mp3<'r>(
+    wav: BoxStream<'r, Chunk>,
+    encode: StreamEncode,
+) -> Result<(ContentType, ByteStream![Vec<u8> + 'r]), WaveemapiError> {
+    let (mut pipeline, reader, writer) = Pipeline::new(wav, STREAM_CHUNKS_BUFFERED);
+    tokio::task::spawn_blocking(move || {
+        let mut out = BufWriter::with_capacity(STREAM_CHUNK_BYTES, writer);
+        if let Err(e) = encode(reader, &mut out) {
+            out.get_ref().fail(e);
+        }
+    });
+    let first = match pipeline.next().await {
+        Some(Err(e)) => return Err(e),
+        Some(Ok(chunk)) => Some(chunk),
+        None => None,
+    };
+    let stream = ByteStream! {
+        if let Some(chunk) = first {
+            yield chunk;
+        }
+        while let Some(chunk) = pipeline.next().await {
+            match chunk {
+                Ok(chunk) => yield chunk,
+                Err(e) => {
+                    eprintln!("Streaming encode failed: {}", e);
+                    break;
+                }
+            }
+        }
+    };
+    Ok((ContentType::new("audio", "mpeg"), stream))
+}
+
+/// Validated encode settings for one upload.
+pub(crate) struct EncodeRequest {
+    options: EncodeOptions,
+    process: ProcessOptions,
+    tags: TrackTags,
+    /// Paths of the assets played before and after the upload.
+    intro: Option<String>,
+    outro: Option<String>,
+}
+
+impl EncodeRequest {
+    pub(crate) fn new(
+        upload: &UploadOptions,
+        cover: Option<Cover>,
+        config: &Config,
+    ) -> Result<Self, WaveemapiError> {
+        let options = EncodeOptions::new(
+            upload.mode.as_deref(),
+            upload.bitrate,
+            upload.quality,
+            upload.vbr_quality,
+        )?;
+        let process = ProcessOptions {
+            mix: MixSpec::new(
+                upload.downmix.as_deref(),
+                upload.channels.as_deref(),
+                upload.matrix.as_deref(),
+            )?,
+            sample_rate: check_sample_rate(upload.sample_rate)?,
+            max_duration: config.max_duration(),
+            trim: TrimSpec::new(
+                upload.start.as_deref(),
+                upload.end.as_deref(),
+                upload.fade_in.as_deref(),
+                upload.fade_out.as_deref(),
+            )?,
+            trim_silence: SilenceSpec::new(
+                upload.trim_silence,
+                upload.silence_threshold,
+                upload.silence_min_duration,
+            )?,
+            normalize_lufs: check_normalize_lufs(upload.normalize_lufs)?,
+            gain_db: None,
+            crossfade: check_crossfade(upload.crossfade)?,
+            split: SplitSpec::new(
+                upload.split.as_deref(),
+                upload.split_threshold,
+                upload.split_min_gap,
+            )?,
+        };
+        if process.crossfade.is_some() && !upload.concat.unwrap_or(false) {
+            return Err(WaveemapiError::InvalidOptions(
+                "crossfade is only valid with concat".to_string(),
+            ));
+        }
+        if process.split.is_some() && (process.trim.start.is_some() || process.trim.end.is_some()) {
+            return Err(WaveemapiError::InvalidOptions(
+                "start and end cannot be combined with split".to_string(),
+            ));
+        }
+        let mut tags = TrackTags::new(
+            upload.title.as_deref(),
+            upload.artist.as_deref(),
+            upload.album.as_deref(),
+            upload.year.as_deref(),
+            upload.comment.as_deref(),
+            upload.track.as_deref(),
+            upload.genre.as_deref(),
+        )?;
+        tags.cover = cover;
+        let asset = |name: &Option<String>| {
+            name.as_deref()
+                .map(str::trim)
+                .filter(|name| !name.is_empty())
+                .map(|name| config.asset(name))
+                .transpose()
+        };
+        let (intro, outro) = (asset(&upload.intro)?, asset(&upload.outro)?);
+        if process.split.is_some() && (intro.is_some() || outro.is_some()) {
+            return Err(WaveemapiError::InvalidOptions(
+                "intro and outro cannot be combined with split".to_string(),
+            ));
+        }
+        Ok(EncodeRequest {
+            options,
+            process,
+            tags,
+            intro,
+            outro,
+        })
+    }
+
+    fn has_assets(&self) -> bool {
+        self.intro.is_some() || self.outro.is_some()
+    }
+
+    /// Encodes the WAV read from `reader` to a new MP3 in `data_path`,
+    /// returning its path. Blocks.
+    fn decode<R: Read>(
+        &self,
+        reader: R,
+        data_path: &str,
+        progress: &Progress,
+    ) -> Result<(String, ProcessReport), WaveemapiError> {
+        wav_reader_decode(
+            reader,
+            data_path,
+            &self.options,
+            &self.process,
+            &self.tags,
+            progress,
+        )
+    }
+
+    /// Encodes the WAV read from `reader` into `out`. Blocks.
+    fn encode_to<R: Read>(
+        &self,
+        reader: R,
+        out: &mut dyn Write,
+        progress: &Progress,
+    ) -> Result<ProcessReport, WaveemapiError> {
+        wav_read_encode(
+            reader,
+            out,
+            &self.options,
+            &self.process,
+            &self.tags,
+            progress,
+        )
+    }
+}
+
+/// An upload with its WAV persisted to `data_path`, for encoding later.
+pub(crate) struct PreparedUpload {
+    wav: String,
+    /// Further parts, joined to `wav` by `join`.
+    parts: Vec<String>,
+    /// Where `join` writes, picked up front so it is among `files`.
+    joined: String,
+    /// What `join` did when it processed the upload ahead of the assets.
+    report: ProcessReport,
+    data_path: String,
+    request: EncodeRequest,
+}
+
+impl PreparedUpload {
+    pub(crate) async fn new(
+        wav: BoxStream<'_, Chunk>,
+        request: EncodeRequest,
+        data_path: String,
+    ) -> Result<Self, WaveemapiError> {
+        check_data_path(&data_path)?;
+        Ok(PreparedUpload {
+            wav: store(wav, &data_path).await?,
+            parts: Vec::new(),
+            joined: wav_path(&data_path),
+            report: ProcessReport::default(),
+            data_path,
+            request,
+        })
+    }
+
+    /// Also stores the `wav` parts in `more`, removing everything stored on error.
+    pub(crate) async fn with_parts(
+        mut self,
+        mut more: WavParts<'_>,
+    ) -> Result<Self, WaveemapiError> {
+        loop {
+            let stored = match more.next().await {
+                Ok(Some(wav)) => store(wav, &self.data_path).await,
+                Ok(None) => return Ok(self),
+                Err(e) => Err(e),
+            };
+            match stored {
+                Ok(part) => self.parts.push(part),
+                Err(e) => {
+                    for path in std::iter::once(&self.wav).chain(&self.parts) {
+                        tokio::fs::remove_file(path).await.ok();
+                    }
+                    return Err(e);
+                }
+            }
+        }
+    }
+
+    /// The stored upload, its parts and the WAV `join` will write.
+    pub(crate) fn files(&self) -> Vec<String> {
+        std::iter::once(&self.wav)
+            .chain(&self.parts)
+            .chain(std::iter::once(&self.joined).filter(|_| self.needs_join()))
+            .cloned()
+            .collect()
+    }
+
+    fn needs_join(&self) -> bool {
+        !self.parts.is_empty() || self.request.has_assets()
+    }
+
+    /// Joins the parts into one WAV between the intro and outro, keeping the
+    /// format and metadata of the first part. With assets the upload is
+    /// processed first, leaving only resampling to the encode. Removes the
+    /// parts, and the WAV on error. Blocks.
+    pub(crate) fn join(mut self) -> Result<Self, WaveemapiError> {
+        if !self.needs_join() {
+            return Ok(self);
+        }
+        let mut parts = vec![std::mem::take(&mut self.wav)];
+        parts.append(&mut self.parts);
+        let joined = wav_files_concat(
+            &parts,
+            self.request.intro.as_deref(),
+            self.request.outro.as_deref(),
+            &self.joined,
+            &self.request.process,
+        );
+        for part in &parts {
+            std::fs::remove_file(part).ok();
+        }
+        let (embedded, report) = joined?;
+        self.wav = self.joined.clone();
+        if self.request.has_assets() {
+            self.report = report;
+            self.request.process = ProcessOptions {
+                sample_rate: self.request.process.sample_rate,
+                max_duration: self.request.process.max_duration,
+                ..ProcessOptions::default()
+            };
+        }
+        self.request.intro = None;
+        self.request.outro = None;
+        self.request.tags = std::mem::take(&mut self.request.tags).or_embedded(embedded);
+        Ok(self)
+    }
+
+    /// Measures the WAV when `normalize_lufs` was requested and sets the gain
+    /// reaching it, which is also returned, or the one `join` applied. Removes
+    /// the WAV on error. Blocks.
+    pub(crate) fn normalize(mut self) -> Result<(Self, Option<f64>), WaveemapiError> {
+        match wav_file_normalize(&self.wav, &self.request.process) {
+            Ok(gain) => {
+                self.request.process.gain_db = gain;
+                let gain = gain.or(self.report.gain_db);
+                Ok((self, gain))
+            }
+            Err(e) => {
+                std::fs::remove_file(&self.wav).ok();
+                Err(e)
+            }
+        }
+    }
+
+    /// Encodes the WAV into `out` and removes it. Blocks.
+    fn encode_to(
+        self,
+        out: &mut dyn Write,
+        progress: &Progress,
+    ) -> Result<ProcessReport, WaveemapiError> {
+        let result = wav_file_encode(
+            &self.wav,
+            out,
+            &self.request.options,
+            &self.request.process,
+            &self.request.tags,
+            progress,
+        );
+        std::fs::remove_file(&self.wav)?;
+        result.map(|report| report.or(self.report))
+    }
+
+    /// Encodes the WAV and removes it, returning the path of the MP3, or of
+    /// the ZIP of tracks when splitting. Blocks.
+    pub(crate) fn encode(
+        self,
+        progress: &Progress,
+    ) -> Result<(String, ProcessReport), WaveemapiError> {
+        let encode = if self.request.process.split.is_some() {
+            wav_file_split
+        } else {
+            wav_file_decode
+        };
+        let result = encode(
+            &self.wav,
+            &self.data_path,
+            &self.request.options,
+            &self.request.process,
+            &self.request.tags,
+            progress,
+        );
+        std::fs::remove_file(&self.wav)?; // remove wav after mp3 encode
+        result.map(|(path, report)| (path, report.or(self.report)))
+    }
+}
+
+/// Writes `wav` to a new file in `data_path`, returning its path. Nothing is
+/// left behind on error.
+async fn store(mut wav: BoxStream<'_, Chunk>, data_path: &str) -> Result<String, WaveemapiError> {
+    let wav_file = wav_path(data_path);
+    let mut file = tokio::fs::File::create(&wav_file).await?;
+    let mut written = Ok(());
+    while let Some(chunk) = wav.next().await {
+        written = match chunk {
+            Ok(chunk) => file.write_all(&chunk).await.map_err(WaveemapiError::from),
+            Err(e) => Err(e),
+        };
+        if written.is_err() {
+            break;
+        }
+    }
+    if written.is_ok() {
+        written = file.flush().await.map_err(WaveemapiError::from);
+    }
+    if let Err(e) = written {
+        drop(file);
+        tokio::fs::remove_file(&wav_file).await.ok();
+        return Err(e);
+    }
+    Ok(wav_file)
 }
 
 #[cfg(test)]
@@ -71,4 +653,119 @@ mod tests {
             .dispatch();
         assert_eq!(response.status(), rocket::http::Status::Unauthorized);
     }
+
+    fn upload_form(
+        data_path: &std::path::Path,
+        fields: &[(&str, &[u8])],
+    ) -> (rocket::http::Status, Vec<u8>) {
+        use rocket::local::blocking::Client;
+
+        let client = Client::tracked(crate::authorized_rocket(data_path)).expect("valid `Rocket`");
+        let (content_type, body) = crate::api::multipart::form_data(fields);
+        let response = client
+            .post("/api/upload")
+            .header(content_type)
+            .header(rocket::http::Header::new(
+                "Authorization",
+                format!("Bearer {}", crate::TEST_TOKEN),
+            ))
+            .body(body)
+            .dispatch();
+        (response.status(), response.into_bytes().unwrap_or_default())
+    }
+
+    #[test]
+    fn test_upload_late_field() {
+        use rocket::http::Status;
+
+        let tmpdir = tempfile::tempdir().unwrap();
+        let wav = std::fs::read(format!("{}sine51i16.wav", crate::audio::SAMPLE_PATH)).unwrap();
+        let (status, _) = upload_form(tmpdir.path(), &[("bitrate", b"64"), ("wav", &wav)]);
+        assert_eq!(status, Status::Ok);
+        let (status, body) = upload_form(tmpdir.path(), &[("wav", &wav), ("bitrate", b"64")]);
+        assert_eq!(status, Status::BadRequest);
+        assert!(String::from_utf8_lossy(&body).contains("bitrate was sent after wav"));
+        let (status, _) = upload_form(
+            tmpdir.path(),
+            &[
+                ("normalize_lufs", b"-16"),
+                ("wav", &wav),
+                ("title", b"Late"),
+            ],
+        );
+        assert_eq!(status, Status::BadRequest);
+        // A stream only starts once the whole form has been read.
+        let (status, mp3) = upload_form(tmpdir.path(), &[("stream", b"true"), ("wav", &wav)]);
+        assert_eq!(status, Status::Ok);
+        assert!(mp3.len() > 1000);
+        let (status, body) = upload_form(
+            tmpdir.path(),
+            &[("stream", b"true"), ("wav", &wav), ("title", b"Late")],
+        );
+        assert_eq!(status, Status::BadRequest);
+        assert!(String::from_utf8_lossy(&body).contains("title was sent after wav"));
+    }
+
+    #[test]
+    fn test_upload_trailing_info() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let wav = std::fs::read(format!("{}infobexti16.wav", crate::audio::SAMPLE_PATH)).unwrap();
+        // LIST/INFO follows the samples, bext precedes them.
+        let (status, mp3) = upload_form(tmpdir.path(), &[("wav", &wav)]);
+        assert_eq!(status, rocket::http::Status::Ok);
+        assert!(mp3.starts_with(b"ID3"));
+        let has = |text: &[u8]| mp3.windows(text.len()).any(|w| w == text);
+        assert!(has(b"Field Take 3"));
+        assert!(has(b"Sound Recordist"));
+        // Client supplied tags still win.
+        let (_, mp3) = upload_form(tmpdir.path(), &[("title", b"Mine"), ("wav", &wav)]);
+        assert!(mp3.windows(4).any(|w| w == b"Mine"));
+        assert!(!mp3.windows(12).any(|w| w == b"Field Take 3"));
+    }
+
+    #[rocket::async_test]
+    async fn test_prepared_join_files() {
+        use super::{EncodeRequest, PreparedUpload, UploadOptions};
+        use rocket::form::Form;
+        use rocket::futures::stream::{self, StreamExt};
+
+        let tmpdir = tempfile::tempdir().unwrap();
+        let data_path = tmpdir.path().to_string_lossy().to_string();
+        let sample = format!("{}sineu8.wav", crate::audio::SAMPLE_PATH);
+        let config = crate::config::Config {
+            data_path: data_path.clone(),
+            assets: [("bell".to_string(), sample.clone())].into(),
+            ..crate::config::Config::default()
+        };
+        let wav = std::fs::read(&sample).unwrap();
+        let options = Form::<UploadOptions>::parse("intro=bell&split=cue").unwrap();
+        assert!(EncodeRequest::new(&options, None, &config).is_err());
+        let options = Form::<UploadOptions>::parse("intro=bell&fade_in=0.1").unwrap();
+        let request = EncodeRequest::new(&options, None, &config).unwrap();
+        let body = stream::once(async move { Ok(wav) }).boxed();
+        let prepared = PreparedUpload::new(body, request, data_path).await.unwrap();
+        // The joined WAV is known before the job runs, so cleanup keeps it.
+        let files = prepared.files();
+        assert_eq!(files.len(), 2);
+        let prepared = prepared.join().unwrap();
+        assert_eq!(prepared.files(), [files[1].clone()]);
+        // The fade was applied to the upload before the intro was added.
+        assert!(prepared.request.process.trim.is_empty());
+        std::fs::remove_file(&files[1]).unwrap();
+    }
+
+    #[test]
+    fn test_upload_raw_auth_no_head() {
+        use rocket::local::blocking::Client;
+
+        let client = Client::tracked(rocket()).expect("valid `Rocket`");
+        for sub in ["wav", "x-wav", "aiff", "flac"] {
+            let response = client
+                .post("/api/upload?mode=vbr")
+                .header(rocket::http::ContentType::new("audio", sub))
+                .body("RIFF")
+                .dispatch();
+            assert_eq!(response.status(), rocket::http::Status::Unauthorized);
+        }
+    }
 }
@@ -0,0 +1,125 @@
+use rocket::data::{Data, Limits};
+use rocket::futures::stream::BoxStream;
+use rocket::http::ContentType;
+use rocket::serde::json::Json;
+use rocket::{Either, State};
+use rocket_apitoken::Authorized;
+
+use crate::api::multipart::MultipartUpload;
+use crate::api::upload::{AudioBody, raw_body};
+use crate::audio::{
+    MixSpec, ProcessOptions, Waveform, WaveformOptions, check_sample_rate, wav_read_waveform,
+};
+use crate::bridge::{Chunk, read_blocking};
+use crate::config::Config;
+use crate::error::WaveemapiError;
+
+/// Upload chunks buffered before the upload waits for the peaks.
+const CHUNKS_BUFFERED: usize = 16;
+
+type WaveformResp = Either<Json<Waveform>, (ContentType, Vec<u8>)>;
+
+pub fn routes() -> Vec<rocket::Route> {
+    routes![waveform_raw, waveform_upload]
+}
+
+/// The fields of a waveform request. Encoder options are ignored, so the same
+/// fields as for `/api/upload` can be sent.
+#[derive(FromForm)]
+pub(crate) struct WaveformQuery {
+    downmix: Option<String>,
+    channels: Option<String>,
+    matrix: Option<String>,
+    sample_rate: Option<u32>,
+    samples_per_pixel: Option<u32>,
+    bits: Option<u8>,
+    split_channels: Option<bool>,
+    format: Option<String>,
+}
+
+#[post("/?<query..>", data = "<data>", rank = 1)]
+async fn waveform_raw(
+    _wav: AudioBody,
+    _auth: Authorized,
+    query: WaveformQuery,
+    data: Data<'_>,
+    limits: &Limits,
+    config: &State<Config>,
+) -> Result<WaveformResp, WaveemapiError> {
+    waveform(raw_body(data, limits), query, config.max_duration()).await
+}
+
+#[post("/", data = "<data>", rank = 2)]
+async fn waveform_upload(
+    _auth: Authorized,
+    content_type: Option<&ContentType>,
+    data: Data<'_>,
+    limits: &Limits,
+    config: &State<Config>,
+) -> Result<WaveformResp, WaveemapiError> {
+    let upload =
+        MultipartUpload::read(content_type, data, limits, config.cover_max_bytes()).await?;
+    waveform(upload.wav, upload.options, config.max_duration()).await
+}
+
+/// Collects the peaks of `wav` as it arrives, as audiowaveform JSON or `.dat`.
+async fn waveform(
+    wav: BoxStream<'_, Chunk>,
+    query: WaveformQuery,
+    max_duration: Option<u64>,
+) -> Result<WaveformResp, WaveemapiError> {
+    let dat = match query
+        .format
+        .as_deref()
+        .map(str::to_ascii_lowercase)
+        .as_deref()
+    {
+        None | Some("json") => false,
+        Some("dat") => true,
+        Some(other) => {
+            return Err(WaveemapiError::InvalidOptions(format!(
+                "Unknown format '{}', expected json or dat",
+                other
+            )));
+        }
+    };
+    let process = ProcessOptions {
+        mix: MixSpec::new(
+            query.downmix.as_deref(),
+            query.channels.as_deref(),
+            query.matrix.as_deref(),
+        )?,
+        sample_rate: check_sample_rate(query.sample_rate)?,
+        max_duration,
+        ..ProcessOptions::default()
+    };
+    let options = WaveformOptions::new(query.samples_per_pixel, query.bits, query.split_channels)?;
+    let waveform = read_blocking(wav, CHUNKS_BUFFERED, move |reader| {
+        wav_read_waveform(reader, &process, &options)
+    })
+    .await?;
+    if dat {
+        return Ok(Either::Right((ContentType::Binary, waveform.to_dat())));
+    }
+    Ok(Either::Left(Json(waveform)))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::rocket;
+    use rocket::http::{ContentType, Status};
+    use rocket::local::blocking::Client;
+
+    #[test]
+    fn test_waveform_auth_no_head() {
+        let client = Client::tracked(rocket()).expect("valid `Rocket`");
+        let response = client.post("/api/waveform").dispatch();
+        assert_eq!(response.status(), Status::Unauthorized);
+        let response = client
+            .post("/api/waveform?format=dat")
+            .header(ContentType::new("audio", "wav"))
+            .body("RIFF")
+            .dispatch();
+        assert_eq!(response.status(), Status::Unauthorized);
+    }
+}
@@ -1,113 +1,1199 @@
 use crate::error::WaveemapiError;
-use crate::helpers::mp3_path;
+use crate::helpers::{mp3_path, zip_path};
+use crate::id3::TrackTags;
 use hound::WavReader;
-use mp3lame_encoder::{BuildError, Builder, DualPcm, Encoder, FlushNoGap, Id3Tag, MonoPcm};
+use mp3lame_encoder::{
+    Bitrate, BuildError, Builder, DualPcm, Encoder, FlushNoGap, MonoPcm, Quality, VbrMode,
+};
 
 use std::fs::File;
-use std::io::{BufWriter, Write};
+use std::io::{self, BufReader, BufWriter, Cursor, Seek, SeekFrom, Write};
 
 use std::cmp;
 use std::io::Read;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+mod adpcm;
+mod aiff;
+mod analyze;
+mod concat;
+mod flac;
+mod g711;
+mod limiter;
+mod mix;
+mod pcm;
+mod probe;
+mod resample;
+mod riff;
+mod split;
+mod trim;
+mod waveform;
+
+pub use analyze::Analysis;
+pub use concat::check_crossfade;
+pub use limiter::check_normalize_lufs;
+pub use mix::MixSpec;
+pub use probe::{Probe, probe};
+pub use resample::check_sample_rate;
+pub use split::SplitSpec;
+pub use trim::{SilenceSpec, TrimSpec};
+pub use waveform::{Waveform, WaveformOptions};
 
 const CHUNK_SIZE: usize = 1152; // https://stackoverflow.com/questions/72416908/mp3-exact-frame-size-calculation
 const I32_MAXPONE: f32 = 2147483648.0_f32; // 2^31
 const I24_MAXPONE: f32 = 8388608.0_f32; // 2^23
 const I16_MAXPONE: f32 = 32768.0_f32; // 2^15
+const I8_MAXPONE: f32 = 128.0_f32; // 2^7
 
-pub fn wav_decode<R: Read>(
-    mut reader: WavReader<R>,
+const BITRATES: [u16; 16] = [
+    8, 16, 24, 32, 40, 48, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320,
+];
+
+/// How LAME spends bits across the file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RateMode {
+    Cbr,
+    Vbr,
+    Abr,
+}
+
+/// Per-request encoder settings. Build with [`EncodeOptions::new`] so that
+/// everything handed to LAME has already been validated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EncodeOptions {
+    pub mode: RateMode,
+    /// Constant bitrate for CBR, target bitrate for ABR. Unused for VBR.
+    pub bitrate: u16,
+    /// LAME algorithm quality, 0 (best) to 9 (worst).
+    pub quality: u8,
+    /// VBR quality, 0 (best) to 9 (worst). Only used for VBR.
+    pub vbr_quality: u8,
+}
+
+impl Default for EncodeOptions {
+    fn default() -> Self {
+        EncodeOptions {
+            mode: RateMode::Cbr,
+            bitrate: 128,
+            quality: 6,
+            vbr_quality: 4,
+        }
+    }
+}
+
+impl EncodeOptions {
+    pub fn new(
+        mode: Option<&str>,
+        bitrate: Option<u16>,
+        quality: Option<u8>,
+        vbr_quality: Option<u8>,
+    ) -> Result<Self, WaveemapiError> {
+        let mode = match mode.map(str::to_ascii_lowercase).as_deref() {
+            None | Some("cbr") => RateMode::Cbr,
+            Some("vbr") => RateMode::Vbr,
+            Some("abr") => RateMode::Abr,
+            Some(other) => {
+                return Err(WaveemapiError::InvalidOptions(format!(
+                    "Unknown mode '{}', expected cbr, vbr or abr",
+                    other
+                )));
+            }
+        };
+        let mut options = EncodeOptions {
+            mode,
+            ..EncodeOptions::default()
+        };
+        if let Some(bitrate) = bitrate {
+            if options.mode == RateMode::Vbr {
+                return Err(WaveemapiError::InvalidOptions(
+                    "bitrate cannot be combined with vbr mode, use vbr_quality instead".to_string(),
+                ));
+            }
+            if !BITRATES.contains(&bitrate) {
+                return Err(WaveemapiError::InvalidOptions(format!(
+                    "Unsupported bitrate {}, expected one of {:?}",
+                    bitrate, BITRATES
+                )));
+            }
+            options.bitrate = bitrate;
+        }
+        if let Some(vbr_quality) = vbr_quality {
+            if options.mode != RateMode::Vbr {
+                return Err(WaveemapiError::InvalidOptions(
+                    "vbr_quality is only valid with vbr mode".to_string(),
+                ));
+            }
+            if lame_quality(vbr_quality).is_none() {
+                return Err(WaveemapiError::InvalidOptions(
+                    "vbr_quality must be between 0 and 9".to_string(),
+                ));
+            }
+            options.vbr_quality = vbr_quality;
+        }
+        if let Some(quality) = quality {
+            if lame_quality(quality).is_none() {
+                return Err(WaveemapiError::InvalidOptions(
+                    "quality must be between 0 and 9".to_string(),
+                ));
+            }
+            options.quality = quality;
+        }
+        Ok(options)
+    }
+
+    fn apply(&self, builder: &mut Builder) -> Result<(), WaveemapiError> {
+        let quality =
+            lame_quality(self.quality).ok_or(WaveemapiError::Build(BuildError::Generic))?;
+        builder
+            .set_quality(quality)
+            .map_err(WaveemapiError::Build)?;
+        match self.mode {
+            RateMode::Cbr => {
+                builder
+                    .set_vbr_mode(VbrMode::Off)
+                    .map_err(WaveemapiError::Build)?;
+                builder
+                    .set_brate(lame_bitrate(self.bitrate)?)
+                    .map_err(WaveemapiError::Build)?;
+            }
+            RateMode::Abr => {
+                builder
+                    .set_vbr_mode(VbrMode::Abr)
+                    .map_err(WaveemapiError::Build)?;
+                builder
+                    .set_brate(lame_bitrate(self.bitrate)?)
+                    .map_err(WaveemapiError::Build)?;
+            }
+            RateMode::Vbr => {
+                let vbr_quality = lame_quality(self.vbr_quality)
+                    .ok_or(WaveemapiError::Build(BuildError::Generic))?;
+                builder
+                    .set_vbr_mode(VbrMode::Mtrh)
+                    .map_err(WaveemapiError::Build)?;
+                builder
+                    .set_vbr_quality(vbr_quality)
+                    .map_err(WaveemapiError::Build)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn lame_bitrate(kbps: u16) -> Result<Bitrate, WaveemapiError> {
+    let bitrate = match kbps {
+        8 => Bitrate::Kbps8,
+        16 => Bitrate::Kbps16,
+        24 => Bitrate::Kbps24,
+        32 => Bitrate::Kbps32,
+        40 => Bitrate::Kbps40,
+        48 => Bitrate::Kbps48,
+        64 => Bitrate::Kbps64,
+        80 => Bitrate::Kbps80,
+        96 => Bitrate::Kbps96,
+        112 => Bitrate::Kbps112,
+        128 => Bitrate::Kbps128,
+        160 => Bitrate::Kbps160,
+        192 => Bitrate::Kbps192,
+        224 => Bitrate::Kbps224,
+        256 => Bitrate::Kbps256,
+        320 => Bitrate::Kbps320,
+        _ => return Err(WaveemapiError::Build(BuildError::BadBRate)),
+    };
+    Ok(bitrate)
+}
+
+fn lame_quality(quality: u8) -> Option<Quality> {
+    let quality = match quality {
+        0 => Quality::Best,
+        1 => Quality::SecondBest,
+        2 => Quality::NearBest,
+        3 => Quality::VeryNice,
+        4 => Quality::Nice,
+        5 => Quality::Good,
+        6 => Quality::Decent,
+        7 => Quality::Ok,
+        8 => Quality::SecondWorst,
+        9 => Quality::Worst,
+        _ => return None,
+    };
+    Some(quality)
+}
+
+type Samples<'a> = Box<dyn Iterator<Item = Result<f32, WaveemapiError>> + 'a>;
+
+/// Decoded, interleaved samples scaled to the range -1.0..=1.0.
+pub struct AudioStream<'a> {
+    pub samples: Samples<'a>,
+    pub channels: usize,
+    pub sample_rate: u32,
+    /// Speaker layout from `WAVE_FORMAT_EXTENSIBLE`, if the source had one.
+    pub channel_mask: Option<u32>,
+    /// Total number of interleaved samples, when known up front.
+    pub total_samples: Option<u64>,
+}
+
+impl<'a> AudioStream<'a> {
+    fn downmix(self, mix: &MixSpec) -> Result<AudioStream<'a>, WaveemapiError> {
+        let Some(matrix) = mix.matrix(self.channels, self.channel_mask)? else {
+            return Ok(self);
+        };
+        let samples = mix::Downmix::new(self.samples, matrix);
+        Ok(AudioStream {
+            channels: samples.channels(),
+            samples: Box::new(samples),
+            sample_rate: self.sample_rate,
+            channel_mask: None,
+            total_samples: None,
+        })
+    }
+
+    /// Converts to `target`, or to the nearest rate LAME can encode when the
+    /// source rate is not one of them.
+    fn resample(self, target: Option<u32>) -> AudioStream<'a> {
+        let target = target.unwrap_or_else(|| resample::nearest_supported_rate(self.sample_rate));
+        if target == self.sample_rate {
+            return self;
+        }
+        let samples =
+            resample::Resampler::new(self.samples, self.channels, self.sample_rate, target);
+        AudioStream {
+            samples: Box::new(samples),
+            sample_rate: target,
+            total_samples: None,
+            ..self
+        }
+    }
+
+    /// Refuses input longer than `max_seconds`: up front when the length is
+    /// known, otherwise once that many samples have been read.
+    fn limit_duration(self, max_seconds: Option<u64>) -> Result<AudioStream<'a>, WaveemapiError> {
+        let Some(max_seconds) = max_seconds else {
+            return Ok(self);
+        };
+        let max_samples = max_seconds * self.sample_rate as u64 * self.channels as u64;
+        let too_long = move || {
+            WaveemapiError::TooLarge(format!(
+                "audio is longer than the {} second limit",
+                max_seconds
+            ))
+        };
+        if self.total_samples.is_some_and(|total| total > max_samples) {
+            return Err(too_long());
+        }
+        let mut read = 0u64;
+        let samples = self.samples.map(move |sample| {
+            read += 1;
+            if read > max_samples {
+                return Err(too_long());
+            }
+            sample
+        });
+        Ok(AudioStream {
+            samples: Box::new(samples),
+            ..self
+        })
+    }
+
+    /// Applies `gain_db`, limiting the result to the true peak ceiling.
+    fn gain(self, gain_db: Option<f64>) -> AudioStream<'a> {
+        let Some(gain_db) = gain_db else {
+            return self;
+        };
+        let samples = limiter::Limiter::new(self.samples, self.channels, self.sample_rate, gain_db);
+        AudioStream {
+            samples: Box::new(samples),
+            ..self
+        }
+    }
+
+    /// Everything `process` asks for, in the order it is done before encoding,
+    /// along with what silence trimming removes once the samples are read.
+    fn process(
+        self,
+        process: &ProcessOptions,
+        progress: &Progress,
+    ) -> Result<(AudioStream<'a>, trim::Trimmed), WaveemapiError> {
+        let trimmed = trim::Trimmed::default();
+        let stream = self
+            .prepare(process, progress, &trimmed)?
+            .resample(process.sample_rate)
+            .gain(process.gain_db);
+        Ok((stream, trimmed))
+    }
+
+    /// The part of `process` done before resampling and gain, which is also
+    /// what loudness is measured on.
+    fn prepare(
+        self,
+        process: &ProcessOptions,
+        progress: &Progress,
+        trimmed: &trim::Trimmed,
+    ) -> Result<AudioStream<'a>, WaveemapiError> {
+        self.limit_duration(process.max_duration)?
+            .track(progress)
+            .trim(&process.trim)?
+            .trim_silence(process.trim_silence.as_ref(), trimmed)
+            .downmix(&process.mix)
+    }
+
+    /// Keeps the part of the input `spec` selects, with its fades.
+    fn trim(self, spec: &TrimSpec) -> Result<AudioStream<'a>, WaveemapiError> {
+        if spec.is_empty() {
+            return Ok(self);
+        }
+        let channels = self.channels as u64;
+        let samples = trim::Trim::new(
+            self.samples,
+            self.channels,
+            self.sample_rate,
+            spec,
+            self.total_samples.map(|total| total / channels),
+        )?;
+        Ok(AudioStream {
+            total_samples: samples.length().map(|frames| frames * channels),
+            samples: Box::new(samples),
+            ..self
+        })
+    }
+
+    /// Removes leading and trailing silence, reporting how much to `trimmed`.
+    fn trim_silence(self, spec: Option<&SilenceSpec>, trimmed: &trim::Trimmed) -> AudioStream<'a> {
+        let Some(spec) = spec else {
+            return self;
+        };
+        let samples = trim::SilenceTrim::new(
+            self.samples,
+            self.channels,
+            self.sample_rate,
+            spec,
+            trimmed.clone(),
+        );
+        AudioStream {
+            samples: Box::new(samples),
+            total_samples: None,
+            ..self
+        }
+    }
+
+    /// Reports the share of samples consumed to `progress`.
+    fn track(self, progress: &Progress) -> AudioStream<'a> {
+        let Some(total) = self.total_samples.filter(|&total| total > 0) else {
+            return self;
+        };
+        let progress = progress.clone();
+        let mut read = 0u64;
+        let samples = self.samples.inspect(move |_| {
+            read += 1;
+            if read.is_multiple_of(CHUNK_SIZE as u64) || read == total {
+                progress.set((read * 100 / total).min(100) as u8);
+            }
+        });
+        AudioStream {
+            samples: Box::new(samples),
+            ..self
+        }
+    }
+}
+
+/// Encode progress in percent, shared with whoever started the encode.
+#[derive(Debug, Clone, Default)]
+pub struct Progress(Arc<AtomicU8>);
+
+impl Progress {
+    pub fn get(&self) -> u8 {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    pub fn set(&self, percent: u8) {
+        self.0.store(percent, Ordering::Relaxed);
+    }
+}
+
+/// Processing applied to the decoded samples before they reach LAME.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ProcessOptions {
+    pub mix: MixSpec,
+    /// Output sample rate, defaults to the nearest one LAME supports.
+    pub sample_rate: Option<u32>,
+    /// Longest accepted input in seconds, unlimited when `None`.
+    pub max_duration: Option<u64>,
+    /// Part of the input to keep, and fades.
+    pub trim: TrimSpec,
+    /// Leading and trailing silence to remove.
+    pub trim_silence: Option<SilenceSpec>,
+    /// Integrated loudness to normalize to, in LUFS.
+    pub normalize_lufs: Option<f64>,
+    /// Gain in dB reaching `normalize_lufs`, found by `wav_file_normalize`.
+    pub gain_db: Option<f64>,
+    /// Where `wav_file_split` cuts the input into tracks.
+    pub split: Option<SplitSpec>,
+    /// Seconds `wav_files_concat` overlaps consecutive parts by.
+    pub crossfade: Option<f64>,
+}
+
+/// What processing did to the audio, for the client.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ProcessReport {
+    /// Gain applied for `normalize_lufs`.
+    pub gain_db: Option<f64>,
+    /// Seconds of silence removed from the start and end for `trim_silence`.
+    pub silence_trimmed: Option<(f64, f64)>,
+}
+
+impl ProcessReport {
+    /// This report, with what it leaves out taken from `other`.
+    pub fn or(self, other: ProcessReport) -> ProcessReport {
+        ProcessReport {
+            gain_db: self.gain_db.or(other.gain_db),
+            silence_trimmed: self.silence_trimmed.or(other.silence_trimmed),
+        }
+    }
+}
+
+/// Encodes a WAV file on disk to a new MP3 in `data_path`, returning its path.
+pub fn wav_file_decode(
+    path: &str,
     data_path: &str,
-) -> Result<String, WaveemapiError> {
-    let channels = reader.spec().channels as usize;
-    let bit_depth = reader.spec().bits_per_sample;
-    if channels != 1 && channels != 2 {
-        return Err(WaveemapiError::Hound(hound::Error::Unsupported));
+    options: &EncodeOptions,
+    process: &ProcessOptions,
+    tags: &TrackTags,
+    progress: &Progress,
+) -> Result<(String, ProcessReport), WaveemapiError> {
+    to_mp3_file(data_path, |out| {
+        wav_file_encode(path, out, options, process, tags, progress)
+    })
+}
+
+/// Encodes a WAV read front to back, e.g. straight from a request body, to a
+/// new MP3 in `data_path`, returning its path. The ID3 tag is written up front
+/// from `tags` and the metadata before the samples, and rewritten only when
+/// metadata following the samples changes it.
+pub fn wav_reader_decode<R: Read>(
+    reader: R,
+    data_path: &str,
+    options: &EncodeOptions,
+    process: &ProcessOptions,
+    tags: &TrackTags,
+    progress: &Progress,
+) -> Result<(String, ProcessReport), WaveemapiError> {
+    let mut written = TrackTags::default();
+    let mut embedded = TrackTags::default();
+    let (path, report) = to_mp3_file(data_path, |out| {
+        let (report, found) = read_stream(reader, |stream, header| {
+            written = tags.clone().or_embedded(header);
+            process_samples(stream, out, options, process, &written, progress)
+        })?;
+        embedded = found;
+        Ok(report)
+    })?;
+    let tags = tags.clone().or_embedded(embedded);
+    if tags == written {
+        return Ok((path, report));
     }
-    let sample_rate = reader.spec().sample_rate;
+    let retagged = to_mp3_file(data_path, |out| {
+        let mut mp3 = File::open(&path)?;
+        mp3.seek(SeekFrom::Start(written.to_id3v2().len() as u64))?;
+        out.write_all(&tags.to_id3v2())?;
+        io::copy(&mut mp3, out)?;
+        Ok(report)
+    });
+    std::fs::remove_file(&path).ok();
+    retagged
+}
 
-    let decode_result = match bit_depth {
-        16 => process_samples(
-            reader.samples::<i16>(),
-            channels,
-            1.0 / I16_MAXPONE,
-            sample_rate,
-            data_path,
-        )?,
-        24 => process_samples(
-            reader.samples::<i32>(),
-            channels,
-            1.0 / I24_MAXPONE,
-            sample_rate,
-            data_path,
-        )?,
-        32 => match reader.spec().sample_format {
-            hound::SampleFormat::Float => process_samples(
-                reader.samples::<f32>(),
+/// Runs `encode` against a new MP3 file, removing it again if encoding or
+/// writing it out fails.
+fn to_mp3_file(
+    data_path: &str,
+    encode: impl FnOnce(&mut dyn Write) -> Result<ProcessReport, WaveemapiError>,
+) -> Result<(String, ProcessReport), WaveemapiError> {
+    let ppath = mp3_path(data_path);
+    let mut bwriter = BufWriter::new(File::create(&ppath)?);
+    let result = encode(&mut bwriter).and_then(|report| {
+        bwriter.flush()?;
+        Ok(report)
+    });
+    if result.is_err() {
+        drop(bwriter);
+        std::fs::remove_file(&ppath).ok();
+    }
+    result.map(|report| (ppath, report))
+}
+
+/// Encodes a WAV, AIFF or FLAC that can only be read front to back into `out`.
+/// Only the metadata before the sample data is used for the ID3 tag.
+pub fn wav_read_encode<R: Read>(
+    reader: R,
+    out: &mut dyn Write,
+    options: &EncodeOptions,
+    process: &ProcessOptions,
+    tags: &TrackTags,
+    progress: &Progress,
+) -> Result<ProcessReport, WaveemapiError> {
+    read_stream(reader, |stream, embedded| {
+        let tags = tags.clone().or_embedded(embedded);
+        process_samples(stream, out, options, process, &tags, progress)
+    })
+    .map(|(report, _)| report)
+}
+
+/// Encodes a WAV, AIFF or FLAC file on disk into `out`, using its embedded
+/// metadata for the ID3 tag when the client did not supply any.
+pub fn wav_file_encode(
+    path: &str,
+    out: &mut dyn Write,
+    options: &EncodeOptions,
+    process: &ProcessOptions,
+    tags: &TrackTags,
+    progress: &Progress,
+) -> Result<ProcessReport, WaveemapiError> {
+    file_stream(path, |stream, embedded| {
+        let tags = tags.clone().or_embedded(embedded);
+        process_samples(stream, out, options, process, &tags, progress)
+    })
+}
+
+/// Measures the loudness of a WAV, AIFF or FLAC file on disk as it would be
+/// encoded and returns the gain in dB that brings it to `normalize_lufs`.
+/// `None` when no target was requested or the file is silent.
+pub fn wav_file_normalize(
+    path: &str,
+    process: &ProcessOptions,
+) -> Result<Option<f64>, WaveemapiError> {
+    let Some(target) = process.normalize_lufs else {
+        return Ok(None);
+    };
+    let loudness = file_stream(path, |stream, _| {
+        analyze::integrated_loudness(stream.prepare(
+            process,
+            &Progress::default(),
+            &trim::Trimmed::default(),
+        )?)
+    })?;
+    Ok(loudness.map(|lufs| target - lufs))
+}
+
+/// Cuts a WAV, AIFF or FLAC file on disk into tracks where `process.split`
+/// says, encodes each to its own MP3 and stores them, numbered, in a new ZIP
+/// in `data_path`, returning its path. Tracks are titled by their cue label.
+pub fn wav_file_split(
+    path: &str,
+    data_path: &str,
+    options: &EncodeOptions,
+    process: &ProcessOptions,
+    tags: &TrackTags,
+    progress: &Progress,
+) -> Result<(String, ProcessReport), WaveemapiError> {
+    let silence = match process.split {
+        Some(SplitSpec::Silence {
+            threshold_dbfs,
+            min_gap,
+        }) => Some(file_stream(path, |stream, _| {
+            let stream = stream.limit_duration(process.max_duration)?;
+            split::silence_segments(
+                stream.samples,
+                stream.channels,
+                stream.sample_rate,
+                threshold_dbfs,
+                min_gap,
+            )
+        })?),
+        Some(SplitSpec::Cue) => None,
+        None => {
+            return Err(WaveemapiError::InvalidOptions(
+                "no split requested".to_string(),
+            ));
+        }
+    };
+    let markers = riff::scan_metadata(&mut File::open(path)?)?.markers();
+    // Each track gets the rest of the processing on its own.
+    let track_process = ProcessOptions {
+        max_duration: None,
+        ..process.clone()
+    };
+    let zpath = zip_path(data_path);
+    let result = file_stream(path, |stream, embedded| {
+        let tags = tags.clone().or_embedded(embedded);
+        let channels = stream.channels;
+        let segments = match silence {
+            Some(segments) => segments,
+            None => split::cue_segments(
+                &markers,
+                stream.total_samples.unwrap_or(0) / channels.max(1) as u64,
+            ),
+        };
+        if segments.is_empty() {
+            return Err(WaveemapiError::InvalidOptions(
+                "nothing to split, the audio is empty or has no cue points".to_string(),
+            ));
+        }
+        let (sample_rate, channel_mask) = (stream.sample_rate, stream.channel_mask);
+        let mut samples = stream
+            .limit_duration(process.max_duration)?
+            .track(progress)
+            .samples;
+        let mut zip = zip::ZipWriter::new(BufWriter::new(File::create(&zpath)?));
+        let file_options = zip::write::SimpleFileOptions::default()
+            .compression_method(zip::CompressionMethod::Stored);
+        let width = segments.len().to_string().len().max(2);
+        for (n, segment) in segments.iter().enumerate() {
+            let track_samples = (segment.end - segment.start) * channels as u64;
+            let track = AudioStream {
+                samples: Box::new((&mut samples).take(track_samples as usize)),
                 channels,
-                1.0,
                 sample_rate,
-                data_path,
-            )?,
-            hound::SampleFormat::Int => process_samples(
-                reader.samples::<i32>(),
+                channel_mask,
+                total_samples: Some(track_samples),
+            };
+            let track_tags = TrackTags {
+                title: Some(
+                    segment
+                        .title
+                        .clone()
+                        .unwrap_or_else(|| format!("Track {}", n + 1)),
+                ),
+                track: Some(format!("{}/{}", n + 1, segments.len())),
+                ..tags.clone()
+            };
+            zip.start_file(format!("{:0width$}.mp3", n + 1), file_options)?;
+            process_samples(
+                track,
+                &mut zip,
+                options,
+                &track_process,
+                &track_tags,
+                &Progress::default(),
+            )?;
+        }
+        zip.finish()?.flush()?;
+        Ok(ProcessReport {
+            gain_db: process.gain_db,
+            ..ProcessReport::default()
+        })
+    });
+    match result {
+        Ok(report) => Ok((zpath, report)),
+        Err(e) => {
+            std::fs::remove_file(&zpath).ok();
+            Err(e)
+        }
+    }
+}
+
+/// Joins WAV, AIFF or FLAC files on disk into a new 32-bit float WAV at
+/// `wpath`: the `parts` in order, crossfaded by `process.crossfade`, with
+/// `intro` and `outro` played before and after them without a gap. Parts are
+/// resampled to the rate of the first and mono parts upmixed to its channels.
+/// With an intro or outro, `process` other than `sample_rate` is applied to
+/// the joined parts first and the assets are conformed to the result, which is
+/// reported. Returns the metadata of the first part.
+pub fn wav_files_concat(
+    parts: &[String],
+    intro: Option<&str>,
+    outro: Option<&str>,
+    wpath: &str,
+    process: &ProcessOptions,
+) -> Result<(TrackTags, ProcessReport), WaveemapiError> {
+    if parts.is_empty() {
+        return Err(WaveemapiError::InvalidOptions("no wav to join".to_string()));
+    }
+    let assets = intro.is_some() || outro.is_some();
+    let gain_db = match process.normalize_lufs {
+        Some(target) if assets => files_streams(parts, Vec::new(), &mut |streams| {
+            let (upload, _) = join_parts(streams, process)?;
+            analyze::integrated_loudness(upload.prepare(
+                process,
+                &Progress::default(),
+                &trim::Trimmed::default(),
+            )?)
+        })?
+        .map(|lufs| target - lufs),
+        _ => None,
+    };
+    let paths: Vec<String> = intro
+        .into_iter()
+        .chain(parts.iter().map(String::as_str))
+        .chain(outro)
+        .map(str::to_string)
+        .collect();
+    let trimmed = trim::Trimmed::default();
+    let mut upload_rate = 0;
+    let result = files_streams(&paths, Vec::new(), &mut |mut streams| {
+        let outro = outro.and_then(|_| streams.pop());
+        let intro = intro.map(|_| streams.remove(0));
+        let (mut upload, tags) = join_parts(streams, process)?;
+        upload_rate = upload.sample_rate;
+        if assets {
+            upload = upload
+                .prepare(process, &Progress::default(), &trimmed)?
+                .gain(gain_db);
+        }
+        let (channels, sample_rate) = (upload.channels, upload.sample_rate);
+        let intro = intro
+            .map(|(stream, _)| conform_asset(stream, "intro", channels, sample_rate))
+            .transpose()?;
+        let outro = outro
+            .map(|(stream, _)| conform_asset(stream, "outro", channels, sample_rate))
+            .transpose()?;
+        let joined = AudioStream {
+            samples: Box::new(concat::Concat::new(
+                intro
+                    .into_iter()
+                    .chain([upload.samples])
+                    .chain(outro)
+                    .collect(),
                 channels,
-                1.0 / I32_MAXPONE,
-                sample_rate,
-                data_path,
-            )?,
-        },
-        _ => return Err(WaveemapiError::Hound(hound::Error::Unsupported)),
+                0,
+            )),
+            channels,
+            sample_rate,
+            channel_mask: None,
+            total_samples: None,
+        }
+        .limit_duration(process.max_duration)?;
+        write_wav(joined, wpath)?;
+        Ok(tags)
+    });
+    match result {
+        Ok(tags) => Ok((
+            tags,
+            ProcessReport {
+                gain_db,
+                silence_trimmed: trimmed.frames().map(|(start, end)| {
+                    (
+                        start as f64 / upload_rate as f64,
+                        end as f64 / upload_rate as f64,
+                    )
+                }),
+            },
+        )),
+        Err(e) => {
+            std::fs::remove_file(wpath).ok();
+            Err(e)
+        }
+    }
+}
+
+/// Joins `streams` crossfaded by `process.crossfade`, resampled to the rate of
+/// the first and mono ones upmixed to its channels. Returns the metadata of
+/// the first along with the joined audio.
+fn join_parts<'a>(
+    mut streams: Vec<(AudioStream<'a>, TrackTags)>,
+    process: &ProcessOptions,
+) -> Result<(AudioStream<'a>, TrackTags), WaveemapiError> {
+    let tags = std::mem::take(&mut streams[0].1);
+    let first = &streams[0].0;
+    let (channels, sample_rate, channel_mask) =
+        (first.channels, first.sample_rate, first.channel_mask);
+    let mut conformed = Vec::with_capacity(streams.len());
+    for (n, (part, _)) in streams.into_iter().enumerate() {
+        let found = part.channels;
+        conformed.push(conform(part, channels, sample_rate).ok_or_else(|| {
+            WaveemapiError::InvalidOptions(format!(
+                "wav part {} has {} channels, part 1 has {}",
+                n + 1,
+                found,
+                channels
+            ))
+        })?);
+    }
+    let crossfade = process.crossfade.unwrap_or(0.0) * sample_rate as f64;
+    let joined = AudioStream {
+        samples: Box::new(concat::Concat::new(
+            conformed,
+            channels,
+            crossfade.round() as u64,
+        )),
+        channels,
+        sample_rate,
+        channel_mask,
+        total_samples: None,
     };
-    Ok(decode_result)
+    Ok((joined, tags))
 }
 
-fn process_samples<T>(
-    samples: impl Iterator<Item = hound::Result<T>>,
+/// Conforms the asset `name` to the processed upload, downmixing it when the
+/// upload was mixed to fewer channels.
+fn conform_asset<'a>(
+    stream: AudioStream<'a>,
+    name: &str,
     channels: usize,
-    scale: f32,
     sample_rate: u32,
-    data_path: &str,
-) -> Result<String, WaveemapiError>
+) -> Result<Samples<'a>, WaveemapiError> {
+    let found = stream.channels;
+    let stream = match channels {
+        _ if found == channels || found == 1 => stream,
+        1 => stream.downmix(&MixSpec::Mono)?,
+        2 => stream.downmix(&MixSpec::Stereo)?,
+        _ => stream,
+    };
+    conform(stream, channels, sample_rate).ok_or_else(|| {
+        WaveemapiError::InvalidOptions(format!(
+            "{} has {} channels, the upload has {}",
+            name, found, channels
+        ))
+    })
+}
+
+/// Resamples `stream` to `sample_rate` and upmixes it to `channels` if it is
+/// mono. `None` when the channels differ otherwise.
+fn conform<'a>(stream: AudioStream<'a>, channels: usize, sample_rate: u32) -> Option<Samples<'a>> {
+    let stream = stream.resample(Some(sample_rate));
+    match stream.channels {
+        c if c == channels => Some(stream.samples),
+        1 => Some(Box::new(mix::Downmix::new(
+            stream.samples,
+            vec![vec![1.0]; channels],
+        ))),
+        _ => None,
+    }
+}
+
+/// Writes `stream` to a new 32-bit float WAV at `wpath`.
+fn write_wav(stream: AudioStream<'_>, wpath: &str) -> Result<(), WaveemapiError> {
+    let spec = hound::WavSpec {
+        channels: stream.channels as u16,
+        sample_rate: stream.sample_rate,
+        bits_per_sample: 32,
+        sample_format: hound::SampleFormat::Float,
+    };
+    let mut writer = hound::WavWriter::create(wpath, spec)?;
+    for sample in stream.samples {
+        writer.write_sample(sample?)?;
+    }
+    writer.finalize()?;
+    Ok(())
+}
+
+type ConsumeParts<'c, T> =
+    dyn FnMut(Vec<(AudioStream<'_>, TrackTags)>) -> Result<T, WaveemapiError> + 'c;
+
+/// Decodes every file in `paths` after `parts`, handing all of them with
+/// their metadata to `consume`.
+fn files_streams<T>(
+    paths: &[String],
+    parts: Vec<(AudioStream<'_>, TrackTags)>,
+    consume: &mut ConsumeParts<'_, T>,
+) -> Result<T, WaveemapiError> {
+    let Some((path, rest)) = paths.split_first() else {
+        return consume(parts);
+    };
+    file_stream(path, |stream, embedded| {
+        let mut parts = parts;
+        parts.push((stream, embedded));
+        files_streams(rest, parts, consume)
+    })
+}
+
+/// Measures the levels of a WAV, AIFF or FLAC read front to back.
+pub fn wav_read_analyze<R: Read>(
+    reader: R,
+    max_duration: Option<u64>,
+) -> Result<Analysis, WaveemapiError> {
+    read_stream(reader, |stream, _| {
+        analyze::analyze(stream.limit_duration(max_duration)?)
+    })
+    .map(|(analysis, _)| analysis)
+}
+
+/// Collects waveform peaks of a WAV, AIFF or FLAC read front to back, after
+/// the same processing as before encoding.
+pub fn wav_read_waveform<R: Read>(
+    reader: R,
+    process: &ProcessOptions,
+    options: &WaveformOptions,
+) -> Result<Waveform, WaveemapiError> {
+    read_stream(reader, |stream, _| {
+        let (stream, _) = stream.process(process, &Progress::default())?;
+        waveform::waveform(stream, options)
+    })
+    .map(|(waveform, _)| waveform)
+}
+
+/// Decodes a WAV, AIFF or FLAC that can only be read front to back, handing
+/// the samples and the metadata found before them to `consume`. The rest of
+/// the input is read afterwards, and returned along with the result is the
+/// metadata of all of it, including WAV chunks that follow the samples.
+fn read_stream<R: Read, T>(
+    mut reader: R,
+    consume: impl FnOnce(AudioStream<'_>, TrackTags) -> Result<T, WaveemapiError>,
+) -> Result<(T, TrackTags), WaveemapiError> {
+    let (mut embedded, header) = riff::scan_header(&mut reader)?;
+    if aiff::is_aiff(&header) || flac::is_flac(&header) {
+        let mut found = TrackTags::default();
+        let consume = |stream: AudioStream<'_>, tags: TrackTags| {
+            found = tags.clone();
+            consume(stream, tags)
+        };
+        let input = Cursor::new(header.as_slice()).chain(&mut reader);
+        let result = if aiff::is_aiff(&header) {
+            aiff_stream(input, consume)?
+        } else {
+            flac_stream(BufReader::new(input), consume)?
+        };
+        io::copy(&mut reader, &mut io::sink())?;
+        return Ok((result, found));
+    }
+    let tags = embedded.to_tags();
+    // The decoder may stop short of, or read ahead past, the end of the
+    // samples, so it only gets to see them.
+    let mut samples = (&mut reader).take(embedded.data.map_or(u64::MAX, |d| d.padded_len()));
+    let result = match (embedded.fmt, embedded.data) {
+        (Some(fmt), Some(data)) if embedded.rf64 || needs_fallback(&fmt) => {
+            let stream = fallback_stream(
+                BufReader::new(&mut samples),
+                &fmt,
+                data.len,
+                embedded.fact_frames,
+            )?;
+            consume(stream, tags)?
+        }
+        _ => {
+            let channel_mask = embedded.fmt.and_then(|fmt| fmt.channel_mask);
+            let input = Cursor::new(header.as_slice()).chain(&mut samples);
+            wav_stream(
+                WavReader::new(BufReader::new(input))?,
+                channel_mask,
+                tags,
+                consume,
+            )?
+        }
+    };
+    io::copy(&mut samples, &mut io::sink())?;
+    riff::scan_after_data(&mut reader, &mut embedded)?;
+    io::copy(&mut reader, &mut io::sink())?;
+    Ok((result, embedded.to_tags()))
+}
+
+/// Decodes a WAV, AIFF or FLAC file on disk, handing the samples and its
+/// metadata to `consume`.
+fn file_stream<T>(
+    path: &str,
+    consume: impl FnOnce(AudioStream<'_>, TrackTags) -> Result<T, WaveemapiError>,
+) -> Result<T, WaveemapiError> {
+    let mut file = File::open(path)?;
+    let mut magic = [0u8; 12];
+    let magic_len = riff::read_full(&mut file, &mut magic)?;
+    file.seek(SeekFrom::Start(0))?;
+    if aiff::is_aiff(&magic[..magic_len]) {
+        return aiff_stream(BufReader::new(file), consume);
+    }
+    if flac::is_flac(&magic[..magic_len]) {
+        return flac_stream(BufReader::new(file), consume);
+    }
+    let embedded = riff::scan_metadata(&mut file)?;
+    let tags = embedded.to_tags();
+    if let (Some(fmt), Some(data)) = (embedded.fmt, embedded.data)
+        && (embedded.rf64 || needs_fallback(&fmt))
+    {
+        file.seek(SeekFrom::Start(data.offset))?;
+        let stream = fallback_stream(BufReader::new(file), &fmt, data.len, embedded.fact_frames)?;
+        return consume(stream, tags);
+    }
+    file.seek(SeekFrom::Start(0))?;
+    let channel_mask = embedded.fmt.and_then(|fmt| fmt.channel_mask);
+    let reader = WavReader::new(BufReader::new(file))?;
+    wav_stream(reader, channel_mask, tags, consume)
+}
+
+/// Decodes AIFF or AIFF-C, whose `NAME`, `AUTH` and `ANNO` chunks become the
+/// embedded metadata.
+fn aiff_stream<R: Read, T>(
+    mut reader: R,
+    consume: impl FnOnce(AudioStream<'_>, TrackTags) -> Result<T, WaveemapiError>,
+) -> Result<T, WaveemapiError> {
+    let header = aiff::read_header(&mut reader)?;
+    let tags = header.to_tags();
+    let samples = match header.encoding {
+        aiff::Encoding::Int(order) => scaled(
+            pcm::PcmSamples::aiff(
+                reader,
+                header.container_bytes() as usize,
+                header.data_len,
+                order,
+            )?,
+            1.0 / I32_MAXPONE,
+        ),
+        aiff::Encoding::Float32 => scaled(
+            pcm::FloatSamples::new(reader, header.data_len, pcm::ByteOrder::Big),
+            1.0,
+        ),
+    };
+    let stream = AudioStream {
+        samples,
+        channels: header.channels as usize,
+        sample_rate: header.sample_rate,
+        channel_mask: None,
+        total_samples: Some(header.data_len / header.container_bytes() as u64),
+    };
+    consume(stream, tags)
+}
+
+/// Decodes FLAC, whose Vorbis comments become the embedded metadata.
+fn flac_stream<R: Read, T>(
+    reader: R,
+    consume: impl FnOnce(AudioStream<'_>, TrackTags) -> Result<T, WaveemapiError>,
+) -> Result<T, WaveemapiError> {
+    let mut reader = claxon::FlacReader::new(reader).map_err(flac::to_hound)?;
+    let tags = flac::to_tags(reader.tags());
+    let info = reader.streaminfo();
+    // Samples are right-justified, so scale by the stream's bit depth.
+    let scale = 1.0 / (1u64 << (info.bits_per_sample - 1)) as f32;
+    let stream = AudioStream {
+        samples: scaled(reader.samples().map(|s| s.map_err(flac::to_hound)), scale),
+        channels: info.channels as usize,
+        sample_rate: info.sample_rate,
+        channel_mask: None,
+        total_samples: info.samples.map(|frames| frames * info.channels as u64),
+    };
+    consume(stream, tags)
+}
+
+/// Layouts `hound` cannot read: integer PCM such as 12-bit or 20-bit
+/// samples, G.711 A-law and µ-law, and IMA ADPCM.
+fn needs_fallback(fmt: &riff::FmtChunk) -> bool {
+    match fmt.format() {
+        riff::WAVE_FORMAT_PCM => !matches!(
+            (fmt.container_bytes(), fmt.valid_bits()),
+            (1, 8) | (2, 16) | (3, 24) | (4, 24) | (4, 32)
+        ),
+        riff::WAVE_FORMAT_ALAW | riff::WAVE_FORMAT_MULAW | riff::WAVE_FORMAT_IMA_ADPCM => true,
+        _ => false,
+    }
+}
+
+/// Expands the sample data of a layout picked by `needs_fallback`, or of any
+/// PCM or float RF64. The `fact` chunk, when present, gives the frame count
+/// of compressed data.
+fn fallback_stream<'a, R: Read + 'a>(
+    reader: R,
+    fmt: &riff::FmtChunk,
+    data_len: u64,
+    fact_frames: Option<u32>,
+) -> Result<AudioStream<'a>, WaveemapiError> {
+    let channels = fmt.channels as u64;
+    let (samples, total_samples) = match fmt.format() {
+        riff::WAVE_FORMAT_ALAW | riff::WAVE_FORMAT_MULAW => {
+            let law = if fmt.format() == riff::WAVE_FORMAT_ALAW {
+                g711::Law::A
+            } else {
+                g711::Law::Mu
+            };
+            let samples = g711::G711Samples::new(reader, law, data_len);
+            (scaled(samples, 1.0 / I16_MAXPONE), data_len)
+        }
+        riff::WAVE_FORMAT_IMA_ADPCM => {
+            let samples =
+                adpcm::ImaAdpcmSamples::new(reader, fmt.channels, fmt.block_align, data_len)?;
+            let total = fallback_frames(fmt, data_len, fact_frames) * channels;
+            (
+                scaled(samples.take(total as usize), 1.0 / I16_MAXPONE),
+                total,
+            )
+        }
+        riff::WAVE_FORMAT_IEEE_FLOAT if fmt.container_bytes() == 4 => {
+            let samples = pcm::FloatSamples::new(reader, data_len, pcm::ByteOrder::Little);
+            (scaled(samples, 1.0), data_len / 4)
+        }
+        riff::WAVE_FORMAT_PCM => {
+            let samples = pcm::PcmSamples::new(reader, fmt.container_bytes() as usize, data_len)?;
+            let total = data_len / fmt.container_bytes() as u64;
+            (scaled(samples, 1.0 / I32_MAXPONE), total)
+        }
+        _ => return Err(WaveemapiError::Hound(hound::Error::Unsupported)),
+    };
+    Ok(AudioStream {
+        samples,
+        channels: fmt.channels as usize,
+        sample_rate: fmt.sample_rate,
+        channel_mask: fmt.channel_mask,
+        total_samples: Some(total_samples),
+    })
+}
+
+/// Sample frames in the data of a layout picked by `needs_fallback`.
+fn fallback_frames(fmt: &riff::FmtChunk, data_len: u64, fact_frames: Option<u32>) -> u64 {
+    let block_align = fmt.block_align.max(1) as u64;
+    match fmt.format() {
+        riff::WAVE_FORMAT_IMA_ADPCM => fact_frames.map(u64::from).unwrap_or_else(|| {
+            data_len.div_ceil(block_align) * adpcm::frames_per_block(fmt.block_align, fmt.channels)
+        }),
+        _ => data_len / block_align,
+    }
+}
+
+fn wav_stream<R: Read, T>(
+    mut reader: WavReader<R>,
+    channel_mask: Option<u32>,
+    tags: TrackTags,
+    consume: impl FnOnce(AudioStream<'_>, TrackTags) -> Result<T, WaveemapiError>,
+) -> Result<T, WaveemapiError> {
+    let spec = reader.spec();
+    let total_samples = reader.len() as u64;
+    let samples = match (spec.bits_per_sample, spec.sample_format) {
+        (8, hound::SampleFormat::Int) => scaled(reader.samples::<i8>(), 1.0 / I8_MAXPONE),
+        (16, hound::SampleFormat::Int) => scaled(reader.samples::<i16>(), 1.0 / I16_MAXPONE),
+        (24, hound::SampleFormat::Int) => scaled(reader.samples::<i32>(), 1.0 / I24_MAXPONE),
+        (32, hound::SampleFormat::Int) => scaled(reader.samples::<i32>(), 1.0 / I32_MAXPONE),
+        (32, hound::SampleFormat::Float) => scaled(reader.samples::<f32>(), 1.0),
+        _ => return Err(WaveemapiError::Hound(hound::Error::Unsupported)),
+    };
+    let stream = AudioStream {
+        samples,
+        channels: spec.channels as usize,
+        sample_rate: spec.sample_rate,
+        channel_mask,
+        total_samples: Some(total_samples),
+    };
+    consume(stream, tags)
+}
+
+fn scaled<'a, T>(samples: impl Iterator<Item = hound::Result<T>> + 'a, scale: f32) -> Samples<'a>
 where
     f64: From<T>,
 {
+    Box::new(samples.map(move |sample| {
+        let srb: f64 = sample.map_err(WaveemapiError::Hound)?.into();
+        let sr: f32 = srb as f32;
+        Ok(sr * scale)
+    }))
+}
+
+fn process_samples(
+    stream: AudioStream<'_>,
+    out: &mut dyn Write,
+    options: &EncodeOptions,
+    process: &ProcessOptions,
+    tags: &TrackTags,
+    progress: &Progress,
+) -> Result<ProcessReport, WaveemapiError> {
+    let sample_rate = stream.sample_rate as f64;
+    let (stream, trimmed) = stream.process(process, progress)?;
+    let channels = stream.channels;
+    if channels != 1 && channels != 2 {
+        return Err(WaveemapiError::Hound(hound::Error::Unsupported));
+    }
     let mut mp3_encoder =
         Builder::new().ok_or_else(|| WaveemapiError::Build(BuildError::Generic))?;
     mp3_encoder
         .set_num_channels(channels as u8)
         .expect("Failed to set number of channels on MP3 encoder");
     mp3_encoder
-        .set_sample_rate(sample_rate)
-        .map_err(WaveemapiError::Build)?;
-    mp3_encoder
-        .set_brate(mp3lame_encoder::Bitrate::Kbps128)
-        .map_err(WaveemapiError::Build)?;
-    mp3_encoder
-        .set_quality(mp3lame_encoder::Quality::Decent)
+        .set_sample_rate(stream.sample_rate)
         .map_err(WaveemapiError::Build)?;
-
-    mp3_encoder.set_id3_tag(Id3Tag {
-        title: b"title",
-        artist: b"artist",
-        album_art: &[],
-        album: b"album",
-        year: b"year",
-        comment: b"comment",
-    })?;
+    options.apply(&mut mp3_encoder)?;
 
     let mut mp3_encoder = mp3_encoder.build().map_err(WaveemapiError::Build)?;
-    let ppath = mp3_path(data_path);
-    let file = File::create(&ppath).map_err(WaveemapiError::Io)?;
-    let mut bwriter = BufWriter::new(file);
+    if !tags.is_empty() {
+        out.write_all(&tags.to_id3v2())?;
+    }
     let mut left = Vec::with_capacity(CHUNK_SIZE);
     let mut right = Vec::with_capacity(CHUNK_SIZE);
     let is_stereo = channels == 2;
 
-    for (idx, sample) in samples.enumerate() {
-        let sample_val = sample.map_err(WaveemapiError::Hound)?;
-        let srb: f64 = sample_val.into();
-        let sr: f32 = srb as f32;
-        let s = sr * scale;
+    for (idx, sample) in stream.samples.enumerate() {
+        let s = sample?;
         if is_stereo {
             if idx % 2 == 0 {
                 left.push(s);
@@ -118,7 +1204,7 @@ where
                 encode_dual(
                     &left[..CHUNK_SIZE],
                     &right[..CHUNK_SIZE],
-                    &mut bwriter,
+                    out,
                     &mut mp3_encoder,
                 )?;
                 left.clear();
@@ -127,7 +1213,7 @@ where
         } else {
             left.push(s);
             if left.len() >= CHUNK_SIZE {
-                encode_mono(&left, &mut bwriter, &mut mp3_encoder)?;
+                encode_mono(&left, out, &mut mp3_encoder)?;
                 left.clear();
             }
         }
@@ -137,29 +1223,34 @@ where
             let max_len = std::cmp::max(left.len(), right.len());
             left.resize(max_len, 0.0);
             right.resize(max_len, 0.0);
-            encode_dual(&left, &right, &mut bwriter, &mut mp3_encoder)?;
+            encode_dual(&left, &right, out, &mut mp3_encoder)?;
             left.clear();
             right.clear();
         }
     } else if !left.is_empty() {
-        encode_mono(&left, &mut bwriter, &mut mp3_encoder)?;
+        encode_mono(&left, out, &mut mp3_encoder)?;
         left.clear();
     }
     let num_frames = cmp::max(left.len(), right.len());
     let mut tail = Vec::with_capacity(mp3lame_encoder::max_required_buffer_size(num_frames));
     let flushed = mp3_encoder.flush_to_vec::<FlushNoGap>(&mut tail)?;
     if flushed > 0 {
-        bwriter.write_all(&tail).map_err(WaveemapiError::Io)?;
+        out.write_all(&tail).map_err(WaveemapiError::Io)?;
     }
 
-    bwriter.flush()?;
-    Ok(ppath)
+    out.flush()?;
+    Ok(ProcessReport {
+        gain_db: process.gain_db,
+        silence_trimmed: trimmed
+            .frames()
+            .map(|(start, end)| (start as f64 / sample_rate, end as f64 / sample_rate)),
+    })
 }
 
 fn encode_dual(
     left: &[f32],
     right: &[f32],
-    bwriter: &mut BufWriter<File>,
+    bwriter: &mut dyn Write,
     encoder: &mut Encoder,
 ) -> Result<(), WaveemapiError> {
     let chunk = DualPcm { left, right };
@@ -175,7 +1266,7 @@ fn encode_dual(
 
 fn encode_mono(
     left: &[f32],
-    bwriter: &mut BufWriter<File>,
+    bwriter: &mut dyn Write,
     encoder: &mut Encoder,
 ) -> Result<(), WaveemapiError> {
     let chunk = MonoPcm(left);
@@ -190,7 +1281,7 @@ fn encode_mono(
 }
 
 #[allow(dead_code)]
-const SAMPLE_PATH: &str = concat!(
+pub(crate) const SAMPLE_PATH: &str = concat!(
     env!("CARGO_MANIFEST_DIR"),
     "/",
     "tests",
@@ -201,9 +1292,49 @@ const SAMPLE_PATH: &str = concat!(
 
 #[allow(dead_code)]
 fn decode_sample(name: &str, data_path: &str) -> Result<String, WaveemapiError> {
+    decode_sample_with(
+        name,
+        data_path,
+        &EncodeOptions::default(),
+        &TrackTags::default(),
+    )
+}
+
+#[allow(dead_code)]
+fn decode_sample_processed(
+    name: &str,
+    data_path: &str,
+    process: &ProcessOptions,
+) -> Result<String, WaveemapiError> {
+    let path = format!("{}{}", SAMPLE_PATH, name);
+    wav_file_decode(
+        &path,
+        data_path,
+        &EncodeOptions::default(),
+        process,
+        &TrackTags::default(),
+        &Progress::default(),
+    )
+    .map(|(path, _)| path)
+}
+
+#[allow(dead_code)]
+fn decode_sample_with(
+    name: &str,
+    data_path: &str,
+    options: &EncodeOptions,
+    tags: &TrackTags,
+) -> Result<String, WaveemapiError> {
     let path = format!("{}{}", SAMPLE_PATH, name);
-    let reader = WavReader::open(&path)?;
-    wav_decode(reader, data_path)
+    wav_file_decode(
+        &path,
+        data_path,
+        options,
+        &ProcessOptions::default(),
+        tags,
+        &Progress::default(),
+    )
+    .map(|(path, _)| path)
 }
 
 #[cfg(test)]
@@ -357,4 +1488,929 @@ mod tests {
         );
         fs::remove_file(out_path).ok();
     }
+
+    #[test]
+    fn test_options_default() {
+        let options = EncodeOptions::new(None, None, None, None).unwrap();
+        assert_eq!(options, EncodeOptions::default());
+    }
+
+    #[test]
+    fn test_options_vbr() {
+        let options = EncodeOptions::new(Some("VBR"), None, Some(2), Some(0)).unwrap();
+        assert_eq!(options.mode, RateMode::Vbr);
+        assert_eq!(options.quality, 2);
+        assert_eq!(options.vbr_quality, 0);
+    }
+
+    #[test]
+    fn test_options_invalid() {
+        let invalid = [
+            EncodeOptions::new(Some("lossless"), None, None, None),
+            EncodeOptions::new(Some("vbr"), Some(192), None, None),
+            EncodeOptions::new(Some("cbr"), None, None, Some(2)),
+            EncodeOptions::new(None, Some(100), None, None),
+            EncodeOptions::new(None, None, Some(10), None),
+            EncodeOptions::new(Some("vbr"), None, None, Some(10)),
+        ];
+        for result in invalid {
+            assert!(matches!(result, Err(WaveemapiError::InvalidOptions(_))));
+        }
+    }
+
+    #[test]
+    fn test_stereo_i16_vbr() {
+        let tmpdir = tempdir().unwrap();
+        let data_path = tmpdir.path().to_str().unwrap();
+        let options = EncodeOptions::new(Some("vbr"), None, None, Some(2)).unwrap();
+        let out_path = decode_sample_with(
+            "untitledi16.wav",
+            data_path,
+            &options,
+            &TrackTags::default(),
+        )
+        .unwrap();
+        assert!(
+            fs::metadata(&out_path).unwrap().len() > 0,
+            "MP3 file is empty"
+        );
+        fs::remove_file(out_path).ok();
+    }
+
+    #[test]
+    fn test_stereo_i16_abr() {
+        let tmpdir = tempdir().unwrap();
+        let data_path = tmpdir.path().to_str().unwrap();
+        let options = EncodeOptions::new(Some("abr"), Some(96), Some(7), None).unwrap();
+        let out_path = decode_sample_with(
+            "untitledi16.wav",
+            data_path,
+            &options,
+            &TrackTags::default(),
+        )
+        .unwrap();
+        assert!(
+            fs::metadata(&out_path).unwrap().len() > 0,
+            "MP3 file is empty"
+        );
+        fs::remove_file(out_path).ok();
+    }
+
+    #[test]
+    fn test_untagged_output() {
+        let tmpdir = tempdir().unwrap();
+        let data_path = tmpdir.path().to_str().unwrap();
+        let out_path = decode_sample("idkf32monoleft.wav", data_path).unwrap();
+        let bytes = fs::read(&out_path).unwrap();
+        assert_ne!(
+            &bytes[..3],
+            b"ID3",
+            "Untagged output should not carry an ID3 tag"
+        );
+        fs::remove_file(out_path).ok();
+    }
+
+    #[test]
+    fn test_tagged_output() {
+        let tmpdir = tempdir().unwrap();
+        let data_path = tmpdir.path().to_str().unwrap();
+        let tags = TrackTags::new(
+            Some("Title"),
+            Some("Artist"),
+            None,
+            Some("2025"),
+            None,
+            Some("1/2"),
+            Some("Podcast"),
+        )
+        .unwrap();
+        let out_path = decode_sample_with(
+            "idkf32monoleft.wav",
+            data_path,
+            &EncodeOptions::default(),
+            &tags,
+        )
+        .unwrap();
+        let bytes = fs::read(&out_path).unwrap();
+        let tag = tags.to_id3v2();
+        assert_eq!(&bytes[..tag.len()], &tag[..]);
+        fs::remove_file(out_path).ok();
+    }
+
+    #[test]
+    fn test_embedded_tags() {
+        let tmpdir = tempdir().unwrap();
+        let data_path = tmpdir.path().to_str().unwrap();
+        let out_path = decode_sample("infobexti16.wav", data_path).unwrap();
+        let bytes = fs::read(&out_path).unwrap();
+        let tag = TrackTags::new(
+            Some("Field Take 3"),
+            Some("Sound Recordist"),
+            None,
+            Some("2024"),
+            Some("Dawn chorus, north ridge"),
+            None,
+            None,
+        )
+        .unwrap()
+        .to_id3v2();
+        assert_eq!(&bytes[..tag.len()], &tag[..]);
+        fs::remove_file(out_path).ok();
+    }
+
+    #[test]
+    fn test_client_tags_override_embedded() {
+        let tmpdir = tempdir().unwrap();
+        let data_path = tmpdir.path().to_str().unwrap();
+        let tags = TrackTags::new(Some("Client"), None, None, None, None, None, None).unwrap();
+        let out_path = decode_sample_with(
+            "infobexti16.wav",
+            data_path,
+            &EncodeOptions::default(),
+            &tags,
+        )
+        .unwrap();
+        let bytes = fs::read(&out_path).unwrap();
+        let tag = tags.to_id3v2();
+        assert_eq!(&bytes[..tag.len()], &tag[..]);
+        fs::remove_file(out_path).ok();
+    }
+
+    /// The first `count` samples of a test file after `mix`.
+    fn first_samples(name: &str, count: usize, mix: &MixSpec) -> Vec<f32> {
+        file_stream(&format!("{}{}", SAMPLE_PATH, name), |stream, _| {
+            stream.downmix(mix)?.samples.take(count).collect()
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_mono_u8() {
+        // Unsigned bytes 0x80 0x88 0x90 0x97, centred on 128.
+        assert_eq!(
+            first_samples("sineu8.wav", 4, &MixSpec::Auto),
+            [0.0, 8.0 / 128.0, 16.0 / 128.0, 23.0 / 128.0]
+        );
+        let tmpdir = tempdir().unwrap();
+        let data_path = tmpdir.path().to_str().unwrap();
+        let out_path = decode_sample("sineu8.wav", data_path).unwrap();
+        assert!(
+            fs::metadata(&out_path).unwrap().len() > 0,
+            "MP3 file is empty"
+        );
+        fs::remove_file(out_path).ok();
+    }
+
+    #[test]
+    fn test_mono_i12() {
+        // 12 bit values left aligned in 16 bit containers.
+        assert_eq!(
+            first_samples("sinei12.wav", 4, &MixSpec::Auto),
+            [
+                0.0,
+                0x0800 as f32 / 32768.0,
+                0x0fe0 as f32 / 32768.0,
+                0x1780 as f32 / 32768.0
+            ]
+        );
+        let tmpdir = tempdir().unwrap();
+        let data_path = tmpdir.path().to_str().unwrap();
+        let out_path = decode_sample("sinei12.wav", data_path).unwrap();
+        assert!(
+            fs::metadata(&out_path).unwrap().len() > 0,
+            "MP3 file is empty"
+        );
+        fs::remove_file(out_path).ok();
+    }
+
+    #[test]
+    fn test_stereo_i20() {
+        // Left is a sine at half the frequency of right, so left frame 2
+        // matches right frame 1.
+        let raw = [
+            0, 0, 0x0800d0, 0x0fe180, 0x0fe180, 0x1ec4b0, 0x178250, 0x2bbb30,
+        ];
+        assert_eq!(
+            first_samples("sinei20.wav", 8, &MixSpec::Auto),
+            raw.map(|v: i32| v as f32 / 8388608.0)
+        );
+        let tmpdir = tempdir().unwrap();
+        let data_path = tmpdir.path().to_str().unwrap();
+        let out_path = decode_sample("sinei20.wav", data_path).unwrap();
+        assert!(
+            fs::metadata(&out_path).unwrap().len() > 0,
+            "MP3 file is empty"
+        );
+        fs::remove_file(out_path).ok();
+    }
+
+    #[test]
+    fn test_pcm_fallback_selection() {
+        let fmt = |container: u16, bits: u16| riff::FmtChunk {
+            format_tag: riff::WAVE_FORMAT_PCM,
+            channels: 1,
+            block_align: container,
+            bits_per_sample: bits,
+            ..riff::FmtChunk::default()
+        };
+        assert!(!needs_fallback(&fmt(1, 8)));
+        assert!(!needs_fallback(&fmt(3, 24)));
+        assert!(needs_fallback(&fmt(2, 12)));
+        assert!(needs_fallback(&fmt(3, 20)));
+        for format_tag in [
+            riff::WAVE_FORMAT_ALAW,
+            riff::WAVE_FORMAT_MULAW,
+            riff::WAVE_FORMAT_IMA_ADPCM,
+        ] {
+            assert!(needs_fallback(&riff::FmtChunk {
+                format_tag,
+                ..fmt(1, 8)
+            }));
+        }
+    }
+
+    /// The second frame of `sine51i16.wav`: FL FR FC LFE BL BR.
+    const SURROUND_FRAME: [f32; 6] = [
+        616.0 / 32768.0,
+        1229.0 / 32768.0,
+        1838.0 / 32768.0,
+        2439.0 / 32768.0,
+        3031.0 / 32768.0,
+        3611.0 / 32768.0,
+    ];
+
+    /// Stereo downmix of `SURROUND_FRAME`, with centre and surrounds at -3 dB,
+    /// LFE dropped and each side scaled so it cannot clip.
+    fn surround_stereo() -> (f32, f32) {
+        let [fl, fr, fc, _, bl, br] = SURROUND_FRAME;
+        let m = std::f32::consts::FRAC_1_SQRT_2;
+        let norm = 1.0 + 2.0 * m;
+        ((fl + m * (fc + bl)) / norm, (fr + m * (fc + br)) / norm)
+    }
+
+    fn assert_close(got: &[f32], want: &[f32]) {
+        assert_eq!(got.len(), want.len());
+        for (got, want) in got.iter().zip(want) {
+            assert!((got - want).abs() < 1e-6, "{} != {}", got, want);
+        }
+    }
+
+    #[test]
+    fn test_surround_downmix() {
+        for (pick, want) in ["0,1", "2,3", "4,5"].iter().zip(SURROUND_FRAME.chunks(2)) {
+            let mix = MixSpec::new(None, Some(pick), None).unwrap();
+            assert_eq!(&first_samples("sine51i16.wav", 4, &mix)[2..], want);
+        }
+        let (left, right) = surround_stereo();
+        assert_close(
+            &first_samples("sine51i16.wav", 4, &MixSpec::Auto),
+            &[0.0, 0.0, left, right],
+        );
+        let tmpdir = tempdir().unwrap();
+        let data_path = tmpdir.path().to_str().unwrap();
+        let out_path = decode_sample("sine51i16.wav", data_path).unwrap();
+        assert!(
+            fs::metadata(&out_path).unwrap().len() > 0,
+            "MP3 file is empty"
+        );
+        fs::remove_file(out_path).ok();
+    }
+
+    #[test]
+    fn test_surround_mono() {
+        let (left, right) = surround_stereo();
+        assert_close(
+            &first_samples("sine51i16.wav", 2, &MixSpec::Mono),
+            &[0.0, (left + right) / 2.0],
+        );
+        let tmpdir = tempdir().unwrap();
+        let data_path = tmpdir.path().to_str().unwrap();
+        let process = ProcessOptions {
+            mix: MixSpec::new(Some("mono"), None, None).unwrap(),
+            ..ProcessOptions::default()
+        };
+        let out_path = decode_sample_processed("sine51i16.wav", data_path, &process).unwrap();
+        assert!(
+            fs::metadata(&out_path).unwrap().len() > 0,
+            "MP3 file is empty"
+        );
+        fs::remove_file(out_path).ok();
+    }
+
+    #[test]
+    fn test_quad_pick_channels() {
+        let tmpdir = tempdir().unwrap();
+        let data_path = tmpdir.path().to_str().unwrap();
+        let process = ProcessOptions {
+            mix: MixSpec::new(None, Some("2,3"), None).unwrap(),
+            ..ProcessOptions::default()
+        };
+        let out_path = decode_sample_processed("sinequadi16.wav", data_path, &process).unwrap();
+        assert!(
+            fs::metadata(&out_path).unwrap().len() > 0,
+            "MP3 file is empty"
+        );
+        fs::remove_file(out_path).ok();
+    }
+
+    #[test]
+    fn test_pick_missing_channel() {
+        let tmpdir = tempdir().unwrap();
+        let data_path = tmpdir.path().to_str().unwrap();
+        let process = ProcessOptions {
+            mix: MixSpec::new(None, Some("2"), None).unwrap(),
+            ..ProcessOptions::default()
+        };
+        let result = decode_sample_processed("untitledi16.wav", data_path, &process);
+        assert!(matches!(result, Err(WaveemapiError::InvalidOptions(_))));
+    }
+
+    /// Sample rate from the first MPEG audio frame header of an untagged MP3.
+    fn mp3_sample_rate(path: &str) -> u32 {
+        let mp3 = fs::read(path).unwrap();
+        assert_eq!(mp3[0], 0xFF, "expected an MPEG frame sync");
+        let rates = match (mp3[1] >> 3) & 0b11 {
+            0b11 => [44100, 48000, 32000],
+            0b10 => [22050, 24000, 16000],
+            _ => [11025, 12000, 8000],
+        };
+        rates[((mp3[2] >> 2) & 0b11) as usize]
+    }
+
+    #[test]
+    fn test_resample_high_rate() {
+        let tmpdir = tempdir().unwrap();
+        let data_path = tmpdir.path().to_str().unwrap();
+        let out_path = decode_sample("sine96kf32.wav", data_path).unwrap();
+        assert_eq!(mp3_sample_rate(&out_path), 48000);
+        fs::remove_file(out_path).ok();
+    }
+
+    #[test]
+    fn test_resample_nearest_rate() {
+        let tmpdir = tempdir().unwrap();
+        let data_path = tmpdir.path().to_str().unwrap();
+        let out_path = decode_sample("sine37ki16.wav", data_path).unwrap();
+        assert_eq!(mp3_sample_rate(&out_path), 32000);
+        fs::remove_file(out_path).ok();
+    }
+
+    #[test]
+    fn test_progress() {
+        let tmpdir = tempdir().unwrap();
+        let data_path = tmpdir.path().to_str().unwrap();
+        let progress = Progress::default();
+        let path = format!("{}{}", SAMPLE_PATH, "untitledi16.wav");
+        let (out_path, _) = wav_file_decode(
+            &path,
+            data_path,
+            &EncodeOptions::default(),
+            &ProcessOptions::default(),
+            &TrackTags::default(),
+            &progress,
+        )
+        .unwrap();
+        assert_eq!(progress.get(), 100);
+        fs::remove_file(out_path).ok();
+    }
+
+    #[test]
+    fn test_encode_to_writer() {
+        let tmpdir = tempdir().unwrap();
+        let data_path = tmpdir.path().to_str().unwrap();
+        let out_path = decode_sample("untitledi16.wav", data_path).unwrap();
+        let mut streamed = Vec::new();
+        wav_file_encode(
+            &format!("{}{}", SAMPLE_PATH, "untitledi16.wav"),
+            &mut streamed,
+            &EncodeOptions::default(),
+            &ProcessOptions::default(),
+            &TrackTags::default(),
+            &Progress::default(),
+        )
+        .unwrap();
+        assert_eq!(streamed, fs::read(&out_path).unwrap());
+        fs::remove_file(out_path).ok();
+    }
+
+    /// Encodes a sample from its file and read front to back, which must give
+    /// the same MP3, and returns it.
+    fn encode_both(name: &str) -> Vec<u8> {
+        let path = format!("{}{}", SAMPLE_PATH, name);
+        let mut from_file = Vec::new();
+        wav_file_encode(
+            &path,
+            &mut from_file,
+            &EncodeOptions::default(),
+            &ProcessOptions::default(),
+            &TrackTags::default(),
+            &Progress::default(),
+        )
+        .unwrap();
+        let mut from_reader = Vec::new();
+        wav_read_encode(
+            File::open(&path).unwrap(),
+            &mut from_reader,
+            &EncodeOptions::default(),
+            &ProcessOptions::default(),
+            &TrackTags::default(),
+            &Progress::default(),
+        )
+        .unwrap();
+        assert_eq!(from_reader, from_file, "{}", name);
+        from_file
+    }
+
+    #[test]
+    fn test_encode_from_reader() {
+        // File, then its channels, sample rate and frames as decoded, and the
+        // embedded title that should reach the ID3 tag.
+        let samples: &[(&str, usize, u32, u64, Option<&str>)] = &[
+            ("untitledi16.wav", 2, 48000, 501797, None),
+            ("sinei20.wav", 2, 22050, 11025, None),
+            ("untitledf32.wav", 2, 48000, 498516, None),
+            ("sinei16.aiff", 2, 44100, 4410, Some("Sine")),
+            ("sinef32.aifc", 1, 48000, 4800, None),
+            ("sinei16.flac", 2, 44100, 4410, Some("Flac Sine")),
+            ("sinei24.flac", 1, 48000, 4800, None),
+            ("sinealaw.wav", 1, 22050, 22050, None),
+            ("sinemulaw.wav", 2, 22050, 22050, None),
+            ("sineima.wav", 2, 22050, 22044, None),
+            ("sinerf64i16.wav", 2, 44100, 22050, None),
+            ("sinebw64f32.wav", 1, 44100, 22050, None),
+        ];
+        for &(name, channels, sample_rate, frames, title) in samples {
+            let path = format!("{}{}", SAMPLE_PATH, name);
+            let embedded = file_stream(&path, |stream, tags| {
+                assert_eq!(stream.channels, channels, "{}", name);
+                assert_eq!(stream.sample_rate, sample_rate, "{}", name);
+                assert_eq!(
+                    stream.total_samples,
+                    Some(frames * channels as u64),
+                    "{}",
+                    name
+                );
+                Ok(tags)
+            })
+            .unwrap();
+            assert_eq!(embedded.title.as_deref(), title, "{}", name);
+            let mp3 = encode_both(name);
+            assert!(mp3.len() > 1000, "{}", name);
+            if let Some(title) = title {
+                assert!(
+                    mp3.windows(title.len()).any(|w| w == title.as_bytes()),
+                    "{}",
+                    name
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_reader_decode_tags() {
+        let tmpdir = tempdir().unwrap();
+        let data_path = tmpdir.path().to_str().unwrap();
+        let client = TrackTags::new(Some("Client"), None, None, None, None, None, None).unwrap();
+        // Tags before the samples, after them, and from the client.
+        for (name, tags) in [
+            ("sinei16.aiff", TrackTags::default()),
+            ("infobexti16.wav", TrackTags::default()),
+            ("infobexti16.wav", client),
+        ] {
+            let from_file =
+                decode_sample_with(name, data_path, &EncodeOptions::default(), &tags).unwrap();
+            let reader = File::open(format!("{}{}", SAMPLE_PATH, name)).unwrap();
+            let (from_reader, _) = wav_reader_decode(
+                reader,
+                data_path,
+                &EncodeOptions::default(),
+                &ProcessOptions::default(),
+                &tags,
+                &Progress::default(),
+            )
+            .unwrap();
+            assert_eq!(
+                fs::read(&from_reader).unwrap(),
+                fs::read(&from_file).unwrap()
+            );
+            fs::remove_file(from_file).unwrap();
+            fs::remove_file(from_reader).unwrap();
+        }
+        assert_eq!(fs::read_dir(data_path).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_analyze_file() {
+        let file = File::open(format!("{}{}", SAMPLE_PATH, "sinerf64i16.wav")).unwrap();
+        let analysis = wav_read_analyze(file, None).unwrap();
+        assert!(analysis.integrated_lufs.is_some());
+        assert!(analysis.true_peak_dbtp >= analysis.sample_peak_dbfs);
+        let file = File::open(format!("{}{}", SAMPLE_PATH, "untitledi16.wav")).unwrap();
+        assert!(matches!(
+            wav_read_analyze(file, Some(1)),
+            Err(WaveemapiError::TooLarge(_))
+        ));
+    }
+
+    #[test]
+    fn test_normalize() {
+        let path = format!("{}{}", SAMPLE_PATH, "sinerf64i16.wav");
+        let normalized = |normalize_lufs| {
+            let mut process = ProcessOptions {
+                normalize_lufs: Some(normalize_lufs),
+                ..ProcessOptions::default()
+            };
+            process.gain_db = wav_file_normalize(&path, &process).unwrap();
+            assert!(process.gain_db.is_some());
+            file_stream(&path, |stream, _| {
+                analyze::analyze(stream.resample(None).gain(process.gain_db))
+            })
+            .unwrap()
+        };
+        let quiet = normalized(-30.0);
+        assert!((quiet.integrated_lufs.unwrap() + 30.0).abs() < 0.05);
+        // Reaching 0 LUFS takes more gain than the true peak ceiling allows.
+        let loud = normalized(0.0);
+        assert!(loud.true_peak_dbtp.unwrap() <= limiter::TRUE_PEAK_CEILING_DBTP + 0.01);
+        assert_eq!(loud.clipped_samples, 0);
+        let process = ProcessOptions::default();
+        assert_eq!(wav_file_normalize(&path, &process).unwrap(), None);
+    }
+
+    #[test]
+    fn test_waveform_file() {
+        let file = File::open(format!("{}{}", SAMPLE_PATH, "sinei24.flac")).unwrap();
+        let process = ProcessOptions {
+            sample_rate: Some(8000),
+            ..ProcessOptions::default()
+        };
+        let options = WaveformOptions::new(Some(100), None, Some(true)).unwrap();
+        let waveform = wav_read_waveform(file, &process, &options).unwrap();
+        assert_eq!(waveform.sample_rate, 8000);
+        assert_eq!(
+            waveform.length as usize * 2 * waveform.channels as usize,
+            waveform.data.len()
+        );
+        assert!(waveform.data.iter().any(|&peak| peak != 0));
+    }
+
+    #[test]
+    fn test_trim_file() {
+        let path = format!("{}{}", SAMPLE_PATH, "untitledi16.wav");
+        let spec = TrimSpec::new(Some("1"), Some("96000smp"), Some("0.5"), Some("0.5")).unwrap();
+        let (total, samples) = file_stream(&path, |stream, _| {
+            let stream = stream.trim(&spec)?;
+            let total = stream.total_samples;
+            Ok((total, stream.samples.collect::<Result<Vec<_>, _>>()?))
+        })
+        .unwrap();
+        // 48 kHz stereo.
+        assert_eq!(total, Some(96000));
+        assert_eq!(samples.len(), 96000);
+        assert_eq!(&samples[..2], &[0.0, 0.0]);
+        assert_eq!(&samples[96000 - 2..], &[0.0, 0.0]);
+        let spec = TrimSpec::new(Some("20"), None, None, None).unwrap();
+        let mut out = Vec::new();
+        let process = ProcessOptions {
+            trim: spec,
+            ..ProcessOptions::default()
+        };
+        assert!(matches!(
+            wav_file_encode(
+                &path,
+                &mut out,
+                &EncodeOptions::default(),
+                &process,
+                &TrackTags::default(),
+                &Progress::default()
+            ),
+            Err(WaveemapiError::InvalidOptions(_))
+        ));
+    }
+
+    #[test]
+    fn test_trim_silence_report() {
+        let tmpdir = tempdir().unwrap();
+        let path = tmpdir.path().join("padded.wav");
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: 8000,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::create(&path, spec).unwrap();
+        // 0.5 s of silence, 0.5 s of tone and 1 s of silence.
+        for i in 0..16000 {
+            let tone = (4000..8000).contains(&i) && i % 8 < 4;
+            writer.write_sample(if tone { 8000i16 } else { 3 }).unwrap();
+        }
+        writer.finalize().unwrap();
+        let process = ProcessOptions {
+            trim_silence: SilenceSpec::new(Some(true), None, None).unwrap(),
+            ..ProcessOptions::default()
+        };
+        let report = wav_file_encode(
+            path.to_str().unwrap(),
+            &mut Vec::new(),
+            &EncodeOptions::default(),
+            &process,
+            &TrackTags::default(),
+            &Progress::default(),
+        )
+        .unwrap();
+        // The last tone period ends in 4 quiet samples.
+        assert_eq!(report.silence_trimmed, Some((0.5, 1.0 + 4.0 / 8000.0)));
+        assert_eq!(report.gain_db, None);
+    }
+
+    fn split_sample(data_path: &str, split: SplitSpec) -> Vec<(String, Vec<u8>)> {
+        let process = ProcessOptions {
+            split: Some(split),
+            ..ProcessOptions::default()
+        };
+        let (path, _) = wav_file_split(
+            &format!("{}{}", SAMPLE_PATH, "cuesi16.wav"),
+            data_path,
+            &EncodeOptions::default(),
+            &process,
+            &TrackTags::default(),
+            &Progress::default(),
+        )
+        .unwrap();
+        assert!(path.ends_with(".zip"));
+        let mut zip = zip::ZipArchive::new(File::open(&path).unwrap()).unwrap();
+        (0..zip.len())
+            .map(|i| {
+                let mut file = zip.by_index(i).unwrap();
+                let mut mp3 = Vec::new();
+                file.read_to_end(&mut mp3).unwrap();
+                (file.name().to_string(), mp3)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_split_cue() {
+        let tmpdir = tempdir().unwrap();
+        let tracks = split_sample(tmpdir.path().to_str().unwrap(), SplitSpec::Cue);
+        let names: Vec<&str> = tracks.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["01.mp3", "02.mp3", "03.mp3"]);
+        for (n, ((_, mp3), title)) in tracks.iter().zip(["Intro", "Middle", "Outro"]).enumerate() {
+            let tag = TrackTags {
+                title: Some(title.to_string()),
+                track: Some(format!("{}/3", n + 1)),
+                ..TrackTags::default()
+            }
+            .to_id3v2();
+            assert_eq!(&mp3[..tag.len()], &tag[..]);
+            assert!(mp3.len() > tag.len());
+        }
+    }
+
+    #[test]
+    fn test_split_silence() {
+        let tmpdir = tempdir().unwrap();
+        let split = SplitSpec::new(Some("silence"), None, None)
+            .unwrap()
+            .unwrap();
+        let tracks = split_sample(tmpdir.path().to_str().unwrap(), split);
+        assert_eq!(tracks.len(), 3);
+        let tag = TrackTags {
+            title: Some("Track 2".to_string()),
+            track: Some("2/3".to_string()),
+            ..TrackTags::default()
+        }
+        .to_id3v2();
+        assert!(tracks[1].1.starts_with(&tag));
+        let split = SplitSpec::new(Some("silence"), None, Some(3.0))
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            split_sample(tmpdir.path().to_str().unwrap(), split).len(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_split_without_cues() {
+        let tmpdir = tempdir().unwrap();
+        let process = ProcessOptions {
+            split: Some(SplitSpec::Cue),
+            ..ProcessOptions::default()
+        };
+        let result = wav_file_split(
+            &format!("{}{}", SAMPLE_PATH, "sinei16.flac"),
+            tmpdir.path().to_str().unwrap(),
+            &EncodeOptions::default(),
+            &process,
+            &TrackTags::default(),
+            &Progress::default(),
+        );
+        assert!(matches!(result, Err(WaveemapiError::InvalidOptions(_))));
+        assert_eq!(fs::read_dir(tmpdir.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_concat_files() {
+        let tmpdir = tempdir().unwrap();
+        let data_path = tmpdir.path().to_str().unwrap();
+        let sample = |name: &str| format!("{}{}", SAMPLE_PATH, name);
+        let process = ProcessOptions {
+            crossfade: Some(0.1),
+            ..ProcessOptions::default()
+        };
+        // Stereo at 44.1 kHz, then mono at 22.05 kHz, both half a second.
+        let path = crate::helpers::wav_path(data_path);
+        wav_files_concat(
+            &[sample("sinerf64i16.wav"), sample("sineu8.wav")],
+            None,
+            None,
+            &path,
+            &process,
+        )
+        .unwrap();
+        let reader = WavReader::open(&path).unwrap();
+        assert_eq!(reader.spec().channels, 2);
+        assert_eq!(reader.spec().sample_rate, 44100);
+        let frames = reader.duration() as i64;
+        assert!((frames - (22050 + 22050 - 4410)).abs() < 100, "{}", frames);
+        fs::remove_file(&path).unwrap();
+
+        let intro = sample("cuesi16.wav");
+        let (tags, _) = wav_files_concat(
+            &[sample("infobexti16.wav")],
+            Some(&intro),
+            None,
+            &path,
+            &ProcessOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(tags.title.as_deref(), Some("Field Take 3"));
+        // The rate of the upload is kept.
+        assert_eq!(WavReader::open(&path).unwrap().spec().sample_rate, 44100);
+        fs::remove_file(&path).unwrap();
+
+        let result = wav_files_concat(
+            &[sample("sineu8.wav"), sample("sine51i16.wav")],
+            None,
+            None,
+            &path,
+            &process,
+        );
+        assert!(matches!(result, Err(WaveemapiError::InvalidOptions(_))));
+        assert_eq!(fs::read_dir(data_path).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_concat_assets_gapless() {
+        let tmpdir = tempdir().unwrap();
+        let sample = |name: &str| format!("{}{}", SAMPLE_PATH, name);
+        let process = ProcessOptions {
+            crossfade: Some(0.1),
+            ..ProcessOptions::default()
+        };
+        let (intro, outro) = (sample("sinerf64i16.wav"), sample("sinebw64f32.wav"));
+        let path = crate::helpers::wav_path(tmpdir.path().to_str().unwrap());
+        wav_files_concat(
+            &[sample("sinerf64i16.wav")],
+            Some(&intro),
+            Some(&outro),
+            &path,
+            &process,
+        )
+        .unwrap();
+        // The crossfade only applies between parts of the upload.
+        assert_eq!(WavReader::open(&path).unwrap().duration(), 3 * 22050);
+
+        wav_files_concat(
+            &[sample("sinerf64i16.wav"), sample("sinerf64i16.wav")],
+            Some(&intro),
+            Some(&outro),
+            &path,
+            &process,
+        )
+        .unwrap();
+        assert_eq!(WavReader::open(&path).unwrap().duration(), 4 * 22050 - 4410);
+    }
+
+    #[test]
+    fn test_concat_assets_processed() {
+        let tmpdir = tempdir().unwrap();
+        let sample = |name: &str| format!("{}{}", SAMPLE_PATH, name);
+        let (intro, outro) = (sample("sinerf64i16.wav"), sample("sinebw64f32.wav"));
+        let path = crate::helpers::wav_path(tmpdir.path().to_str().unwrap());
+        let process = ProcessOptions {
+            mix: MixSpec::Mono,
+            trim: TrimSpec::new(Some("0.1"), None, Some("0.2"), None).unwrap(),
+            normalize_lufs: Some(-16.0),
+            ..ProcessOptions::default()
+        };
+        let (_, report) = wav_files_concat(
+            &[sample("sinerf64i16.wav")],
+            Some(&intro),
+            Some(&outro),
+            &path,
+            &process,
+        )
+        .unwrap();
+        assert!(report.gain_db.is_some());
+        // Only the upload is trimmed, and the stereo intro mixed down with it.
+        let reader = WavReader::open(&path).unwrap();
+        assert_eq!(reader.spec().channels, 1);
+        assert_eq!(reader.duration(), 22050 + (22050 - 4410) + 22050);
+        let head = file_stream(&path, |stream, _| {
+            stream.samples.take(8).collect::<Result<Vec<_>, _>>()
+        })
+        .unwrap();
+        assert_close(&head, &first_samples("sinerf64i16.wav", 8, &MixSpec::Mono));
+    }
+
+    #[test]
+    fn test_max_duration() {
+        let path = format!("{}{}", SAMPLE_PATH, "untitledi16.wav");
+        let encode = |max_duration| {
+            let mut out = Vec::new();
+            let process = ProcessOptions {
+                max_duration,
+                ..ProcessOptions::default()
+            };
+            wav_file_encode(
+                &path,
+                &mut out,
+                &EncodeOptions::default(),
+                &process,
+                &TrackTags::default(),
+                &Progress::default(),
+            )
+            .map(|_| out)
+        };
+        let Err(WaveemapiError::TooLarge(_)) = encode(Some(10)) else {
+            panic!("a 10.4 second file passed a 10 second limit");
+        };
+        assert!(!encode(Some(11)).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_max_duration_unknown_length() {
+        let stream = AudioStream {
+            samples: Box::new((0..5).map(|_| Ok(0.0))),
+            channels: 1,
+            sample_rate: 2,
+            channel_mask: None,
+            total_samples: None,
+        };
+        let results: Vec<_> = stream.limit_duration(Some(2)).unwrap().samples.collect();
+        assert!(results[..4].iter().all(Result::is_ok));
+        assert!(matches!(results[4], Err(WaveemapiError::TooLarge(_))));
+    }
+
+    #[test]
+    fn test_aiff_tags_embedded() {
+        let mut out = Vec::new();
+        wav_file_encode(
+            &format!("{}{}", SAMPLE_PATH, "sinei16.aiff"),
+            &mut out,
+            &EncodeOptions::default(),
+            &ProcessOptions::default(),
+            &TrackTags::default(),
+            &Progress::default(),
+        )
+        .unwrap();
+        assert!(out.starts_with(b"ID3"));
+        assert!(out.windows(4).any(|w| w == b"Sine"));
+    }
+
+    #[test]
+    fn test_reader_not_wave() {
+        let result = wav_read_encode(
+            &b"RIFF\x00\x00\x00\x00WEBPVP8 "[..],
+            &mut Vec::new(),
+            &EncodeOptions::default(),
+            &ProcessOptions::default(),
+            &TrackTags::default(),
+            &Progress::default(),
+        );
+        assert!(matches!(result, Err(WaveemapiError::Hound(_))));
+    }
+
+    #[test]
+    fn test_failed_encode_leaves_no_file() {
+        let tmpdir = tempdir().unwrap();
+        let data_path = tmpdir.path().to_str().unwrap();
+        assert!(decode_sample("477.webp", data_path).is_err());
+        assert_eq!(fs::read_dir(data_path).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_resample_requested_rate() {
+        let tmpdir = tempdir().unwrap();
+        let data_path = tmpdir.path().to_str().unwrap();
+        let process = ProcessOptions {
+            sample_rate: Some(22050),
+            ..ProcessOptions::default()
+        };
+        let out_path = decode_sample_processed("untitledi16.wav", data_path, &process).unwrap();
+        assert_eq!(mp3_sample_rate(&out_path), 22050);
+        fs::remove_file(out_path).ok();
+    }
 }
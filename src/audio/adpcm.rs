@@ -0,0 +1,193 @@
+use std::io::Read;
+
+use crate::audio::riff::read_full;
+
+const INDEX_TABLE: [i8; 16] = [-1, -1, -1, -1, 2, 4, 6, 8, -1, -1, -1, -1, 2, 4, 6, 8];
+
+const STEP_TABLE: [i32; 89] = [
+    7, 8, 9, 10, 11, 12, 13, 14, 16, 17, 19, 21, 23, 25, 28, 31, 34, 37, 41, 45, 50, 55, 60, 66,
+    73, 80, 88, 97, 107, 118, 130, 143, 157, 173, 190, 209, 230, 253, 279, 307, 337, 371, 408, 449,
+    494, 544, 598, 658, 724, 796, 876, 963, 1060, 1166, 1282, 1411, 1552, 1707, 1878, 2066, 2272,
+    2499, 2749, 3024, 3327, 3660, 4026, 4428, 4871, 5358, 5894, 6484, 7132, 7845, 8630, 9493,
+    10442, 11487, 12635, 13899, 15289, 16818, 18500, 20350, 22385, 24623, 27086, 29794, 32767,
+];
+
+/// Frames in a full block of `block_align` bytes: the header sample plus two
+/// per data byte and channel.
+pub fn frames_per_block(block_align: u16, channels: u16) -> u64 {
+    let header = 4 * channels as u64;
+    (block_align as u64).saturating_sub(header) * 2 / channels.max(1) as u64 + 1
+}
+
+/// Reads Microsoft IMA ADPCM (`WAVE_FORMAT_IMA_ADPCM`), 4 bits per sample,
+/// decoding one block at a time into interleaved 16-bit samples.
+pub struct ImaAdpcmSamples<R> {
+    reader: R,
+    channels: usize,
+    block_align: usize,
+    remaining: u64,
+    block: Vec<i16>,
+    pos: usize,
+}
+
+impl<R: Read> ImaAdpcmSamples<R> {
+    pub fn new(reader: R, channels: u16, block_align: u16, data_len: u64) -> hound::Result<Self> {
+        let channels = channels as usize;
+        if channels == 0
+            || block_align as usize <= 4 * channels
+            || !(block_align as usize).is_multiple_of(4 * channels)
+        {
+            return Err(hound::Error::FormatError("invalid IMA ADPCM block size"));
+        }
+        Ok(ImaAdpcmSamples {
+            reader,
+            channels,
+            block_align: block_align as usize,
+            remaining: data_len,
+            block: Vec::new(),
+            pos: 0,
+        })
+    }
+
+    fn read_block(&mut self) -> hound::Result<()> {
+        let mut bytes = vec![0u8; self.block_align.min(self.remaining as usize)];
+        let read = read_full(&mut self.reader, &mut bytes)?;
+        self.remaining -= bytes.len() as u64;
+        bytes.truncate(read);
+        self.block = decode_block(&bytes, self.channels)?;
+        self.pos = 0;
+        Ok(())
+    }
+}
+
+impl<R: Read> Iterator for ImaAdpcmSamples<R> {
+    type Item = hound::Result<i16>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.pos == self.block.len() {
+            if self.remaining == 0 {
+                return None;
+            }
+            if let Err(e) = self.read_block() {
+                self.remaining = 0;
+                self.block.clear();
+                return Some(Err(e));
+            }
+        }
+        self.pos += 1;
+        Some(Ok(self.block[self.pos - 1]))
+    }
+}
+
+/// Decodes one block. Each channel starts with a 4-byte header holding the
+/// first sample and step index, followed by groups of 4 bytes (8 samples)
+/// per channel in turn, low nibble first. A short final block yields only
+/// the samples it holds.
+fn decode_block(block: &[u8], channels: usize) -> hound::Result<Vec<i16>> {
+    if block.is_empty() {
+        return Ok(Vec::new());
+    }
+    let header = 4 * channels;
+    if block.len() < header {
+        return Err(hound::Error::FormatError("truncated IMA ADPCM block"));
+    }
+    let mut decoders: Vec<Decoder> = block[..header]
+        .chunks_exact(4)
+        .map(|h| Decoder {
+            predictor: i16::from_le_bytes([h[0], h[1]]) as i32,
+            index: h[2].min(88) as usize,
+        })
+        .collect();
+    let groups = block[header..].chunks_exact(4 * channels);
+    let mut samples = vec![0i16; (1 + groups.len() * 8) * channels];
+    for (c, decoder) in decoders.iter().enumerate() {
+        samples[c] = decoder.predictor as i16;
+    }
+    for (g, group) in groups.enumerate() {
+        for (c, decoder) in decoders.iter_mut().enumerate() {
+            for (k, &byte) in group[4 * c..4 * c + 4].iter().enumerate() {
+                for (h, nibble) in [byte & 0x0F, byte >> 4].into_iter().enumerate() {
+                    let frame = 1 + g * 8 + k * 2 + h;
+                    samples[frame * channels + c] = decoder.decode(nibble);
+                }
+            }
+        }
+    }
+    Ok(samples)
+}
+
+struct Decoder {
+    predictor: i32,
+    index: usize,
+}
+
+impl Decoder {
+    fn decode(&mut self, nibble: u8) -> i16 {
+        let step = STEP_TABLE[self.index];
+        let mut diff = step >> 3;
+        if nibble & 1 != 0 {
+            diff += step >> 2;
+        }
+        if nibble & 2 != 0 {
+            diff += step >> 1;
+        }
+        if nibble & 4 != 0 {
+            diff += step;
+        }
+        if nibble & 8 != 0 {
+            diff = -diff;
+        }
+        self.predictor = (self.predictor + diff).clamp(i16::MIN as i32, i16::MAX as i32);
+        self.index =
+            (self.index as i32 + INDEX_TABLE[nibble as usize] as i32).clamp(0, 88) as usize;
+        self.predictor as i16
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frames_per_block() {
+        assert_eq!(frames_per_block(256, 1), 505);
+        assert_eq!(frames_per_block(1024, 2), 1017);
+    }
+
+    #[test]
+    fn test_decode_block() {
+        // Mono: header sample 100 at step index 0, then nibbles 7, 0, 8, 0, ...
+        let block = [100, 0, 0, 0, 0x07, 0x08, 0x0F, 0x00];
+        let samples = decode_block(&block, 1).unwrap();
+        assert_eq!(samples.len(), 9);
+        // 7 sets every magnitude bit: 7>>3 + 7>>2 + 7>>1 + 7 = 11, then the
+        // step index moves up 8 places to a step of 16.
+        assert_eq!(&samples[..3], &[100, 111, 113]);
+    }
+
+    #[test]
+    fn test_stereo_interleave() {
+        let mut block = vec![0u8; 16];
+        block[0..2].copy_from_slice(&1000i16.to_le_bytes());
+        block[4..6].copy_from_slice(&(-1000i16).to_le_bytes());
+        // The second channel's first group starts at byte 12.
+        block[12] = 0x07;
+        let samples = decode_block(&block, 2).unwrap();
+        assert_eq!(samples.len(), 18);
+        assert_eq!(&samples[..4], &[1000, -1000, 1000, -989]);
+    }
+
+    #[test]
+    fn test_truncated_block() {
+        assert!(decode_block(&[0, 0], 1).is_err());
+        let mut samples = ImaAdpcmSamples::new(&[0u8; 6][..], 1, 8, 6).unwrap();
+        // The partial block only holds its header sample.
+        assert!(matches!(samples.next(), Some(Ok(0))));
+        assert!(samples.next().is_none());
+    }
+
+    #[test]
+    fn test_invalid_block_align() {
+        assert!(ImaAdpcmSamples::new(&[0u8; 0][..], 2, 6, 0).is_err());
+    }
+}
@@ -0,0 +1,257 @@
+use std::io::{self, Read};
+
+use crate::audio::pcm::ByteOrder;
+use crate::audio::riff::{read_full, text};
+use crate::id3::TrackTags;
+
+/// Text chunks larger than this are skipped rather than read into memory.
+const MAX_TEXT_CHUNK: u64 = 64 * 1024;
+/// Channels, frames, bits, rate and the AIFF-C compression type. The
+/// compression name after them is skipped.
+const COMM_FIELDS_LEN: usize = 22;
+
+/// True for the first bytes of an AIFF or AIFF-C file.
+pub fn is_aiff(magic: &[u8]) -> bool {
+    magic.len() >= 12 && magic.starts_with(b"FORM") && matches!(&magic[8..12], b"AIFF" | b"AIFC")
+}
+
+/// How the samples in `SSND` are stored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Int(ByteOrder),
+    Float32,
+}
+
+/// The `COMM` chunk, plus what follows from it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AiffHeader {
+    pub channels: u16,
+    pub frames: u32,
+    pub bits_per_sample: u16,
+    pub sample_rate: u32,
+    pub encoding: Encoding,
+    /// Bytes of sample data following the header.
+    pub data_len: u64,
+    pub name: Option<String>,
+    pub author: Option<String>,
+    pub annotation: Option<String>,
+}
+
+impl AiffHeader {
+    pub fn container_bytes(&self) -> u16 {
+        self.bits_per_sample.div_ceil(8)
+    }
+
+    /// `NAME`, `AUTH` and `ANNO` as ID3 fields.
+    pub fn to_tags(&self) -> TrackTags {
+        TrackTags {
+            title: self.name.clone(),
+            artist: self.author.clone(),
+            comment: self.annotation.clone(),
+            ..TrackTags::default()
+        }
+    }
+}
+
+/// Reads chunks up to the start of the sample data in `SSND`, leaving
+/// `reader` positioned on the first sample. Chunks after `SSND` are not seen.
+pub fn read_header<R: Read>(reader: &mut R) -> hound::Result<AiffHeader> {
+    let mut form = [0u8; 12];
+    if read_full(reader, &mut form)? < form.len() || !is_aiff(&form) {
+        return Err(hound::Error::FormatError("no AIFF tag found"));
+    }
+    let aifc = form.ends_with(b"AIFC");
+    let mut comm = None;
+    let (mut name, mut author, mut annotation) = (None, None, None);
+    let mut chunk_header = [0u8; 8];
+    loop {
+        if read_full(reader, &mut chunk_header)? < chunk_header.len() {
+            return Err(hound::Error::FormatError("missing SSND chunk"));
+        }
+        let id = &chunk_header[..4];
+        let len = u32::from_be_bytes(chunk_header[4..].try_into().unwrap()) as u64;
+        let padded = len + (len & 1);
+        match id {
+            b"COMM" if len >= 18 => {
+                let mut body = [0u8; COMM_FIELDS_LEN];
+                let body_len = len.min(COMM_FIELDS_LEN as u64) as usize;
+                reader.read_exact(&mut body[..body_len])?;
+                comm = Some(parse_comm(&body[..body_len], aifc)?);
+                skip(reader, padded - body_len as u64)?;
+            }
+            b"NAME" | b"AUTH" | b"ANNO" if len <= MAX_TEXT_CHUNK => {
+                let mut body = vec![0u8; len as usize];
+                reader.read_exact(&mut body)?;
+                let value = text(&body);
+                match id {
+                    b"NAME" => name = name.or(value),
+                    b"AUTH" => author = author.or(value),
+                    _ => annotation = annotation.or(value),
+                }
+                skip(reader, padded - len)?;
+            }
+            b"SSND" if len >= 8 => {
+                let mut offsets = [0u8; 8];
+                reader.read_exact(&mut offsets)?;
+                let offset = u32::from_be_bytes(offsets[..4].try_into().unwrap()) as u64;
+                skip(reader, offset)?;
+                let (channels, frames, bits_per_sample, sample_rate, encoding) =
+                    comm.ok_or(hound::Error::FormatError("SSND chunk before COMM"))?;
+                let mut header = AiffHeader {
+                    channels,
+                    frames,
+                    bits_per_sample,
+                    sample_rate,
+                    encoding,
+                    data_len: 0,
+                    name,
+                    author,
+                    annotation,
+                };
+                let frame_bytes = header.container_bytes() as u64 * channels as u64;
+                header.data_len = (len - 8)
+                    .saturating_sub(offset)
+                    .min(frames as u64 * frame_bytes);
+                return Ok(header);
+            }
+            _ => skip(reader, padded)?,
+        }
+    }
+}
+
+type Comm = (u16, u32, u16, u32, Encoding);
+
+fn parse_comm(body: &[u8], aifc: bool) -> hound::Result<Comm> {
+    let channels = u16::from_be_bytes([body[0], body[1]]);
+    let frames = u32::from_be_bytes(body[2..6].try_into().unwrap());
+    let bits_per_sample = u16::from_be_bytes([body[6], body[7]]);
+    let sample_rate = extended_to_rate(body[8..18].try_into().unwrap())
+        .ok_or(hound::Error::FormatError("invalid AIFF sample rate"))?;
+    let compression = if aifc && body.len() >= 22 {
+        &body[18..22]
+    } else {
+        &b"NONE"[..]
+    };
+    let encoding = match compression {
+        b"NONE" | b"twos" => Encoding::Int(ByteOrder::Big),
+        b"sowt" => Encoding::Int(ByteOrder::Little),
+        b"fl32" | b"FL32" if bits_per_sample == 32 => Encoding::Float32,
+        _ => return Err(hound::Error::Unsupported),
+    };
+    if channels == 0 || !(1..=32).contains(&bits_per_sample) {
+        return Err(hound::Error::FormatError("invalid AIFF COMM chunk"));
+    }
+    Ok((channels, frames, bits_per_sample, sample_rate, encoding))
+}
+
+/// The sample rate is an 80-bit IEEE 754 extended float.
+fn extended_to_rate(bytes: [u8; 10]) -> Option<u32> {
+    let exponent = u16::from_be_bytes([bytes[0], bytes[1]]);
+    let mantissa = u64::from_be_bytes(bytes[2..].try_into().unwrap());
+    if exponent & 0x8000 != 0 || mantissa == 0 {
+        return None;
+    }
+    let rate = mantissa as f64 * 2f64.powi(exponent as i32 - 16383 - 63);
+    (1.0..=u32::MAX as f64)
+        .contains(&rate)
+        .then(|| rate.round() as u32)
+}
+
+fn skip<R: Read>(reader: &mut R, len: u64) -> io::Result<()> {
+    let skipped = io::copy(&mut reader.take(len), &mut io::sink())?;
+    if skipped < len {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::SAMPLE_PATH;
+    use std::fs::File;
+    use std::io::BufReader;
+
+    fn header_of(name: &str) -> AiffHeader {
+        let file = File::open(format!("{}{}", SAMPLE_PATH, name)).unwrap();
+        read_header(&mut BufReader::new(file)).unwrap()
+    }
+
+    #[test]
+    fn test_is_aiff() {
+        assert!(is_aiff(b"FORM\x00\x00\x00\x00AIFF"));
+        assert!(is_aiff(b"FORM\x00\x00\x00\x00AIFC"));
+        assert!(!is_aiff(b"RIFF\x00\x00\x00\x00WAVE"));
+        assert!(!is_aiff(b"FORM"));
+    }
+
+    #[test]
+    fn test_extended_to_rate() {
+        // 44100 and 48000 as written by common encoders.
+        assert_eq!(
+            extended_to_rate([0x40, 0x0E, 0xAC, 0x44, 0, 0, 0, 0, 0, 0]),
+            Some(44100)
+        );
+        assert_eq!(
+            extended_to_rate([0x40, 0x0E, 0xBB, 0x80, 0, 0, 0, 0, 0, 0]),
+            Some(48000)
+        );
+        assert_eq!(extended_to_rate([0; 10]), None);
+    }
+
+    #[test]
+    fn test_read_aiff_header() {
+        let header = header_of("sinei16.aiff");
+        assert_eq!(header.channels, 2);
+        assert_eq!(header.bits_per_sample, 16);
+        assert_eq!(header.sample_rate, 44100);
+        assert_eq!(header.encoding, Encoding::Int(ByteOrder::Big));
+        assert_eq!(header.data_len, header.frames as u64 * 4);
+        assert_eq!(header.name.as_deref(), Some("Sine"));
+        assert_eq!(header.to_tags().artist.as_deref(), Some("waveemapi"));
+    }
+
+    #[test]
+    fn test_read_aifc_header() {
+        let header = header_of("sinef32.aifc");
+        assert_eq!(header.channels, 1);
+        assert_eq!(header.sample_rate, 48000);
+        assert_eq!(header.encoding, Encoding::Float32);
+        assert_eq!(header.data_len, header.frames as u64 * 4);
+    }
+
+    #[test]
+    fn test_not_aiff() {
+        let file = File::open(format!("{}{}", SAMPLE_PATH, "untitledi16.wav")).unwrap();
+        assert!(matches!(
+            read_header(&mut BufReader::new(file)),
+            Err(hound::Error::FormatError(_))
+        ));
+    }
+
+    #[test]
+    fn test_unsupported_compression() {
+        let mut comm = vec![
+            0, 1, 0, 0, 0, 1, 0, 16, 0x40, 0x0E, 0xAC, 0x44, 0, 0, 0, 0, 0, 0,
+        ];
+        comm.extend_from_slice(b"ima4");
+        assert!(matches!(
+            parse_comm(&comm, true),
+            Err(hound::Error::Unsupported)
+        ));
+        assert!(parse_comm(&comm, false).is_ok());
+    }
+
+    #[test]
+    fn test_oversized_comm() {
+        // A COMM chunk claiming 4 GB is not read into memory.
+        let mut aiff = b"FORM\x00\x00\x00\x00AIFCCOMM\xff\xff\xff\xfe".to_vec();
+        aiff.extend_from_slice(&[0, 1, 0, 0, 0, 1, 0, 16, 0x40, 0x0E, 0xAC, 0x44]);
+        aiff.extend_from_slice(&[0, 0, 0, 0, 0, 0]);
+        aiff.extend_from_slice(b"NONE\x0enot compressed");
+        assert!(matches!(
+            read_header(&mut aiff.as_slice()),
+            Err(hound::Error::IoError(_))
+        ));
+    }
+}
@@ -0,0 +1,437 @@
+use std::f64::consts::PI;
+
+use serde::Serialize;
+
+use crate::audio::{AudioStream, mix};
+use crate::error::WaveemapiError;
+
+/// Samples at or beyond 16-bit full scale count as clipped.
+const CLIP_LEVEL: f32 = 32767.0 / 32768.0;
+/// True peak is measured on a 4x oversampled signal (ITU-R BS.1770-4 annex 2).
+const OVERSAMPLING: usize = 4;
+/// Taps per phase of the interpolation filter.
+const TAPS: usize = 12;
+/// Gating blocks are 400 ms and short-term windows 3 s, both moving in
+/// 100 ms steps, so both are built from 100 ms sub-blocks.
+const SUB_BLOCKS_PER_BLOCK: usize = 4;
+const SUB_BLOCKS_PER_SHORT_TERM: usize = 30;
+const ABSOLUTE_GATE_LUFS: f64 = -70.0;
+const INTEGRATED_RELATIVE_GATE_LU: f64 = -10.0;
+const LRA_RELATIVE_GATE_LU: f64 = -20.0;
+
+/// Levels of a whole file, as reported by `/api/analyze`. Levels of silence
+/// are `None`, since there is no decibel value for it.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Analysis {
+    pub duration_seconds: f64,
+    pub sample_peak_dbfs: Option<f64>,
+    pub true_peak_dbtp: Option<f64>,
+    /// EBU R128 integrated loudness.
+    pub integrated_lufs: Option<f64>,
+    /// EBU Tech 3342 loudness range.
+    pub loudness_range_lu: Option<f64>,
+    pub clipped_samples: u64,
+    pub channels: Vec<ChannelAnalysis>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ChannelAnalysis {
+    pub sample_peak_dbfs: Option<f64>,
+    pub true_peak_dbtp: Option<f64>,
+    pub rms_dbfs: Option<f64>,
+    /// Mean sample value, relative to full scale.
+    pub dc_offset: f64,
+    pub clipped_samples: u64,
+}
+
+/// BS.1770 integrated loudness of `stream`, `None` for silence or input
+/// shorter than one gating block.
+pub fn integrated_loudness(stream: AudioStream<'_>) -> Result<Option<f64>, WaveemapiError> {
+    let channels = stream.channels;
+    if channels == 0 || stream.sample_rate == 0 {
+        return Err(WaveemapiError::Hound(hound::Error::Unsupported));
+    }
+    let mut loudness = LoudnessMeter::new(channels, stream.sample_rate, stream.channel_mask);
+    for (index, sample) in stream.samples.enumerate() {
+        loudness.add(index % channels, sample?);
+    }
+    Ok(loudness.integrated())
+}
+
+/// Runs every sample of `stream` through the meters.
+pub fn analyze(stream: AudioStream<'_>) -> Result<Analysis, WaveemapiError> {
+    let channels = stream.channels;
+    if channels == 0 || stream.sample_rate == 0 {
+        return Err(WaveemapiError::Hound(hound::Error::Unsupported));
+    }
+    let mut meters: Vec<ChannelMeter> = (0..channels).map(|_| ChannelMeter::new()).collect();
+    let mut loudness = LoudnessMeter::new(channels, stream.sample_rate, stream.channel_mask);
+    let mut samples = 0u64;
+    for sample in stream.samples {
+        let sample = sample?;
+        let channel = (samples % channels as u64) as usize;
+        meters[channel].add(sample);
+        loudness.add(channel, sample);
+        samples += 1;
+    }
+    let channels: Vec<ChannelAnalysis> = meters.iter().map(ChannelMeter::finish).collect();
+    let max_peak =
+        |peak: fn(&ChannelMeter) -> f32| meters.iter().map(peak).fold(0.0f32, f32::max) as f64;
+    Ok(Analysis {
+        duration_seconds: (samples / channels.len() as u64) as f64 / stream.sample_rate as f64,
+        sample_peak_dbfs: decibels(max_peak(|m| m.peak)),
+        true_peak_dbtp: decibels(max_peak(|m| m.true_peak.max(m.peak))),
+        integrated_lufs: loudness.integrated(),
+        loudness_range_lu: loudness.range(),
+        clipped_samples: channels.iter().map(|c| c.clipped_samples).sum(),
+        channels,
+    })
+}
+
+fn decibels(level: f64) -> Option<f64> {
+    (level > 0.0).then(|| 20.0 * level.log10())
+}
+
+struct ChannelMeter {
+    count: u64,
+    sum: f64,
+    sum_squares: f64,
+    peak: f32,
+    true_peak: f32,
+    clipped: u64,
+    interpolation: TruePeak,
+}
+
+impl ChannelMeter {
+    fn new() -> Self {
+        ChannelMeter {
+            count: 0,
+            sum: 0.0,
+            sum_squares: 0.0,
+            peak: 0.0,
+            true_peak: 0.0,
+            clipped: 0,
+            interpolation: TruePeak::new(),
+        }
+    }
+
+    fn add(&mut self, sample: f32) {
+        self.count += 1;
+        self.sum += sample as f64;
+        self.sum_squares += sample as f64 * sample as f64;
+        self.peak = self.peak.max(sample.abs());
+        if sample.abs() >= CLIP_LEVEL {
+            self.clipped += 1;
+        }
+        self.true_peak = self.true_peak.max(self.interpolation.push(sample));
+    }
+
+    fn finish(&self) -> ChannelAnalysis {
+        let count = self.count.max(1) as f64;
+        ChannelAnalysis {
+            sample_peak_dbfs: decibels(self.peak as f64),
+            // The last samples never reach the middle of the interpolation filter.
+            true_peak_dbtp: decibels(self.true_peak.max(self.peak) as f64),
+            rms_dbfs: decibels((self.sum_squares / count).sqrt()),
+            dc_offset: self.sum / count,
+            clipped_samples: self.clipped,
+        }
+    }
+}
+
+/// True peak estimate for one channel from 4x oversampled values.
+pub(super) struct TruePeak {
+    filter: [[f32; TAPS]; OVERSAMPLING],
+    /// The last `TAPS` samples, oldest first.
+    history: [f32; TAPS],
+}
+
+impl TruePeak {
+    /// Samples between a sample being pushed and its peak being reported.
+    pub(super) const DELAY: usize = TAPS / 2;
+
+    pub(super) fn new() -> Self {
+        TruePeak {
+            filter: interpolation_filter(),
+            history: [0.0; TAPS],
+        }
+    }
+
+    /// Adds a sample and returns the peak from the sample `DELAY` back up to
+    /// the one after it, that one excluded.
+    pub(super) fn push(&mut self, sample: f32) -> f32 {
+        self.history.rotate_left(1);
+        self.history[TAPS - 1] = sample;
+        self.filter.iter().fold(0.0, |peak, phase| {
+            let value: f32 = phase.iter().zip(&self.history).map(|(c, s)| c * s).sum();
+            peak.max(value.abs())
+        })
+    }
+}
+
+/// Hann windowed sinc coefficients for each oversampling phase, placing the
+/// interpolated points between the middle two samples of the history.
+fn interpolation_filter() -> [[f32; TAPS]; OVERSAMPLING] {
+    let half_width = (TAPS / 2) as f64;
+    let mut phases = [[0.0; TAPS]; OVERSAMPLING];
+    for (phase, coefficients) in phases.iter_mut().enumerate() {
+        let mut taps = [0.0f64; TAPS];
+        for (k, tap) in taps.iter_mut().enumerate() {
+            let d = k as f64 - (half_width - 1.0) - phase as f64 / OVERSAMPLING as f64;
+            let sinc = if d == 0.0 {
+                1.0
+            } else {
+                (PI * d).sin() / (PI * d)
+            };
+            *tap = sinc * 0.5 * (1.0 + (PI * d / half_width).cos());
+        }
+        let gain: f64 = taps.iter().sum();
+        for (coefficient, tap) in coefficients.iter_mut().zip(taps) {
+            *coefficient = (tap / gain) as f32;
+        }
+    }
+    phases
+}
+
+/// ITU-R BS.1770 loudness: K-weighted mean square energy, summed over the
+/// channels with their weights, per 100 ms sub-block.
+struct LoudnessMeter {
+    filters: Vec<[Biquad; 2]>,
+    weights: Vec<f64>,
+    sub_block_frames: u64,
+    frames: u64,
+    energy: f64,
+    sub_blocks: Vec<f64>,
+}
+
+impl LoudnessMeter {
+    fn new(channels: usize, sample_rate: u32, channel_mask: Option<u32>) -> Self {
+        LoudnessMeter {
+            filters: (0..channels).map(|_| k_weighting(sample_rate)).collect(),
+            weights: mix::loudness_weights(channels, channel_mask),
+            sub_block_frames: (sample_rate as u64 / 10).max(1),
+            frames: 0,
+            energy: 0.0,
+            sub_blocks: Vec::new(),
+        }
+    }
+
+    fn add(&mut self, channel: usize, sample: f32) {
+        let [shelf, high_pass] = &mut self.filters[channel];
+        let weighted = high_pass.process(shelf.process(sample as f64));
+        self.energy += self.weights[channel] * weighted * weighted;
+        if channel + 1 < self.filters.len() {
+            return;
+        }
+        self.frames += 1;
+        if self.frames == self.sub_block_frames {
+            self.sub_blocks.push(self.energy / self.frames as f64);
+            self.frames = 0;
+            self.energy = 0.0;
+        }
+    }
+
+    fn windows(&self, sub_blocks: usize) -> Vec<f64> {
+        self.sub_blocks
+            .windows(sub_blocks)
+            .map(|window| window.iter().sum::<f64>() / sub_blocks as f64)
+            .collect()
+    }
+
+    fn integrated(&self) -> Option<f64> {
+        let gated = gate(
+            &self.windows(SUB_BLOCKS_PER_BLOCK),
+            INTEGRATED_RELATIVE_GATE_LU,
+        );
+        (!gated.is_empty()).then(|| lufs(mean(&gated)))
+    }
+
+    /// The spread between the 10th and 95th percentile of the gated
+    /// short-term loudness.
+    fn range(&self) -> Option<f64> {
+        let mut loudness: Vec<f64> = gate(
+            &self.windows(SUB_BLOCKS_PER_SHORT_TERM),
+            LRA_RELATIVE_GATE_LU,
+        )
+        .into_iter()
+        .map(lufs)
+        .collect();
+        if loudness.is_empty() {
+            return None;
+        }
+        loudness.sort_by(f64::total_cmp);
+        let percentile = |p: f64| loudness[((loudness.len() - 1) as f64 * p).round() as usize];
+        Some(percentile(0.95) - percentile(0.10))
+    }
+}
+
+fn lufs(energy: f64) -> f64 {
+    -0.691 + 10.0 * energy.log10()
+}
+
+fn mean(energies: &[f64]) -> f64 {
+    energies.iter().sum::<f64>() / energies.len() as f64
+}
+
+/// Energies above the absolute gate and within `relative_gate` of the mean
+/// of those.
+fn gate(energies: &[f64], relative_gate: f64) -> Vec<f64> {
+    let audible: Vec<f64> = energies
+        .iter()
+        .copied()
+        .filter(|&energy| lufs(energy) > ABSOLUTE_GATE_LUFS)
+        .collect();
+    if audible.is_empty() {
+        return audible;
+    }
+    let threshold = lufs(mean(&audible)) + relative_gate;
+    audible
+        .into_iter()
+        .filter(|&energy| lufs(energy) > threshold)
+        .collect()
+}
+
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    state: [f64; 2],
+}
+
+impl Biquad {
+    fn process(&mut self, x: f64) -> f64 {
+        let y = self.b[0] * x + self.state[0];
+        self.state[0] = self.b[1] * x - self.a[0] * y + self.state[1];
+        self.state[1] = self.b[2] * x - self.a[1] * y;
+        y
+    }
+}
+
+/// The K-weighting pre-filter and RLB high pass, designed for `sample_rate`
+/// from the analog prototypes of the 48 kHz coefficients in BS.1770.
+fn k_weighting(sample_rate: u32) -> [Biquad; 2] {
+    let fs = sample_rate as f64;
+
+    let (f0, gain_db, q) = (1681.974450955533, 3.999843853973347, 0.7071752369554196);
+    let k = (PI * f0 / fs).tan();
+    let vh = 10f64.powf(gain_db / 20.0);
+    let vb = vh.powf(0.4996667741545416);
+    let a0 = 1.0 + k / q + k * k;
+    let shelf = Biquad {
+        b: [
+            (vh + vb * k / q + k * k) / a0,
+            2.0 * (k * k - vh) / a0,
+            (vh - vb * k / q + k * k) / a0,
+        ],
+        a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        state: [0.0; 2],
+    };
+
+    let (f0, q) = (38.13547087602444, 0.5003270373238773);
+    let k = (PI * f0 / fs).tan();
+    let a0 = 1.0 + k / q + k * k;
+    let high_pass = Biquad {
+        b: [1.0, -2.0, 1.0],
+        a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        state: [0.0; 2],
+    };
+    [shelf, high_pass]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn analyze_samples(samples: Vec<f32>, channels: usize, sample_rate: u32) -> Analysis {
+        analyze(AudioStream {
+            samples: Box::new(samples.into_iter().map(Ok)),
+            channels,
+            sample_rate,
+            channel_mask: None,
+            total_samples: None,
+        })
+        .unwrap()
+    }
+
+    fn sine(frequency: f64, amplitude: f64, seconds: f64, sample_rate: u32) -> Vec<f32> {
+        let frames = (seconds * sample_rate as f64) as usize;
+        (0..frames)
+            .map(|n| {
+                (amplitude * (2.0 * PI * frequency * n as f64 / sample_rate as f64).sin()) as f32
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_k_weighting_48k() {
+        // The coefficients given in BS.1770 for 48 kHz.
+        let [shelf, high_pass] = k_weighting(48000);
+        let close = |a: f64, b: f64| (a - b).abs() < 1e-8;
+        assert!(close(shelf.b[0], 1.53512485958697));
+        assert!(close(shelf.b[1], -2.69169618940638));
+        assert!(close(shelf.b[2], 1.19839281085285));
+        assert!(close(shelf.a[0], -1.69065929318241));
+        assert!(close(shelf.a[1], 0.73248077421585));
+        assert!(close(high_pass.a[0], -1.99004745483398));
+        assert!(close(high_pass.a[1], 0.99007225036621));
+    }
+
+    #[test]
+    fn test_sine_levels() {
+        // A 997 Hz sine at -20 dBFS reads -23 LUFS (BS.1770 calibration).
+        let analysis = analyze_samples(sine(997.0, 0.1, 5.0, 48000), 1, 48000);
+        let integrated = analysis.integrated_lufs.unwrap();
+        assert!((integrated + 23.01).abs() < 0.05, "{}", integrated);
+        assert!(analysis.loudness_range_lu.unwrap() < 0.1);
+        assert!((analysis.sample_peak_dbfs.unwrap() + 20.0).abs() < 0.01);
+        let channel = &analysis.channels[0];
+        assert!((channel.rms_dbfs.unwrap() + 23.01).abs() < 0.01);
+        assert!(channel.dc_offset.abs() < 1e-4);
+        assert_eq!(analysis.clipped_samples, 0);
+        assert_eq!(analysis.duration_seconds, 5.0);
+    }
+
+    #[test]
+    fn test_true_peak() {
+        // At a quarter of the sample rate with a 45 degree phase, every sample
+        // lands at 0.707 while the waveform itself peaks at 1.0.
+        let samples: Vec<f32> = (0..4800)
+            .map(|n| (PI / 2.0 * n as f64 + PI / 4.0).sin() as f32 * 0.5)
+            .collect();
+        let analysis = analyze_samples(samples, 1, 48000);
+        let sample_peak = analysis.sample_peak_dbfs.unwrap();
+        let true_peak = analysis.true_peak_dbtp.unwrap();
+        assert!((sample_peak + 9.03).abs() < 0.01, "{}", sample_peak);
+        assert!((true_peak + 6.02).abs() < 0.3, "{}", true_peak);
+    }
+
+    #[test]
+    fn test_loudness_range() {
+        let mut samples = sine(997.0, 0.1, 10.0, 48000);
+        samples.extend(sine(997.0, 0.01 * 10f64.sqrt(), 10.0, 48000));
+        let range = analyze_samples(samples, 1, 48000)
+            .loudness_range_lu
+            .unwrap();
+        assert!((8.0..=10.5).contains(&range), "{}", range);
+    }
+
+    #[test]
+    fn test_clipping_and_dc() {
+        let samples = vec![1.0, 0.5, -1.0, 0.5, 0.25, 0.5, 0.25, 0.5];
+        let analysis = analyze_samples(samples, 2, 48000);
+        assert_eq!(analysis.clipped_samples, 2);
+        assert_eq!(analysis.channels[0].clipped_samples, 2);
+        assert_eq!(analysis.channels[1].dc_offset, 0.5);
+        assert_eq!(analysis.channels[1].sample_peak_dbfs, decibels(0.5));
+        // Too short for a single gating block.
+        assert_eq!(analysis.integrated_lufs, None);
+    }
+
+    #[test]
+    fn test_silence() {
+        let analysis = analyze_samples(vec![0.0; 96000], 2, 48000);
+        assert_eq!(analysis.sample_peak_dbfs, None);
+        assert_eq!(analysis.channels[0].rms_dbfs, None);
+        assert_eq!(analysis.integrated_lufs, None);
+        assert_eq!(analysis.loudness_range_lu, None);
+    }
+}
@@ -0,0 +1,142 @@
+use std::collections::VecDeque;
+
+use crate::error::WaveemapiError;
+
+/// Validates the `crossfade` option, in seconds.
+pub fn check_crossfade(crossfade: Option<f64>) -> Result<Option<f64>, WaveemapiError> {
+    match crossfade {
+        Some(seconds) if !seconds.is_finite() || seconds < 0.0 => Err(
+            WaveemapiError::InvalidOptions("crossfade must be zero or more seconds".to_string()),
+        ),
+        _ => Ok(crossfade),
+    }
+}
+
+/// Plays parts of the same layout one after the other, overlapping each end
+/// with the start of the next part for up to `crossfade` frames with linear
+/// fades. A part shorter than that is faded as a whole.
+pub struct Concat<I> {
+    parts: VecDeque<I>,
+    current: Option<I>,
+    channels: usize,
+    /// Crossfade length in samples.
+    crossfade: usize,
+    /// Samples at the start of the current part, already mixed with the end
+    /// of the previous one.
+    head: VecDeque<f32>,
+    /// End of what was read, held back for the next crossfade.
+    tail: VecDeque<f32>,
+    out: VecDeque<f32>,
+    done: bool,
+}
+
+impl<I> Concat<I> {
+    pub fn new(parts: Vec<I>, channels: usize, crossfade_frames: u64) -> Self {
+        let mut parts = VecDeque::from(parts);
+        Concat {
+            current: parts.pop_front(),
+            parts,
+            channels,
+            crossfade: crossfade_frames as usize * channels,
+            head: VecDeque::new(),
+            tail: VecDeque::new(),
+            out: VecDeque::new(),
+            done: false,
+        }
+    }
+}
+
+impl<I: Iterator<Item = Result<f32, WaveemapiError>>> Concat<I> {
+    fn step(&mut self) -> Result<(), WaveemapiError> {
+        let sample = match self.head.pop_front() {
+            Some(sample) => Some(sample),
+            None => self.current.as_mut().and_then(Iterator::next).transpose()?,
+        };
+        if let Some(sample) = sample {
+            self.tail.push_back(sample);
+            if self.tail.len() > self.crossfade {
+                self.out.extend(self.tail.pop_front());
+            }
+            return Ok(());
+        }
+        let Some(mut next) = self.parts.pop_front() else {
+            self.out.extend(self.tail.drain(..));
+            self.done = true;
+            return Ok(());
+        };
+        let frames = (self.tail.len() / self.channels.max(1)) as f32;
+        for (i, sample) in self.tail.drain(..).enumerate() {
+            let t = ((i / self.channels) as f32 + 0.5) / frames;
+            let incoming = next.next().transpose()?.unwrap_or(0.0);
+            self.head.push_back(sample * (1.0 - t) + incoming * t);
+        }
+        self.current = Some(next);
+        Ok(())
+    }
+}
+
+impl<I: Iterator<Item = Result<f32, WaveemapiError>>> Iterator for Concat<I> {
+    type Item = Result<f32, WaveemapiError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.out.is_empty() {
+            if self.done {
+                return None;
+            }
+            if let Err(e) = self.step() {
+                self.done = true;
+                return Some(Err(e));
+            }
+        }
+        self.out.pop_front().map(Ok)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn concat(parts: &[&[f32]], channels: usize, crossfade_frames: u64) -> Vec<f32> {
+        let parts = parts
+            .iter()
+            .map(|part| part.iter().map(|&s| Ok(s)))
+            .collect();
+        Concat::new(parts, channels, crossfade_frames)
+            .collect::<Result<_, _>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_check_crossfade() {
+        assert_eq!(check_crossfade(None).unwrap(), None);
+        assert_eq!(check_crossfade(Some(0.5)).unwrap(), Some(0.5));
+        assert!(check_crossfade(Some(-1.0)).is_err());
+        assert!(check_crossfade(Some(f64::NAN)).is_err());
+    }
+
+    #[test]
+    fn test_join() {
+        assert_eq!(
+            concat(&[&[1.0, 2.0], &[], &[3.0]], 1, 0),
+            vec![1.0, 2.0, 3.0]
+        );
+        assert!(concat(&[], 2, 4).is_empty());
+    }
+
+    #[test]
+    fn test_crossfade() {
+        let joined = concat(&[&[1.0; 4], &[0.0; 4]], 1, 2);
+        assert_eq!(joined, vec![1.0, 1.0, 0.75, 0.25, 0.0, 0.0]);
+        // Stereo frames fade together.
+        let joined = concat(&[&[1.0, 1.0, 1.0, 1.0], &[0.0, 0.0]], 2, 2);
+        assert_eq!(joined, vec![0.75, 0.75, 0.25, 0.25]);
+    }
+
+    #[test]
+    fn test_short_parts() {
+        // The middle part is shorter than the crossfade and fades straight
+        // into the last one.
+        let joined = concat(&[&[1.0; 4], &[0.0], &[1.0; 4]], 1, 2);
+        assert_eq!(joined, vec![1.0, 1.0, 0.8125, 0.8125, 1.0, 1.0]);
+    }
+}
@@ -0,0 +1,111 @@
+use crate::audio::riff::year_of;
+use crate::id3::{TrackTags, is_track};
+
+/// True for the first bytes of a FLAC stream.
+pub fn is_flac(magic: &[u8]) -> bool {
+    magic.starts_with(b"fLaC")
+}
+
+/// Claxon's errors mirror hound's, so FLAC problems are reported like any
+/// other invalid input.
+pub fn to_hound(error: claxon::Error) -> hound::Error {
+    match error {
+        claxon::Error::IoError(e) => hound::Error::IoError(e),
+        claxon::Error::FormatError(reason) => hound::Error::FormatError(reason),
+        claxon::Error::Unsupported(_) => hound::Error::Unsupported,
+    }
+}
+
+/// Maps Vorbis comments onto ID3 fields. Field names are case-insensitive
+/// and the first value wins when a field repeats.
+pub fn to_tags<'a>(comments: impl Iterator<Item = (&'a str, &'a str)>) -> TrackTags {
+    let mut tags = TrackTags::default();
+    let (mut track, mut total) = (None, None);
+    for (name, value) in comments {
+        let value = value.trim();
+        if value.is_empty() {
+            continue;
+        }
+        let field = match name.to_ascii_uppercase().as_str() {
+            "TITLE" => &mut tags.title,
+            "ARTIST" => &mut tags.artist,
+            "ALBUM" => &mut tags.album,
+            "DATE" | "YEAR" => {
+                tags.year = tags.year.or_else(|| year_of(value));
+                continue;
+            }
+            "COMMENT" | "DESCRIPTION" => &mut tags.comment,
+            "TRACKNUMBER" => &mut track,
+            "TRACKTOTAL" | "TOTALTRACKS" => &mut total,
+            "GENRE" => &mut tags.genre,
+            _ => continue,
+        };
+        field.get_or_insert_with(|| value.to_string());
+    }
+    tags.track = match (track, total) {
+        (Some(track), Some(total)) if !track.contains('/') => Some(format!("{}/{}", track, total)),
+        (track, _) => track,
+    }
+    .filter(|t| is_track(t));
+    tags
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::SAMPLE_PATH;
+
+    #[test]
+    fn test_is_flac() {
+        assert!(is_flac(b"fLaC\x80\x00\x00\x22"));
+        assert!(!is_flac(b"RIFF\x00\x00\x00\x00WAVE"));
+    }
+
+    #[test]
+    fn test_read_sample() {
+        let mut reader =
+            claxon::FlacReader::open(format!("{}{}", SAMPLE_PATH, "sinei16.flac")).unwrap();
+        let info = reader.streaminfo();
+        assert_eq!(info.channels, 2);
+        assert_eq!(info.sample_rate, 44100);
+        assert_eq!(info.samples, Some(4410));
+        let tags = to_tags(reader.tags());
+        assert_eq!(tags.title.as_deref(), Some("Flac Sine"));
+        assert_eq!(tags.artist.as_deref(), Some("waveemapi"));
+        assert_eq!(tags.year.as_deref(), Some("2021"));
+        assert_eq!(tags.track.as_deref(), Some("3/12"));
+        assert_eq!(tags.genre.as_deref(), Some("Test"));
+        let samples: Vec<i32> = reader.samples().map(Result::unwrap).collect();
+        assert_eq!(samples.len(), 4410 * 2);
+        // Interleaved: 440 Hz left, 660 Hz right.
+        assert_eq!(
+            samples[2],
+            (12000.0 * (2.0 * std::f64::consts::PI * 440.0 / 44100.0).sin()) as i32
+        );
+    }
+
+    #[test]
+    fn test_to_tags() {
+        let tags = to_tags(
+            [
+                ("title", " First "),
+                ("TITLE", "Second"),
+                ("TRACKNUMBER", "4/9"),
+                ("TRACKTOTAL", "12"),
+                ("DATE", "May 2020"),
+                ("COMMENT", ""),
+                ("DESCRIPTION", "desc"),
+            ]
+            .into_iter(),
+        );
+        assert_eq!(tags.title.as_deref(), Some("First"));
+        assert_eq!(tags.track.as_deref(), Some("4/9"));
+        assert_eq!(tags.year, None);
+        assert_eq!(tags.comment.as_deref(), Some("desc"));
+        assert!(
+            to_tags([("TRACKNUMBER", "one")].into_iter())
+                .track
+                .is_none()
+        );
+    }
+}
@@ -0,0 +1,95 @@
+use std::io::Read;
+
+/// G.711 companding law.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Law {
+    A,
+    Mu,
+}
+
+impl Law {
+    /// Expands one companded byte to 16-bit linear PCM.
+    pub fn expand(self, byte: u8) -> i16 {
+        match self {
+            Law::A => {
+                let a = byte ^ 0x55;
+                let mut t = ((a & 0x0F) as i16) << 4;
+                match (a & 0x70) >> 4 {
+                    0 => t += 8,
+                    1 => t += 0x108,
+                    seg => t = (t + 0x108) << (seg - 1),
+                }
+                if a & 0x80 != 0 { t } else { -t }
+            }
+            Law::Mu => {
+                let u = !byte;
+                let t = ((((u & 0x0F) as i16) << 3) + 0x84) << ((u & 0x70) >> 4);
+                if u & 0x80 != 0 { 0x84 - t } else { t - 0x84 }
+            }
+        }
+    }
+}
+
+/// Reads `WAVE_FORMAT_ALAW` or `WAVE_FORMAT_MULAW` samples, one byte each.
+pub struct G711Samples<R> {
+    reader: R,
+    law: Law,
+    remaining: u64,
+}
+
+impl<R: Read> G711Samples<R> {
+    pub fn new(reader: R, law: Law, data_len: u64) -> Self {
+        G711Samples {
+            reader,
+            law,
+            remaining: data_len,
+        }
+    }
+}
+
+impl<R: Read> Iterator for G711Samples<R> {
+    type Item = hound::Result<i16>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        let mut byte = [0u8; 1];
+        if let Err(e) = self.reader.read_exact(&mut byte) {
+            self.remaining = 0;
+            return Some(Err(hound::Error::IoError(e)));
+        }
+        Some(Ok(self.law.expand(byte[0])))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_a_law() {
+        // Reference values from ITU-T G.711.
+        assert_eq!(Law::A.expand(0xD5), 8);
+        assert_eq!(Law::A.expand(0x55), -8);
+        assert_eq!(Law::A.expand(0xAA), 32256);
+        assert_eq!(Law::A.expand(0x2A), -32256);
+    }
+
+    #[test]
+    fn test_mu_law() {
+        assert_eq!(Law::Mu.expand(0xFF), 0);
+        assert_eq!(Law::Mu.expand(0x7F), 0);
+        assert_eq!(Law::Mu.expand(0x80), 32124);
+        assert_eq!(Law::Mu.expand(0x00), -32124);
+    }
+
+    #[test]
+    fn test_samples() {
+        let samples: Vec<i16> = G711Samples::new(&[0xFFu8, 0x80, 0x00][..], Law::Mu, 3)
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(samples, vec![0, 32124, -32124]);
+    }
+}
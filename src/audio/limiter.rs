@@ -0,0 +1,265 @@
+use std::collections::VecDeque;
+
+use crate::audio::analyze::TruePeak;
+use crate::error::WaveemapiError;
+
+/// Highest true peak after normalization, EBU R128 s1 recommends -1 dBTP.
+pub const TRUE_PEAK_CEILING_DBTP: f64 = -1.0;
+/// How far ahead of a peak the gain starts to come down.
+const LOOKAHEAD_SECONDS: f64 = 0.005;
+/// Time for the gain to recover from full reduction.
+const RELEASE_SECONDS: f64 = 0.2;
+
+/// Validates a requested loudness normalization target.
+pub fn check_normalize_lufs(target: Option<f64>) -> Result<Option<f64>, WaveemapiError> {
+    match target {
+        Some(lufs) if !(-70.0..=0.0).contains(&lufs) => Err(WaveemapiError::InvalidOptions(
+            format!("Unsupported normalize_lufs {}, expected -70 to 0", lufs),
+        )),
+        _ => Ok(target),
+    }
+}
+
+/// Applies a fixed gain, then keeps the true peak under the ceiling by
+/// lowering the gain of all channels together shortly before each peak.
+///
+/// Every frame gets the gain required by the loudest peak within the
+/// lookahead, averaged over the lookahead, so the gain ramps down in time and
+/// is never higher than any peak at or near the frame allows.
+pub struct Limiter<I> {
+    samples: I,
+    channels: usize,
+    gain: f32,
+    ceiling: f32,
+    lookahead: usize,
+    /// Gain recovered per frame.
+    release: f32,
+    true_peaks: Vec<TruePeak>,
+    /// Peak between the previous two frames leaving the true peak delay.
+    last_segment: f32,
+    /// Frames pushed into the true peak estimators, real or padding.
+    frames_in: usize,
+    /// Frames whose required gain is known, real or padding.
+    frames_required: usize,
+    /// Required gains in increasing order from the lookahead, by frame.
+    minima: VecDeque<(usize, f32)>,
+    /// The last `lookahead` lookahead minima and their sum.
+    window: VecDeque<f32>,
+    window_sum: f64,
+    envelope: f32,
+    /// Gained samples waiting for their gain reduction.
+    pending: VecDeque<f32>,
+    out: VecDeque<f32>,
+    done: bool,
+}
+
+impl<I> Limiter<I> {
+    pub fn new(samples: I, channels: usize, sample_rate: u32, gain_db: f64) -> Self {
+        Limiter {
+            samples,
+            channels,
+            gain: 10f64.powf(gain_db / 20.0) as f32,
+            ceiling: 10f64.powf(TRUE_PEAK_CEILING_DBTP / 20.0) as f32,
+            lookahead: ((sample_rate as f64 * LOOKAHEAD_SECONDS) as usize).max(1),
+            release: (1.0 / (sample_rate as f64 * RELEASE_SECONDS)) as f32,
+            true_peaks: (0..channels).map(|_| TruePeak::new()).collect(),
+            last_segment: 0.0,
+            frames_in: 0,
+            frames_required: 0,
+            minima: VecDeque::new(),
+            window: VecDeque::new(),
+            window_sum: 0.0,
+            envelope: 1.0,
+            pending: VecDeque::new(),
+            out: VecDeque::new(),
+            done: false,
+        }
+    }
+
+    /// Measures a frame, returning the gain required by the frame leaving the
+    /// true peak delay, which covers the peaks on either side of it.
+    fn measure(&mut self, frame: impl Iterator<Item = f32>) -> Option<f32> {
+        let segment = self
+            .true_peaks
+            .iter_mut()
+            .zip(frame)
+            .fold(0.0f32, |peak, (true_peak, sample)| {
+                peak.max(true_peak.push(sample))
+            });
+        self.frames_in += 1;
+        if self.frames_in <= TruePeak::DELAY {
+            return None;
+        }
+        let peak = segment.max(self.last_segment);
+        self.last_segment = segment;
+        Some(if peak > self.ceiling {
+            self.ceiling / peak
+        } else {
+            1.0
+        })
+    }
+
+    /// Adds the required gain of the next frame, releasing the frame one
+    /// lookahead back once every frame that can affect it is known.
+    fn require(&mut self, gain: f32) {
+        let frame = self.frames_required;
+        self.frames_required += 1;
+        while self.minima.back().is_some_and(|&(_, g)| g >= gain) {
+            self.minima.pop_back();
+        }
+        self.minima.push_back((frame, gain));
+        let Some(first) = (frame + 1).checked_sub(self.lookahead) else {
+            return;
+        };
+        while self.minima.front().is_some_and(|&(f, _)| f < first) {
+            self.minima.pop_front();
+        }
+        let minimum = self.minima[0].1;
+        if self.window.is_empty() {
+            // Frames before the first one take its minimum, which covers them.
+            self.window.resize(self.lookahead, minimum);
+            self.window_sum = minimum as f64 * self.lookahead as f64;
+        } else {
+            self.window_sum += minimum as f64 - self.window.pop_front().unwrap_or(1.0) as f64;
+            self.window.push_back(minimum);
+        }
+        let gain = (self.window_sum / self.lookahead as f64) as f32;
+        self.envelope = gain.min(self.envelope + self.release);
+        if self.pending.len() >= self.channels {
+            let envelope = self.envelope;
+            self.out
+                .extend(self.pending.drain(..self.channels).map(|s| s * envelope));
+        }
+    }
+}
+
+impl<I: Iterator<Item = Result<f32, WaveemapiError>>> Limiter<I> {
+    /// Reads one frame, or flushes the delays once the input has ended.
+    fn step(&mut self) -> Result<(), WaveemapiError> {
+        let mut frame = Vec::with_capacity(self.channels);
+        for _ in 0..self.channels {
+            match self.samples.next() {
+                Some(sample) => frame.push(sample? * self.gain),
+                // A partial trailing frame is dropped.
+                None => {
+                    self.flush();
+                    return Ok(());
+                }
+            }
+        }
+        self.pending.extend(&frame);
+        if let Some(gain) = self.measure(frame.into_iter()) {
+            self.require(gain);
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) {
+        // Silence after the end lets the last frames leave the true peak delay.
+        let real = self.frames_in;
+        while self.frames_required < real {
+            let channels = self.channels;
+            if let Some(gain) = self.measure(std::iter::repeat_n(0.0, channels)) {
+                self.require(gain);
+            }
+        }
+        while !self.pending.is_empty() {
+            self.require(1.0);
+        }
+        self.done = true;
+    }
+}
+
+impl<I: Iterator<Item = Result<f32, WaveemapiError>>> Iterator for Limiter<I> {
+    type Item = Result<f32, WaveemapiError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.out.is_empty() {
+            if self.done {
+                return None;
+            }
+            if let Err(e) = self.step() {
+                self.done = true;
+                return Some(Err(e));
+            }
+        }
+        self.out.pop_front().map(Ok)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limit(samples: Vec<f32>, channels: usize, gain_db: f64) -> Vec<f32> {
+        Limiter::new(samples.into_iter().map(Ok), channels, 48000, gain_db)
+            .collect::<Result<_, _>>()
+            .unwrap()
+    }
+
+    fn sine(frequency: f64, amplitude: f64, frames: usize) -> Vec<f32> {
+        (0..frames)
+            .map(|i| {
+                (amplitude * (2.0 * std::f64::consts::PI * frequency * i as f64 / 48000.0).sin())
+                    as f32
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_check_normalize_lufs() {
+        assert_eq!(check_normalize_lufs(None).unwrap(), None);
+        assert_eq!(check_normalize_lufs(Some(-16.0)).unwrap(), Some(-16.0));
+        assert!(check_normalize_lufs(Some(3.0)).is_err());
+        assert!(check_normalize_lufs(Some(f64::NAN)).is_err());
+    }
+
+    #[test]
+    fn test_gain_below_ceiling() {
+        let input = sine(1000.0, 0.1, 4800);
+        let output = limit(input.clone(), 1, 6.0);
+        assert_eq!(output.len(), input.len());
+        for (i, o) in input.iter().zip(&output) {
+            assert!((i * 10f32.powf(0.3) - o).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn test_limits_true_peak() {
+        // 12 dB of gain would put this sine at +6 dBTP.
+        let input = sine(997.0, 0.5, 48000);
+        let output = limit(input.clone(), 1, 12.0);
+        assert_eq!(output.len(), input.len());
+        let mut true_peak = TruePeak::new();
+        let peak = output
+            .iter()
+            .chain(&[0.0; TruePeak::DELAY])
+            .fold(0.0f32, |peak, &s| peak.max(true_peak.push(s)));
+        let ceiling = 10f32.powf(TRUE_PEAK_CEILING_DBTP as f32 / 20.0);
+        assert!(peak <= ceiling * 1.0001, "peak {}", peak);
+        assert!(peak > ceiling * 0.99, "peak {}", peak);
+    }
+
+    #[test]
+    fn test_limits_channels_together() {
+        let frames = 4800;
+        let loud = sine(1000.0, 1.0, frames);
+        let input: Vec<f32> = loud.iter().flat_map(|&s| [s, s * 0.1]).collect();
+        let output = limit(input.clone(), 2, 0.0);
+        assert_eq!(output.len(), input.len());
+        // The quiet channel follows the loud one's reduction.
+        for frame in output.chunks(2).skip(1000) {
+            assert!((frame[0] * 0.1 - frame[1]).abs() < 1e-6);
+        }
+        assert!(output.iter().step_by(2).all(|s| s.abs() < 0.9));
+    }
+
+    #[test]
+    fn test_short_input() {
+        let output = limit(vec![1.0, -1.0, 1.0], 1, 0.0);
+        assert_eq!(output.len(), 3);
+        assert!(output.iter().all(|s| s.abs() <= 0.9));
+        // A partial trailing frame is dropped.
+        assert_eq!(limit(vec![0.1, 0.1, 0.1], 2, 0.0).len(), 2);
+    }
+}
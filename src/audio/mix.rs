@@ -0,0 +1,431 @@
+use crate::error::WaveemapiError;
+
+const MINUS_3DB: f32 = std::f32::consts::FRAC_1_SQRT_2;
+
+// WAVE_FORMAT_EXTENSIBLE speaker positions, in the order they appear in a file.
+const SPEAKER_FRONT_LEFT: u32 = 0x1;
+const SPEAKER_FRONT_RIGHT: u32 = 0x2;
+const SPEAKER_FRONT_CENTER: u32 = 0x4;
+const SPEAKER_LOW_FREQUENCY: u32 = 0x8;
+const SPEAKER_BACK_LEFT: u32 = 0x10;
+const SPEAKER_BACK_RIGHT: u32 = 0x20;
+const SPEAKER_FRONT_LEFT_OF_CENTER: u32 = 0x40;
+const SPEAKER_FRONT_RIGHT_OF_CENTER: u32 = 0x80;
+const SPEAKER_BACK_CENTER: u32 = 0x100;
+const SPEAKER_SIDE_LEFT: u32 = 0x200;
+const SPEAKER_SIDE_RIGHT: u32 = 0x400;
+const SPEAKER_TOP_CENTER: u32 = 0x800;
+const SPEAKER_TOP_FRONT_LEFT: u32 = 0x1000;
+const SPEAKER_TOP_FRONT_CENTER: u32 = 0x2000;
+const SPEAKER_TOP_FRONT_RIGHT: u32 = 0x4000;
+const SPEAKER_TOP_BACK_LEFT: u32 = 0x8000;
+const SPEAKER_TOP_BACK_CENTER: u32 = 0x10000;
+const SPEAKER_TOP_BACK_RIGHT: u32 = 0x20000;
+
+/// How the decoded channels are turned into the one or two channels LAME encodes.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum MixSpec {
+    /// Mono and stereo pass through, anything wider is downmixed to stereo.
+    #[default]
+    Auto,
+    Mono,
+    Stereo,
+    /// Picks input channels by zero based index, one per output channel.
+    Channels(Vec<usize>),
+    /// One row of input channel gains per output channel.
+    Matrix(Vec<Vec<f32>>),
+}
+
+impl MixSpec {
+    /// Parses the `downmix`, `channels` and `matrix` request fields. At most
+    /// one of them may be given.
+    ///
+    /// `channels` is a comma separated list such as `0,2`, `matrix` has one
+    /// comma separated row of gains per output channel, separated by `;`.
+    pub fn new(
+        downmix: Option<&str>,
+        channels: Option<&str>,
+        matrix: Option<&str>,
+    ) -> Result<Self, WaveemapiError> {
+        let (downmix, channels, matrix) = (present(downmix), present(channels), present(matrix));
+        let given = [downmix, channels, matrix]
+            .iter()
+            .filter(|v| v.is_some())
+            .count();
+        if given > 1 {
+            return Err(invalid(
+                "only one of downmix, channels or matrix may be given",
+            ));
+        }
+        if let Some(downmix) = downmix {
+            return match downmix.to_ascii_lowercase().as_str() {
+                "auto" => Ok(MixSpec::Auto),
+                "mono" => Ok(MixSpec::Mono),
+                "stereo" => Ok(MixSpec::Stereo),
+                other => Err(invalid(&format!(
+                    "Unknown downmix '{}', expected auto, mono or stereo",
+                    other
+                ))),
+            };
+        }
+        if let Some(channels) = channels {
+            let channels = channels
+                .split(',')
+                .map(|c| c.trim().parse::<usize>())
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|_| invalid("channels must be a comma separated list of indices"))?;
+            check_outputs(channels.len())?;
+            return Ok(MixSpec::Channels(channels));
+        }
+        if let Some(matrix) = matrix {
+            let rows = matrix
+                .split(';')
+                .map(|row| {
+                    row.split(',')
+                        .map(|g| g.trim().parse::<f32>())
+                        .collect::<Result<Vec<_>, _>>()
+                })
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|_| invalid("matrix must be rows of comma separated gains"))?;
+            check_outputs(rows.len())?;
+            if rows.iter().flatten().any(|g| !g.is_finite()) {
+                return Err(invalid("matrix gains must be finite"));
+            }
+            return Ok(MixSpec::Matrix(rows));
+        }
+        Ok(MixSpec::Auto)
+    }
+
+    /// Resolves the spec against the actual input into a gain matrix, with
+    /// one row per output channel. Returns `None` when no mixing is needed.
+    pub fn matrix(
+        &self,
+        channels: usize,
+        channel_mask: Option<u32>,
+    ) -> Result<Option<Vec<Vec<f32>>>, WaveemapiError> {
+        let matrix = match self {
+            MixSpec::Auto if channels <= 2 => return Ok(None),
+            MixSpec::Auto | MixSpec::Stereo => stereo_matrix(channels, channel_mask),
+            MixSpec::Mono => {
+                let stereo = stereo_matrix(channels, channel_mask);
+                let row: Vec<f32> = (0..channels)
+                    .map(|i| (stereo[0][i] + stereo[1][i]) / 2.0)
+                    .collect();
+                vec![row]
+            }
+            MixSpec::Channels(picks) => {
+                if let Some(&bad) = picks.iter().find(|&&c| c >= channels) {
+                    return Err(invalid(&format!(
+                        "channel {} does not exist, the input has {} channels",
+                        bad, channels
+                    )));
+                }
+                picks
+                    .iter()
+                    .map(|&pick| {
+                        (0..channels)
+                            .map(|i| f32::from(i == pick))
+                            .collect::<Vec<f32>>()
+                    })
+                    .collect()
+            }
+            MixSpec::Matrix(rows) => {
+                if rows.iter().any(|row| row.len() != channels) {
+                    return Err(invalid(&format!(
+                        "each matrix row needs {} gains, one per input channel",
+                        channels
+                    )));
+                }
+                rows.clone()
+            }
+        };
+        Ok(Some(matrix))
+    }
+}
+
+fn present(value: Option<&str>) -> Option<&str> {
+    value.filter(|v| !v.trim().is_empty())
+}
+
+fn invalid(message: &str) -> WaveemapiError {
+    WaveemapiError::InvalidOptions(message.to_string())
+}
+
+fn check_outputs(outputs: usize) -> Result<(), WaveemapiError> {
+    if outputs == 0 || outputs > 2 {
+        return Err(invalid("a custom mix must produce one or two channels"));
+    }
+    Ok(())
+}
+
+/// Speaker position of every channel. Without a mask the usual WAV layouts
+/// for the channel count are assumed, e.g. `FL FR FC LFE BL BR` for 5.1.
+fn speaker_positions(channels: usize, channel_mask: Option<u32>) -> Vec<u32> {
+    let mask = channel_mask.filter(|&m| m != 0).unwrap_or(match channels {
+        1 => SPEAKER_FRONT_CENTER,
+        2 => SPEAKER_FRONT_LEFT | SPEAKER_FRONT_RIGHT,
+        3 => SPEAKER_FRONT_LEFT | SPEAKER_FRONT_RIGHT | SPEAKER_FRONT_CENTER,
+        4 => SPEAKER_FRONT_LEFT | SPEAKER_FRONT_RIGHT | SPEAKER_BACK_LEFT | SPEAKER_BACK_RIGHT,
+        5 => {
+            SPEAKER_FRONT_LEFT
+                | SPEAKER_FRONT_RIGHT
+                | SPEAKER_FRONT_CENTER
+                | SPEAKER_BACK_LEFT
+                | SPEAKER_BACK_RIGHT
+        }
+        6 => {
+            SPEAKER_FRONT_LEFT
+                | SPEAKER_FRONT_RIGHT
+                | SPEAKER_FRONT_CENTER
+                | SPEAKER_LOW_FREQUENCY
+                | SPEAKER_BACK_LEFT
+                | SPEAKER_BACK_RIGHT
+        }
+        7 => {
+            SPEAKER_FRONT_LEFT
+                | SPEAKER_FRONT_RIGHT
+                | SPEAKER_FRONT_CENTER
+                | SPEAKER_LOW_FREQUENCY
+                | SPEAKER_BACK_CENTER
+                | SPEAKER_SIDE_LEFT
+                | SPEAKER_SIDE_RIGHT
+        }
+        8 => {
+            SPEAKER_FRONT_LEFT
+                | SPEAKER_FRONT_RIGHT
+                | SPEAKER_FRONT_CENTER
+                | SPEAKER_LOW_FREQUENCY
+                | SPEAKER_BACK_LEFT
+                | SPEAKER_BACK_RIGHT
+                | SPEAKER_SIDE_LEFT
+                | SPEAKER_SIDE_RIGHT
+        }
+        _ => 0,
+    });
+    let mut positions: Vec<u32> = (0..32)
+        .map(|bit| 1u32 << bit)
+        .filter(|p| mask & p != 0)
+        .take(channels)
+        .collect();
+    positions.resize(channels, 0); // channels beyond the mask have no position
+    positions
+}
+
+/// ITU-R BS.775 gains towards (left, right) for a speaker position. Centre and
+/// surround channels are attenuated by 3 dB, LFE is dropped.
+fn stereo_gains(position: u32) -> (f32, f32) {
+    match position {
+        SPEAKER_FRONT_LEFT => (1.0, 0.0),
+        SPEAKER_FRONT_RIGHT => (0.0, 1.0),
+        SPEAKER_FRONT_CENTER => (MINUS_3DB, MINUS_3DB),
+        SPEAKER_LOW_FREQUENCY => (0.0, 0.0),
+        SPEAKER_BACK_LEFT | SPEAKER_SIDE_LEFT | SPEAKER_FRONT_LEFT_OF_CENTER => (MINUS_3DB, 0.0),
+        SPEAKER_BACK_RIGHT | SPEAKER_SIDE_RIGHT | SPEAKER_FRONT_RIGHT_OF_CENTER => (0.0, MINUS_3DB),
+        SPEAKER_BACK_CENTER | SPEAKER_TOP_FRONT_CENTER => (0.5, 0.5),
+        SPEAKER_TOP_FRONT_LEFT | SPEAKER_TOP_BACK_LEFT => (0.5, 0.0),
+        SPEAKER_TOP_FRONT_RIGHT | SPEAKER_TOP_BACK_RIGHT => (0.0, 0.5),
+        SPEAKER_TOP_CENTER | SPEAKER_TOP_BACK_CENTER => (0.35, 0.35),
+        _ => (0.5, 0.5),
+    }
+}
+
+/// ITU-R BS.1770 loudness weight of every channel: surrounds count 1.41
+/// (+1.5 dB), LFE is left out.
+pub(super) fn loudness_weights(channels: usize, channel_mask: Option<u32>) -> Vec<f64> {
+    speaker_positions(channels, channel_mask)
+        .into_iter()
+        .map(|position| match position {
+            SPEAKER_LOW_FREQUENCY => 0.0,
+            SPEAKER_BACK_LEFT | SPEAKER_BACK_RIGHT | SPEAKER_SIDE_LEFT | SPEAKER_SIDE_RIGHT => 1.41,
+            _ => 1.0,
+        })
+        .collect()
+}
+
+/// Builds the stereo downmix, scaling each row so a full scale signal on
+/// every input channel cannot clip.
+fn stereo_matrix(channels: usize, channel_mask: Option<u32>) -> Vec<Vec<f32>> {
+    let gains: Vec<(f32, f32)> = speaker_positions(channels, channel_mask)
+        .into_iter()
+        .map(stereo_gains)
+        .collect();
+    // A lone mono channel sits at the centre, but should come out at full level.
+    if channels == 1 {
+        return vec![vec![1.0], vec![1.0]];
+    }
+    let mut left: Vec<f32> = gains.iter().map(|g| g.0).collect();
+    let mut right: Vec<f32> = gains.iter().map(|g| g.1).collect();
+    for row in [&mut left, &mut right] {
+        let sum: f32 = row.iter().sum();
+        if sum > 1.0 {
+            row.iter_mut().for_each(|g| *g /= sum);
+        }
+    }
+    vec![left, right]
+}
+
+/// Applies a gain matrix to interleaved samples, frame by frame.
+pub struct Downmix<I> {
+    samples: I,
+    matrix: Vec<Vec<f32>>,
+    frame: Vec<f32>,
+    out: Vec<f32>,
+    pos: usize,
+}
+
+impl<I> Downmix<I> {
+    pub fn new(samples: I, matrix: Vec<Vec<f32>>) -> Self {
+        let inputs = matrix.first().map_or(0, Vec::len);
+        let outputs = matrix.len();
+        Downmix {
+            samples,
+            matrix,
+            frame: Vec::with_capacity(inputs),
+            out: Vec::with_capacity(outputs),
+            pos: 0,
+        }
+    }
+
+    pub fn channels(&self) -> usize {
+        self.matrix.len()
+    }
+}
+
+impl<I: Iterator<Item = Result<f32, WaveemapiError>>> Iterator for Downmix<I> {
+    type Item = Result<f32, WaveemapiError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.pos < self.out.len() {
+            self.pos += 1;
+            return Some(Ok(self.out[self.pos - 1]));
+        }
+        let inputs = self.matrix.first()?.len();
+        self.frame.clear();
+        while self.frame.len() < inputs {
+            match self.samples.next()? {
+                Ok(s) => self.frame.push(s),
+                Err(e) => return Some(Err(e)),
+            }
+        }
+        self.out.clear();
+        for row in &self.matrix {
+            self.out
+                .push(row.iter().zip(&self.frame).map(|(g, s)| g * s).sum());
+        }
+        self.pos = 1;
+        Some(Ok(self.out[0]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mix(samples: &[f32], matrix: Vec<Vec<f32>>) -> Vec<f32> {
+        Downmix::new(samples.iter().map(|&s| Ok(s)), matrix)
+            .map(Result::unwrap)
+            .collect()
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(MixSpec::new(None, None, None).unwrap(), MixSpec::Auto);
+        assert_eq!(
+            MixSpec::new(Some("Mono"), None, None).unwrap(),
+            MixSpec::Mono
+        );
+        assert_eq!(
+            MixSpec::new(None, Some("2, 3"), None).unwrap(),
+            MixSpec::Channels(vec![2, 3])
+        );
+        assert_eq!(
+            MixSpec::new(None, None, Some("1,0,0.5;0,1,0.5")).unwrap(),
+            MixSpec::Matrix(vec![vec![1.0, 0.0, 0.5], vec![0.0, 1.0, 0.5]])
+        );
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        assert!(MixSpec::new(Some("quad"), None, None).is_err());
+        assert!(MixSpec::new(Some("mono"), Some("0"), None).is_err());
+        assert!(MixSpec::new(None, Some("0,1,2"), None).is_err());
+        assert!(MixSpec::new(None, Some("left"), None).is_err());
+        assert!(MixSpec::new(None, None, Some("1,x")).is_err());
+        assert!(MixSpec::new(None, None, Some("NaN")).is_err());
+    }
+
+    #[test]
+    fn test_auto_passthrough() {
+        assert_eq!(MixSpec::Auto.matrix(1, None).unwrap(), None);
+        assert_eq!(MixSpec::Auto.matrix(2, None).unwrap(), None);
+        assert!(MixSpec::Auto.matrix(6, None).unwrap().is_some());
+    }
+
+    #[test]
+    fn test_five_one_downmix() {
+        let matrix = MixSpec::Stereo.matrix(6, None).unwrap().unwrap();
+        // FL FR FC LFE BL BR, each row normalised by 1 + 2 * -3 dB
+        let norm = 1.0 + 2.0 * MINUS_3DB;
+        let expected_left = [1.0, 0.0, MINUS_3DB, 0.0, MINUS_3DB, 0.0].map(|g| g / norm);
+        for (got, want) in matrix[0].iter().zip(expected_left) {
+            assert!((got - want).abs() < 1e-6);
+        }
+        assert_eq!(matrix[1][3], 0.0, "LFE should be dropped");
+    }
+
+    #[test]
+    fn test_channel_mask_order() {
+        // Quad written as FL FR SL SR instead of the default back speakers.
+        let mask =
+            SPEAKER_FRONT_LEFT | SPEAKER_FRONT_RIGHT | SPEAKER_SIDE_LEFT | SPEAKER_SIDE_RIGHT;
+        assert_eq!(
+            speaker_positions(4, Some(mask)),
+            vec![
+                SPEAKER_FRONT_LEFT,
+                SPEAKER_FRONT_RIGHT,
+                SPEAKER_SIDE_LEFT,
+                SPEAKER_SIDE_RIGHT
+            ]
+        );
+        assert_eq!(speaker_positions(3, Some(SPEAKER_FRONT_CENTER)).len(), 3);
+    }
+
+    #[test]
+    fn test_loudness_weights() {
+        assert_eq!(loudness_weights(2, None), vec![1.0, 1.0]);
+        assert_eq!(
+            loudness_weights(6, None),
+            vec![1.0, 1.0, 1.0, 0.0, 1.41, 1.41]
+        );
+    }
+
+    #[test]
+    fn test_pick_channels() {
+        let matrix = MixSpec::Channels(vec![2]).matrix(4, None).unwrap().unwrap();
+        assert_eq!(
+            mix(&[0.1, 0.2, 0.3, 0.4, 0.5, 0.6, 0.7, 0.8], matrix),
+            vec![0.3, 0.7]
+        );
+        assert!(MixSpec::Channels(vec![4]).matrix(4, None).is_err());
+    }
+
+    #[test]
+    fn test_custom_matrix() {
+        let spec = MixSpec::Matrix(vec![vec![0.5, 0.5, 0.0], vec![0.0, 0.0, 1.0]]);
+        assert!(spec.matrix(2, None).is_err());
+        let matrix = spec.matrix(3, None).unwrap().unwrap();
+        assert_eq!(mix(&[0.25, 0.5, -1.0], matrix), vec![0.375, -1.0]);
+    }
+
+    #[test]
+    fn test_mono_downmix() {
+        let matrix = MixSpec::Mono.matrix(2, None).unwrap().unwrap();
+        assert_eq!(mix(&[1.0, 0.0, 0.5, 0.5], matrix), vec![0.5, 0.5]);
+    }
+
+    #[test]
+    fn test_mono_to_stereo() {
+        let matrix = MixSpec::Stereo.matrix(1, None).unwrap().unwrap();
+        assert_eq!(mix(&[0.25], matrix), vec![0.25, 0.25]);
+    }
+}
@@ -0,0 +1,218 @@
+use std::io::Read;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ByteOrder {
+    Little,
+    Big,
+}
+
+/// Reads integer PCM of any container width from 1 to 4 bytes, for the bit
+/// depths `hound` refuses (e.g. 12-bit in 2 bytes, 20-bit in 3) and for AIFF.
+///
+/// Both WAVE and AIFF store samples left-justified in their container, so
+/// each one is shifted up to full `i32` scale regardless of how many bits are
+/// valid. 8-bit WAVE samples are unsigned and get the usual 128 offset
+/// removed first, 8-bit AIFF samples are already signed.
+pub struct PcmSamples<R> {
+    reader: R,
+    container_bytes: usize,
+    remaining: u64,
+    order: ByteOrder,
+    unsigned_8bit: bool,
+}
+
+impl<R: Read> PcmSamples<R> {
+    /// Little-endian WAVE samples.
+    pub fn new(reader: R, container_bytes: usize, data_len: u64) -> hound::Result<Self> {
+        Self::with_layout(reader, container_bytes, data_len, ByteOrder::Little, true)
+    }
+
+    /// Signed AIFF samples, big-endian unless the file is `sowt` AIFF-C.
+    pub fn aiff(
+        reader: R,
+        container_bytes: usize,
+        data_len: u64,
+        order: ByteOrder,
+    ) -> hound::Result<Self> {
+        Self::with_layout(reader, container_bytes, data_len, order, false)
+    }
+
+    fn with_layout(
+        reader: R,
+        container_bytes: usize,
+        data_len: u64,
+        order: ByteOrder,
+        unsigned_8bit: bool,
+    ) -> hound::Result<Self> {
+        if !(1..=4).contains(&container_bytes) {
+            return Err(hound::Error::Unsupported);
+        }
+        Ok(PcmSamples {
+            reader,
+            container_bytes,
+            remaining: data_len / container_bytes as u64,
+            order,
+            unsigned_8bit,
+        })
+    }
+}
+
+impl<R: Read> Iterator for PcmSamples<R> {
+    type Item = hound::Result<i32>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        let mut buf = [0u8; 4];
+        let bytes = match self.order {
+            ByteOrder::Little => &mut buf[4 - self.container_bytes..],
+            ByteOrder::Big => &mut buf[..self.container_bytes],
+        };
+        if let Err(e) = self.reader.read_exact(bytes) {
+            self.remaining = 0;
+            return Some(Err(hound::Error::IoError(e)));
+        }
+        let sample = match self.order {
+            ByteOrder::Little => i32::from_le_bytes(buf),
+            ByteOrder::Big => i32::from_be_bytes(buf),
+        };
+        if self.container_bytes == 1 && self.unsigned_8bit {
+            return Some(Ok(sample ^ i32::MIN)); // unsigned to two's complement
+        }
+        Some(Ok(sample))
+    }
+}
+
+/// Reads 32-bit float samples: little-endian in WAVE, big-endian in `fl32`
+/// AIFF-C.
+pub struct FloatSamples<R> {
+    reader: R,
+    remaining: u64,
+    order: ByteOrder,
+}
+
+impl<R: Read> FloatSamples<R> {
+    pub fn new(reader: R, data_len: u64, order: ByteOrder) -> Self {
+        FloatSamples {
+            reader,
+            remaining: data_len / 4,
+            order,
+        }
+    }
+}
+
+impl<R: Read> Iterator for FloatSamples<R> {
+    type Item = hound::Result<f32>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        let mut buf = [0u8; 4];
+        if let Err(e) = self.reader.read_exact(&mut buf) {
+            self.remaining = 0;
+            return Some(Err(hound::Error::IoError(e)));
+        }
+        Some(Ok(match self.order {
+            ByteOrder::Little => f32::from_le_bytes(buf),
+            ByteOrder::Big => f32::from_be_bytes(buf),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(bytes: &[u8], container_bytes: usize) -> Vec<i32> {
+        PcmSamples::new(bytes, container_bytes, bytes.len() as u64)
+            .unwrap()
+            .map(Result::unwrap)
+            .collect()
+    }
+
+    #[test]
+    fn test_unsigned_8bit() {
+        assert_eq!(
+            decode(&[0x00, 0x80, 0xFF], 1),
+            vec![i32::MIN, 0, 0x7F << 24]
+        );
+    }
+
+    #[test]
+    fn test_12bit_in_16() {
+        // 12-bit values are stored in the top bits: 0x7FF0 is the largest.
+        assert_eq!(
+            decode(&[0xF0, 0x7F, 0x10, 0x80], 2),
+            vec![0x7FF0 << 16, (0x8010u32 << 16) as i32]
+        );
+    }
+
+    #[test]
+    fn test_20bit_in_24() {
+        assert_eq!(
+            decode(&[0xF0, 0xFF, 0x7F, 0x00, 0x00, 0x80], 3),
+            vec![0x7F_FFF0 << 8, i32::MIN]
+        );
+    }
+
+    #[test]
+    fn test_aiff_big_endian() {
+        let decode = |bytes: &[u8], container_bytes| -> Vec<i32> {
+            PcmSamples::aiff(bytes, container_bytes, bytes.len() as u64, ByteOrder::Big)
+                .unwrap()
+                .map(Result::unwrap)
+                .collect()
+        };
+        // 8-bit AIFF is signed, unlike WAVE.
+        assert_eq!(
+            decode(&[0x80, 0x00, 0x7F], 1),
+            vec![i32::MIN, 0, 0x7F << 24]
+        );
+        assert_eq!(
+            decode(&[0x7F, 0xFF, 0x80, 0x00], 2),
+            vec![0x7FFF << 16, i32::MIN]
+        );
+        assert_eq!(decode(&[0x12, 0x34, 0x56], 3), vec![0x123456 << 8]);
+    }
+
+    #[test]
+    fn test_aiff_little_endian() {
+        let samples: Vec<i32> = PcmSamples::aiff(&[0x34u8, 0x12][..], 2, 2, ByteOrder::Little)
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(samples, vec![0x1234 << 16]);
+    }
+
+    #[test]
+    fn test_float_byte_order() {
+        let le: Vec<f32> = FloatSamples::new(&0.5f32.to_le_bytes()[..], 4, ByteOrder::Little)
+            .map(Result::unwrap)
+            .collect();
+        let be: Vec<f32> = FloatSamples::new(&0.5f32.to_be_bytes()[..], 4, ByteOrder::Big)
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(le, vec![0.5]);
+        assert_eq!(be, vec![0.5]);
+    }
+
+    #[test]
+    fn test_truncated_data() {
+        let mut samples = PcmSamples::new(&[0x00u8, 0x01, 0x02][..], 2, 4).unwrap();
+        assert!(matches!(samples.next(), Some(Ok(_))));
+        assert!(matches!(
+            samples.next(),
+            Some(Err(hound::Error::IoError(_)))
+        ));
+        assert!(samples.next().is_none());
+    }
+
+    #[test]
+    fn test_invalid_container() {
+        assert!(PcmSamples::new(&[0u8; 0][..], 5, 0).is_err());
+    }
+}
@@ -0,0 +1,217 @@
+use std::io::{Cursor, Read};
+
+use hound::WavReader;
+use serde::Serialize;
+
+use crate::audio::{aiff, fallback_frames, flac, needs_fallback, riff};
+use crate::error::WaveemapiError;
+use crate::id3::TrackTags;
+
+/// What an uploaded file holds, as reported by `/api/probe`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Probe {
+    /// `wav`, `rf64`, `aiff` or `flac`.
+    pub container: &'static str,
+    /// The WAVE format tag, looking through `WAVE_FORMAT_EXTENSIBLE`.
+    pub format_tag: Option<u16>,
+    pub channels: u16,
+    pub sample_rate: u32,
+    pub bits_per_sample: u16,
+    /// `int`, `float`, `alaw`, `mulaw` or `ima_adpcm`.
+    pub sample_format: &'static str,
+    pub duration_seconds: Option<f64>,
+    /// Sample frames per channel, unknown for FLAC that does not record it.
+    pub frames: Option<u64>,
+    /// Size of the sample data, unknown for FLAC.
+    pub data_bytes: Option<u64>,
+    /// `LIST`/`INFO` fields of a WAV, as stored.
+    pub info: Option<riff::InfoChunk>,
+    /// Broadcast Wave `bext` fields of a WAV, as stored.
+    pub bext: Option<riff::BextChunk>,
+    /// Embedded metadata, as it would be written to the ID3 tag.
+    pub tags: TrackTags,
+}
+
+/// Describes a WAV, AIFF or FLAC stream without decoding its samples. WAV
+/// input is read to the end for metadata following the sample data.
+pub fn probe<R: Read>(mut reader: R) -> Result<Probe, WaveemapiError> {
+    let (mut embedded, header) = riff::scan_header(&mut reader)?;
+    if aiff::is_aiff(&header) {
+        return probe_aiff(Cursor::new(header).chain(reader));
+    }
+    if flac::is_flac(&header) {
+        return probe_flac(Cursor::new(header).chain(reader));
+    }
+    riff::scan_trailer(&mut reader, &mut embedded)?;
+    probe_wav(&embedded, &header)
+}
+
+fn probe_wav(embedded: &riff::WavMetadata, header: &[u8]) -> Result<Probe, WaveemapiError> {
+    let (Some(fmt), Some(data)) = (embedded.fmt, embedded.data) else {
+        // Let hound explain what is wrong with the header.
+        WavReader::new(Cursor::new(header))?;
+        return Err(WaveemapiError::Hound(hound::Error::FormatError(
+            "missing data chunk",
+        )));
+    };
+    let (bits_per_sample, sample_format, frames) = if embedded.rf64 || needs_fallback(&fmt) {
+        let sample_format = match fmt.format() {
+            riff::WAVE_FORMAT_PCM => "int",
+            riff::WAVE_FORMAT_IEEE_FLOAT => "float",
+            riff::WAVE_FORMAT_ALAW => "alaw",
+            riff::WAVE_FORMAT_MULAW => "mulaw",
+            riff::WAVE_FORMAT_IMA_ADPCM => "ima_adpcm",
+            _ => return Err(WaveemapiError::Hound(hound::Error::Unsupported)),
+        };
+        let frames = fallback_frames(&fmt, data.len, embedded.fact_frames);
+        (fmt.valid_bits(), sample_format, frames)
+    } else {
+        let reader = WavReader::new(Cursor::new(header))?;
+        let spec = reader.spec();
+        let sample_format = match spec.sample_format {
+            hound::SampleFormat::Int => "int",
+            hound::SampleFormat::Float => "float",
+        };
+        (
+            spec.bits_per_sample,
+            sample_format,
+            reader.duration() as u64,
+        )
+    };
+    Ok(Probe {
+        container: if embedded.rf64 { "rf64" } else { "wav" },
+        format_tag: Some(fmt.format()),
+        channels: fmt.channels,
+        sample_rate: fmt.sample_rate,
+        bits_per_sample,
+        sample_format,
+        duration_seconds: duration(Some(frames), fmt.sample_rate),
+        frames: Some(frames),
+        data_bytes: Some(data.len),
+        info: (embedded.info != riff::InfoChunk::default()).then(|| embedded.info.clone()),
+        bext: embedded.bext.clone(),
+        tags: embedded.to_tags(),
+    })
+}
+
+fn probe_aiff<R: Read>(mut reader: R) -> Result<Probe, WaveemapiError> {
+    let header = aiff::read_header(&mut reader)?;
+    let frames = header.frames as u64;
+    Ok(Probe {
+        container: "aiff",
+        format_tag: None,
+        channels: header.channels,
+        sample_rate: header.sample_rate,
+        bits_per_sample: header.bits_per_sample,
+        sample_format: match header.encoding {
+            aiff::Encoding::Int(_) => "int",
+            aiff::Encoding::Float32 => "float",
+        },
+        duration_seconds: duration(Some(frames), header.sample_rate),
+        frames: Some(frames),
+        data_bytes: Some(header.data_len),
+        info: None,
+        bext: None,
+        tags: header.to_tags(),
+    })
+}
+
+fn probe_flac<R: Read>(reader: R) -> Result<Probe, WaveemapiError> {
+    let reader = claxon::FlacReader::new(reader).map_err(flac::to_hound)?;
+    let info = reader.streaminfo();
+    Ok(Probe {
+        container: "flac",
+        format_tag: None,
+        channels: info.channels as u16,
+        sample_rate: info.sample_rate,
+        bits_per_sample: info.bits_per_sample as u16,
+        sample_format: "int",
+        duration_seconds: duration(info.samples, info.sample_rate),
+        frames: info.samples,
+        data_bytes: None,
+        info: None,
+        bext: None,
+        tags: flac::to_tags(reader.tags()),
+    })
+}
+
+fn duration(frames: Option<u64>, sample_rate: u32) -> Option<f64> {
+    frames
+        .filter(|_| sample_rate > 0)
+        .map(|frames| frames as f64 / sample_rate as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::SAMPLE_PATH;
+    use std::fs::File;
+
+    fn probe_sample(name: &str) -> Probe {
+        probe(File::open(format!("{}{}", SAMPLE_PATH, name)).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_probe_wav() {
+        let probe = probe_sample("untitledi16.wav");
+        assert_eq!(probe.container, "wav");
+        assert_eq!(probe.format_tag, Some(riff::WAVE_FORMAT_PCM));
+        assert_eq!(probe.channels, 2);
+        assert_eq!(probe.sample_rate, 48000);
+        assert_eq!(probe.bits_per_sample, 16);
+        assert_eq!(probe.sample_format, "int");
+        assert_eq!(probe.data_bytes, Some(2007188));
+        assert_eq!(probe.frames, Some(2007188 / 4));
+        assert!((probe.duration_seconds.unwrap() - 10.454).abs() < 0.001);
+    }
+
+    #[test]
+    fn test_probe_trailing_metadata() {
+        // LIST/INFO follows the samples in this file.
+        let probe = probe_sample("infobexti16.wav");
+        assert_eq!(probe.tags.title.as_deref(), Some("Field Take 3"));
+        assert_eq!(probe.tags.year.as_deref(), Some("2024"));
+        let info = probe.info.unwrap();
+        assert_eq!(info.title.as_deref(), Some("Field Take 3"));
+        assert_eq!(info.date.as_deref(), Some("2024-05-01"));
+        let bext = probe.bext.unwrap();
+        assert_eq!(bext.originator.as_deref(), Some("FieldRec F8"));
+        assert_eq!(bext.originator_reference.as_deref(), Some("REF123"));
+        assert_eq!(bext.origination_time.as_deref(), Some("12:30:00"));
+    }
+
+    #[test]
+    fn test_probe_fallback_formats() {
+        let probe = probe_sample("sineima.wav");
+        assert_eq!(probe.sample_format, "ima_adpcm");
+        assert_eq!(probe.bits_per_sample, 4);
+        assert_eq!(probe.frames, Some(22044));
+        assert_eq!(probe_sample("sinealaw.wav").sample_format, "alaw");
+        assert_eq!(probe_sample("sinei20.wav").bits_per_sample, 20);
+        let probe = probe_sample("sinebw64f32.wav");
+        assert_eq!(probe.container, "rf64");
+        assert_eq!(probe.sample_format, "float");
+        assert_eq!(probe.duration_seconds, Some(0.5));
+    }
+
+    #[test]
+    fn test_probe_aiff_and_flac() {
+        let probe = probe_sample("sinei16.aiff");
+        assert_eq!(probe.container, "aiff");
+        assert_eq!(probe.format_tag, None);
+        assert_eq!(probe.tags.title.as_deref(), Some("Sine"));
+        assert_eq!((probe.info, probe.bext), (None, None));
+        let probe = probe_sample("sinei24.flac");
+        assert_eq!(probe.container, "flac");
+        assert_eq!(probe.bits_per_sample, 24);
+        assert_eq!(probe.data_bytes, None);
+    }
+
+    #[test]
+    fn test_probe_not_audio() {
+        assert!(matches!(
+            probe(&b"not a wav file at all"[..]),
+            Err(WaveemapiError::Hound(hound::Error::FormatError(_)))
+        ));
+    }
+}
@@ -0,0 +1,303 @@
+use crate::error::WaveemapiError;
+
+/// Sample rates LAME can encode (MPEG-1, MPEG-2 and MPEG-2.5).
+pub const SUPPORTED_RATES: [u32; 9] =
+    [8000, 11025, 12000, 16000, 22050, 24000, 32000, 44100, 48000];
+
+/// Filter half width in input samples when upsampling. Downsampling widens
+/// the filter by the ratio so the transition band stays equally steep.
+const HALF_TAPS: usize = 32;
+/// Kernel table resolution per input sample, linearly interpolated.
+const PHASES: usize = 512;
+/// Passband edge relative to the output Nyquist frequency.
+const ROLLOFF: f64 = 0.945;
+const KAISER_BETA: f64 = 8.6;
+/// Consumed input frames are dropped in batches of this size.
+const COMPACT_FRAMES: usize = 4096;
+
+/// Validates a requested output rate.
+pub fn check_sample_rate(rate: Option<u32>) -> Result<Option<u32>, WaveemapiError> {
+    match rate {
+        Some(rate) if !SUPPORTED_RATES.contains(&rate) => {
+            Err(WaveemapiError::InvalidOptions(format!(
+                "Unsupported sample_rate {}, expected one of {:?}",
+                rate, SUPPORTED_RATES
+            )))
+        }
+        _ => Ok(rate),
+    }
+}
+
+/// The supported rate closest to `rate`, preferring the higher one on a tie.
+pub fn nearest_supported_rate(rate: u32) -> u32 {
+    *SUPPORTED_RATES
+        .iter()
+        .rev()
+        .min_by_key(|&&r| r.abs_diff(rate))
+        .unwrap()
+}
+
+/// Band limited sample rate conversion using a Kaiser windowed sinc.
+pub struct Resampler<I> {
+    samples: I,
+    channels: usize,
+    /// Input frames advanced per output frame.
+    step: f64,
+    half: usize,
+    /// Kernel sampled `PHASES` times per input sample from 0 to `half`.
+    kernel: Vec<f32>,
+    /// Buffered interleaved input frames, starting at frame `start`.
+    buffer: Vec<f32>,
+    start: i64,
+    /// Total number of input frames, known once the input is exhausted.
+    total: Option<i64>,
+    out_index: u64,
+    out: Vec<f32>,
+    pos: usize,
+}
+
+impl<I> Resampler<I> {
+    pub fn new(samples: I, channels: usize, from: u32, to: u32) -> Self {
+        let step = from as f64 / to as f64;
+        let scale = step.max(1.0);
+        let half = (HALF_TAPS as f64 * scale).ceil() as usize;
+        // Cutoff in cycles per input sample, below both Nyquist frequencies.
+        let cutoff = 0.5 * ROLLOFF / scale;
+        let kernel = (0..=half * PHASES + 1)
+            .map(|i| {
+                let x = i as f64 / PHASES as f64;
+                let window = kaiser(x / half as f64);
+                (2.0 * cutoff * sinc(2.0 * cutoff * x) * window) as f32
+            })
+            .collect();
+        Resampler {
+            samples,
+            channels,
+            step,
+            half,
+            kernel,
+            buffer: Vec::new(),
+            start: 0,
+            total: None,
+            out_index: 0,
+            out: vec![0.0; channels],
+            pos: channels,
+        }
+    }
+
+    fn weight(&self, distance: f64) -> f32 {
+        let x = distance.abs() * PHASES as f64;
+        let i = x as usize;
+        if i + 1 >= self.kernel.len() {
+            return 0.0;
+        }
+        let frac = (x - i as f64) as f32;
+        self.kernel[i] + (self.kernel[i + 1] - self.kernel[i]) * frac
+    }
+
+    fn buffered_frames(&self) -> i64 {
+        (self.buffer.len() / self.channels) as i64
+    }
+}
+
+impl<I: Iterator<Item = Result<f32, WaveemapiError>>> Resampler<I> {
+    /// Buffers input up to and including frame `last`, or until the input ends.
+    fn fill(&mut self, last: i64) -> Result<(), WaveemapiError> {
+        while self.total.is_none() && self.start + self.buffered_frames() <= last {
+            for channel in 0..self.channels {
+                match self.samples.next() {
+                    Some(sample) => self.buffer.push(sample?),
+                    None => {
+                        // Drop a partial trailing frame.
+                        self.buffer.truncate(self.buffer.len() - channel);
+                        self.total = Some(self.start + self.buffered_frames());
+                        break;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn next_frame(&mut self) -> Result<bool, WaveemapiError> {
+        let t = self.out_index as f64 * self.step;
+        let center = t.floor() as i64;
+        let first = center - self.half as i64 + 1;
+        let last = center + self.half as i64;
+        self.fill(last)?;
+        if let Some(total) = self.total
+            && t >= total as f64
+        {
+            return Ok(false);
+        }
+        let consumed = first - self.start;
+        if consumed >= COMPACT_FRAMES as i64 {
+            self.buffer.drain(..consumed as usize * self.channels);
+            self.start = first;
+        }
+        self.out.iter_mut().for_each(|s| *s = 0.0);
+        let from = first.max(self.start);
+        let to = last.min(self.start + self.buffered_frames() - 1);
+        for frame in from..=to {
+            let weight = self.weight(t - frame as f64);
+            let offset = (frame - self.start) as usize * self.channels;
+            for (out, sample) in self
+                .out
+                .iter_mut()
+                .zip(&self.buffer[offset..offset + self.channels])
+            {
+                *out += weight * sample;
+            }
+        }
+        self.out_index += 1;
+        Ok(true)
+    }
+}
+
+impl<I: Iterator<Item = Result<f32, WaveemapiError>>> Iterator for Resampler<I> {
+    type Item = Result<f32, WaveemapiError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.pos >= self.channels {
+            match self.next_frame() {
+                Ok(true) => self.pos = 0,
+                Ok(false) => return None,
+                Err(e) => return Some(Err(e)),
+            }
+        }
+        self.pos += 1;
+        Some(Ok(self.out[self.pos - 1]))
+    }
+}
+
+fn sinc(x: f64) -> f64 {
+    if x.abs() < 1e-12 {
+        1.0
+    } else {
+        let px = std::f64::consts::PI * x;
+        px.sin() / px
+    }
+}
+
+/// Kaiser window over `x` in -1.0..=1.0.
+fn kaiser(x: f64) -> f64 {
+    if x.abs() > 1.0 {
+        return 0.0;
+    }
+    bessel_i0(KAISER_BETA * (1.0 - x * x).sqrt()) / bessel_i0(KAISER_BETA)
+}
+
+/// Zeroth order modified Bessel function of the first kind, by its power series.
+fn bessel_i0(x: f64) -> f64 {
+    let mut sum = 1.0;
+    let mut term = 1.0;
+    let half = x / 2.0;
+    for k in 1..50 {
+        term *= (half / k as f64) * (half / k as f64);
+        sum += term;
+        if term < sum * 1e-12 {
+            break;
+        }
+    }
+    sum
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resample(samples: &[f32], channels: usize, from: u32, to: u32) -> Vec<f32> {
+        Resampler::new(samples.iter().map(|&s| Ok(s)), channels, from, to)
+            .map(Result::unwrap)
+            .collect()
+    }
+
+    fn sine(freq: f64, rate: u32, frames: usize) -> Vec<f32> {
+        (0..frames)
+            .map(|i| (2.0 * std::f64::consts::PI * freq * i as f64 / rate as f64).sin() as f32)
+            .collect()
+    }
+
+    fn zero_crossings(samples: &[f32]) -> usize {
+        samples
+            .windows(2)
+            .filter(|w| (w[0] < 0.0) != (w[1] < 0.0))
+            .count()
+    }
+
+    #[test]
+    fn test_nearest_rate() {
+        assert_eq!(nearest_supported_rate(96000), 48000);
+        assert_eq!(nearest_supported_rate(192000), 48000);
+        assert_eq!(nearest_supported_rate(37800), 32000);
+        assert_eq!(nearest_supported_rate(44056), 44100);
+        assert_eq!(nearest_supported_rate(4000), 8000);
+    }
+
+    #[test]
+    fn test_check_rate() {
+        assert_eq!(check_sample_rate(None).unwrap(), None);
+        assert_eq!(check_sample_rate(Some(22050)).unwrap(), Some(22050));
+        assert!(check_sample_rate(Some(96000)).is_err());
+    }
+
+    #[test]
+    fn test_output_length() {
+        let input = vec![0.0; 9600];
+        assert_eq!(resample(&input, 1, 96000, 48000).len(), 4800);
+        assert_eq!(resample(&input, 2, 96000, 48000).len(), 4800);
+        assert_eq!(resample(&input, 1, 44100, 48000).len(), 10449);
+    }
+
+    #[test]
+    fn test_dc_gain() {
+        for (from, to) in [
+            (96000, 48000),
+            (44100, 48000),
+            (37800, 32000),
+            (8000, 48000),
+        ] {
+            let output = resample(&vec![0.5; 8000], 1, from, to);
+            let middle = &output[output.len() / 4..output.len() * 3 / 4];
+            for sample in middle {
+                assert!(
+                    (sample - 0.5).abs() < 1e-3,
+                    "{} -> {}: {}",
+                    from,
+                    to,
+                    sample
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_preserves_frequency() {
+        let input = sine(1000.0, 96000, 96000);
+        let output = resample(&input, 1, 96000, 44100);
+        assert_eq!(output.len(), 44100);
+        let crossings = zero_crossings(&output) as i64;
+        assert!((crossings - 2000).abs() <= 2, "{} crossings", crossings);
+    }
+
+    #[test]
+    fn test_removes_aliases() {
+        // 30 kHz cannot be represented at 48 kHz and must not fold back.
+        let input = sine(30000.0, 96000, 96000);
+        let output = resample(&input, 1, 96000, 48000);
+        let peak = output[1000..output.len() - 1000]
+            .iter()
+            .fold(0.0f32, |peak, s| peak.max(s.abs()));
+        assert!(peak < 1e-3, "alias peak {}", peak);
+    }
+
+    #[test]
+    fn test_channels_stay_separate() {
+        let input: Vec<f32> = (0..4000).flat_map(|_| [0.25, -0.75]).collect();
+        let output = resample(&input, 2, 48000, 32000);
+        let middle = output.len() / 4 * 2;
+        let (left, right) = (output[middle], output[middle + 1]);
+        assert!((left - 0.25).abs() < 1e-3);
+        assert!((right + 0.75).abs() < 1e-3);
+    }
+}
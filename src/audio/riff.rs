@@ -0,0 +1,576 @@
+use std::io::{self, Read, Seek, SeekFrom};
+
+use serde::Serialize;
+
+use crate::id3::{TrackTags, is_track, is_year};
+
+/// Metadata chunks larger than this are skipped rather than read into memory.
+const MAX_METADATA_CHUNK: u64 = 1024 * 1024;
+/// At most this much of a non-seekable stream is buffered looking for `data`.
+const MAX_HEADER_BYTES: u64 = 16 * 1024 * 1024;
+
+pub const WAVE_FORMAT_PCM: u16 = 0x0001;
+pub const WAVE_FORMAT_IEEE_FLOAT: u16 = 0x0003;
+pub const WAVE_FORMAT_ALAW: u16 = 0x0006;
+pub const WAVE_FORMAT_MULAW: u16 = 0x0007;
+pub const WAVE_FORMAT_IMA_ADPCM: u16 = 0x0011;
+pub const WAVE_FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+
+/// Chunks found while scanning a RIFF/WAVE stream: the `fmt ` chunk, where
+/// the `data` chunk lives, text fields from `LIST`/`INFO` and Broadcast
+/// Wave `bext` chunks, and cue points with their `LIST`/`adtl` labels.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct WavMetadata {
+    pub fmt: Option<FmtChunk>,
+    pub data: Option<DataChunk>,
+    pub info: InfoChunk,
+    pub bext: Option<BextChunk>,
+    /// Sample frames per channel from the `fact` chunk, which compressed
+    /// formats carry because the data length alone does not give it.
+    pub fact_frames: Option<u32>,
+    /// RF64 or BW64, which `hound` cannot read.
+    pub rf64: bool,
+    /// Cue points from the `cue ` chunk, in the order stored.
+    pub cues: Vec<CuePoint>,
+    /// `labl` texts from `LIST`/`adtl`, by cue point id.
+    pub labels: Vec<(u32, String)>,
+}
+
+/// A marker in the sample data.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CuePoint {
+    pub id: u32,
+    /// Sample frame the marker is at.
+    pub frame: u32,
+}
+
+/// A cue point with its label, as returned by [`WavMetadata::markers`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Marker {
+    pub frame: u64,
+    pub label: Option<String>,
+}
+
+/// The `WAVEFORMATEX`/`WAVEFORMATEXTENSIBLE` fields we care about.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct FmtChunk {
+    pub format_tag: u16,
+    pub channels: u16,
+    pub sample_rate: u32,
+    pub block_align: u16,
+    pub bits_per_sample: u16,
+    /// `wValidBitsPerSample`, only present for `WAVE_FORMAT_EXTENSIBLE`.
+    pub valid_bits: Option<u16>,
+    /// `dwChannelMask`, only present for `WAVE_FORMAT_EXTENSIBLE`.
+    pub channel_mask: Option<u32>,
+    /// The format tag embedded in the first two bytes of the `SubFormat` GUID.
+    pub sub_format: Option<u16>,
+}
+
+impl FmtChunk {
+    /// The format tag, looking through `WAVE_FORMAT_EXTENSIBLE` to its sub format.
+    pub fn format(&self) -> u16 {
+        match self.sub_format {
+            Some(sub_format) if self.format_tag == WAVE_FORMAT_EXTENSIBLE => sub_format,
+            _ => self.format_tag,
+        }
+    }
+
+    /// Bytes each sample occupies in the data chunk.
+    pub fn container_bytes(&self) -> u16 {
+        self.block_align / self.channels.max(1)
+    }
+
+    pub fn valid_bits(&self) -> u16 {
+        self.valid_bits
+            .filter(|&b| b > 0)
+            .unwrap_or(self.bits_per_sample)
+    }
+}
+
+/// Position and length of the sample data.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct DataChunk {
+    pub offset: u64,
+    pub len: u64,
+}
+
+impl DataChunk {
+    /// Length including the pad byte that keeps the next chunk word aligned.
+    pub fn padded_len(&self) -> u64 {
+        self.len + (self.len & 1)
+    }
+}
+
+/// The subset of `LIST`/`INFO` subchunks that have an ID3 counterpart.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+pub struct InfoChunk {
+    pub title: Option<String>,   // INAM
+    pub artist: Option<String>,  // IART
+    pub album: Option<String>,   // IPRD
+    pub comment: Option<String>, // ICMT
+    pub date: Option<String>,    // ICRD
+    pub genre: Option<String>,   // IGNR
+    pub track: Option<String>,   // ITRK or IPRT
+}
+
+/// Fixed-width text fields at the start of a `bext` chunk (EBU Tech 3285).
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+pub struct BextChunk {
+    pub description: Option<String>,
+    pub originator: Option<String>,
+    pub originator_reference: Option<String>,
+    pub origination_date: Option<String>,
+    pub origination_time: Option<String>,
+}
+
+impl WavMetadata {
+    /// Maps the metadata onto ID3 fields. `INFO` wins over `bext` where both carry a value.
+    pub fn to_tags(&self) -> TrackTags {
+        let bext = self.bext.clone().unwrap_or_default();
+        let date = self
+            .info
+            .date
+            .as_deref()
+            .or(bext.origination_date.as_deref());
+        TrackTags {
+            title: self.info.title.clone(),
+            artist: self.info.artist.clone().or(bext.originator),
+            album: self.info.album.clone(),
+            year: date.and_then(year_of),
+            comment: self.info.comment.clone().or(bext.description),
+            track: self.info.track.clone().filter(|t| is_track(t)),
+            genre: self.info.genre.clone(),
+            cover: None,
+        }
+    }
+
+    /// Cue points in sample order, each with its label if it has one.
+    pub fn markers(&self) -> Vec<Marker> {
+        let mut markers: Vec<Marker> = self
+            .cues
+            .iter()
+            .map(|cue| Marker {
+                frame: cue.frame as u64,
+                label: self
+                    .labels
+                    .iter()
+                    .find(|(id, _)| *id == cue.id)
+                    .map(|(_, label)| label.clone()),
+            })
+            .collect();
+        markers.sort_by_key(|marker| marker.frame);
+        markers
+    }
+}
+
+/// Reads every chunk header in a RIFF/WAVE, RF64 or BW64 stream, collecting metadata and
+/// seeking over everything else. Non-WAVE input yields empty metadata so the
+/// decoder can report the actual format error.
+pub fn scan_metadata<R: Read + Seek>(reader: &mut R) -> io::Result<WavMetadata> {
+    scan(reader, false, |reader, len| {
+        reader.seek(SeekFrom::Current(len as i64)).map(|_| ())
+    })
+}
+
+/// Reads chunks up to the start of the sample data for input that cannot
+/// seek, such as a request body. Returns the metadata along with every byte
+/// consumed, so a decoder can be handed the header again. Metadata after the
+/// `data` chunk is not seen.
+pub fn scan_header<R: Read>(reader: &mut R) -> io::Result<(WavMetadata, Vec<u8>)> {
+    let mut recording = Recording {
+        reader: reader.take(MAX_HEADER_BYTES),
+        bytes: Vec::new(),
+    };
+    let metadata = scan(&mut recording, true, |reader, len| {
+        io::copy(&mut reader.take(len), &mut io::sink()).map(|_| ())
+    })?;
+    Ok((metadata, recording.bytes))
+}
+
+/// Keeps a copy of everything read through it.
+struct Recording<R> {
+    reader: R,
+    bytes: Vec<u8>,
+}
+
+impl<R: Read> Read for Recording<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.reader.read(buf)?;
+        self.bytes.extend_from_slice(&buf[..n]);
+        Ok(n)
+    }
+}
+
+fn scan<R: Read>(
+    reader: &mut R,
+    stop_at_data: bool,
+    skip: impl FnMut(&mut R, u64) -> io::Result<()>,
+) -> io::Result<WavMetadata> {
+    let mut metadata = WavMetadata::default();
+    let mut header = [0u8; 12];
+    if read_full(reader, &mut header)? < header.len()
+        || !matches!(&header[..4], b"RIFF" | b"RF64" | b"BW64")
+        || &header[8..] != b"WAVE"
+    {
+        return Ok(metadata);
+    }
+    metadata.rf64 = &header[..4] != b"RIFF";
+    scan_chunks(
+        reader,
+        &mut metadata,
+        header.len() as u64,
+        stop_at_data,
+        skip,
+    )?;
+    Ok(metadata)
+}
+
+/// Continues after `scan_header` past the sample data, for metadata such as
+/// `LIST`/`INFO` that follows it. The samples are read and discarded.
+pub fn scan_trailer<R: Read>(reader: &mut R, metadata: &mut WavMetadata) -> io::Result<()> {
+    let Some(data) = metadata.data else {
+        return Ok(());
+    };
+    io::copy(&mut reader.take(data.padded_len()), &mut io::sink())?;
+    scan_after_data(reader, metadata)
+}
+
+/// Like `scan_trailer`, for a reader that is already past the sample data.
+pub fn scan_after_data<R: Read>(reader: &mut R, metadata: &mut WavMetadata) -> io::Result<()> {
+    let Some(data) = metadata.data else {
+        return Ok(());
+    };
+    let skip =
+        |reader: &mut R, len: u64| io::copy(&mut reader.take(len), &mut io::sink()).map(|_| ());
+    scan_chunks(
+        reader,
+        metadata,
+        data.offset + data.padded_len(),
+        false,
+        skip,
+    )
+}
+
+fn scan_chunks<R: Read>(
+    reader: &mut R,
+    metadata: &mut WavMetadata,
+    mut offset: u64,
+    stop_at_data: bool,
+    mut skip: impl FnMut(&mut R, u64) -> io::Result<()>,
+) -> io::Result<()> {
+    // RF64 keeps the real `data` size in `ds64` and writes 0xFFFFFFFF in its place.
+    let mut ds64_data_len = None;
+    let mut chunk_header = [0u8; 8];
+    while read_full(reader, &mut chunk_header)? == chunk_header.len() {
+        let id = &chunk_header[..4];
+        let mut len = u32::from_le_bytes(chunk_header[4..].try_into().unwrap()) as u64;
+        if id == b"data" && len == u32::MAX as u64 {
+            len = ds64_data_len.unwrap_or(len);
+        }
+        let padded = len + (len & 1);
+        offset += chunk_header.len() as u64;
+        match id {
+            b"data" => {
+                metadata.data = Some(DataChunk { offset, len });
+                if stop_at_data {
+                    break;
+                }
+                skip(reader, padded)?;
+            }
+            b"fmt " | b"LIST" | b"bext" | b"fact" | b"ds64" | b"cue "
+                if len <= MAX_METADATA_CHUNK =>
+            {
+                let mut body = vec![0u8; len as usize];
+                if read_full(reader, &mut body)? < body.len() {
+                    break;
+                }
+                if id == b"fmt " {
+                    metadata.fmt = parse_fmt(&body);
+                } else if id == b"bext" {
+                    metadata.bext = Some(parse_bext(&body));
+                } else if id == b"fact" {
+                    metadata.fact_frames = body
+                        .get(..4)
+                        .map(|b| u32::from_le_bytes(b.try_into().unwrap()));
+                } else if id == b"ds64" && metadata.rf64 {
+                    ds64_data_len = body
+                        .get(8..16)
+                        .map(|b| u64::from_le_bytes(b.try_into().unwrap()));
+                } else if id == b"cue " {
+                    metadata.cues = parse_cue(&body);
+                } else if body.starts_with(b"INFO") {
+                    parse_info(&body[4..], &mut metadata.info);
+                } else if body.starts_with(b"adtl") {
+                    parse_adtl(&body[4..], &mut metadata.labels);
+                }
+                skip(reader, padded - len)?;
+            }
+            _ => skip(reader, padded)?,
+        }
+        offset += padded;
+    }
+    Ok(())
+}
+
+fn parse_fmt(body: &[u8]) -> Option<FmtChunk> {
+    let u16_at = |at: usize| Some(u16::from_le_bytes(body.get(at..at + 2)?.try_into().ok()?));
+    let u32_at = |at: usize| Some(u32::from_le_bytes(body.get(at..at + 4)?.try_into().ok()?));
+    let mut fmt = FmtChunk {
+        format_tag: u16_at(0)?,
+        channels: u16_at(2)?,
+        sample_rate: u32_at(4)?,
+        block_align: u16_at(12)?,
+        bits_per_sample: u16_at(14)?,
+        ..FmtChunk::default()
+    };
+    if fmt.format_tag == WAVE_FORMAT_EXTENSIBLE && u16_at(16).unwrap_or(0) >= 22 {
+        fmt.valid_bits = u16_at(18);
+        fmt.channel_mask = u32_at(20);
+        fmt.sub_format = u16_at(24);
+    }
+    Some(fmt)
+}
+
+fn parse_info(mut body: &[u8], info: &mut InfoChunk) {
+    while body.len() >= 8 {
+        let id = &body[..4];
+        let len = u32::from_le_bytes(body[4..8].try_into().unwrap()) as usize;
+        let end = (8 + len).min(body.len());
+        let value = text(&body[8..end]);
+        match id {
+            b"INAM" => info.title = value,
+            b"IART" => info.artist = value,
+            b"IPRD" => info.album = value,
+            b"ICMT" => info.comment = value,
+            b"ICRD" => info.date = value,
+            b"IGNR" => info.genre = value,
+            b"ITRK" | b"IPRT" => info.track = value,
+            _ => {}
+        }
+        body = &body[(end + (len & 1)).min(body.len())..];
+    }
+}
+
+/// Each cue point is 24 bytes, the id first and the sample offset last.
+fn parse_cue(body: &[u8]) -> Vec<CuePoint> {
+    let u32_at = |at: usize| Some(u32::from_le_bytes(body.get(at..at + 4)?.try_into().ok()?));
+    let count = u32_at(0).unwrap_or(0) as usize;
+    (0..count)
+        .map_while(|i| {
+            let at = 4 + i * 24;
+            Some(CuePoint {
+                id: u32_at(at)?,
+                frame: u32_at(at + 20)?,
+            })
+        })
+        .collect()
+}
+
+/// Collects the `labl` subchunks, a cue point id followed by text.
+fn parse_adtl(mut body: &[u8], labels: &mut Vec<(u32, String)>) {
+    while body.len() >= 8 {
+        let id = &body[..4];
+        let len = u32::from_le_bytes(body[4..8].try_into().unwrap()) as usize;
+        let end = (8 + len).min(body.len());
+        if id == b"labl" && end >= 12 {
+            let cue = u32::from_le_bytes(body[8..12].try_into().unwrap());
+            if let Some(label) = text(&body[12..end]) {
+                labels.push((cue, label));
+            }
+        }
+        body = &body[(end + (len & 1)).min(body.len())..];
+    }
+}
+
+fn parse_bext(body: &[u8]) -> BextChunk {
+    let field = |start: usize, len: usize| body.get(start..start + len).and_then(text);
+    BextChunk {
+        description: field(0, 256),
+        originator: field(256, 32),
+        originator_reference: field(288, 32),
+        origination_date: field(320, 10),
+        origination_time: field(330, 8),
+    }
+}
+
+/// RIFF text is NUL padded and usually ASCII, but DAWs write both UTF-8 and
+/// Latin-1, so fall back to the latter when the bytes are not valid UTF-8.
+pub(super) fn text(bytes: &[u8]) -> Option<String> {
+    let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    let bytes = &bytes[..end];
+    let value = match std::str::from_utf8(bytes) {
+        Ok(s) => s.to_string(),
+        Err(_) => bytes.iter().map(|&b| b as char).collect(),
+    };
+    let value = value.trim();
+    (!value.is_empty()).then(|| value.to_string())
+}
+
+/// `ICRD` and the `bext` date both start with the year, e.g. `2024-05-01`.
+pub(super) fn year_of(date: &str) -> Option<String> {
+    let year = date.get(..4)?;
+    is_year(year).then(|| year.to_string())
+}
+
+pub(super) fn read_full<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut read = 0;
+    while read < buf.len() {
+        match reader.read(&mut buf[read..]) {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(read)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::SAMPLE_PATH;
+    use std::fs::File;
+    use std::io::Cursor;
+
+    fn scan_sample(name: &str) -> WavMetadata {
+        let mut file = File::open(format!("{}{}", SAMPLE_PATH, name)).unwrap();
+        scan_metadata(&mut file).unwrap()
+    }
+
+    #[test]
+    fn test_scan_info_and_bext() {
+        let metadata = scan_sample("infobexti16.wav");
+        assert_eq!(metadata.info.title.as_deref(), Some("Field Take 3"));
+        assert_eq!(metadata.info.artist.as_deref(), Some("Sound Recordist"));
+        assert_eq!(metadata.info.date.as_deref(), Some("2024-05-01"));
+        let bext = metadata.bext.unwrap();
+        assert_eq!(bext.originator.as_deref(), Some("FieldRec F8"));
+        assert_eq!(bext.origination_time.as_deref(), Some("12:30:00"));
+    }
+
+    #[test]
+    fn test_scan_plain_wav() {
+        let metadata = scan_sample("untitledi16.wav");
+        assert_eq!(metadata.info, InfoChunk::default());
+        let fmt = metadata.fmt.unwrap();
+        assert_eq!(fmt.format(), WAVE_FORMAT_PCM);
+        assert_eq!(fmt.channels, 2);
+        assert_eq!(fmt.sample_rate, 48000);
+        assert_eq!(fmt.valid_bits(), 16);
+        assert_eq!(metadata.data.unwrap().len, 2007188);
+    }
+
+    #[test]
+    fn test_scan_extensible() {
+        let fmt = scan_sample("untitledi32.wav").fmt.unwrap();
+        assert_eq!(fmt.format_tag, WAVE_FORMAT_EXTENSIBLE);
+        assert_eq!(fmt.format(), WAVE_FORMAT_PCM);
+        assert_eq!(fmt.container_bytes(), 4);
+        let fmt = scan_sample("sinei20.wav").fmt.unwrap();
+        assert_eq!(fmt.container_bytes(), 3);
+        assert_eq!(fmt.valid_bits(), 20);
+        assert_eq!(fmt.channel_mask, Some(0x3));
+    }
+
+    #[test]
+    fn test_scan_fact() {
+        let metadata = scan_sample("sineima.wav");
+        let fmt = metadata.fmt.unwrap();
+        assert_eq!(fmt.format(), WAVE_FORMAT_IMA_ADPCM);
+        assert_eq!(fmt.block_align, 512);
+        assert_eq!(metadata.fact_frames, Some(22044));
+        assert_eq!(scan_sample("untitledi16.wav").fact_frames, None);
+    }
+
+    #[test]
+    fn test_scan_rf64() {
+        let metadata = scan_sample("sinerf64i16.wav");
+        assert!(metadata.rf64);
+        assert_eq!(metadata.fmt.unwrap().channels, 2);
+        // The data size comes from ds64, not the 0xFFFFFFFF placeholder.
+        assert_eq!(metadata.data.unwrap().len, 88200);
+        let metadata = scan_sample("sinebw64f32.wav");
+        assert!(metadata.rf64);
+        assert_eq!(metadata.fmt.unwrap().format(), WAVE_FORMAT_IEEE_FLOAT);
+        assert!(!scan_sample("untitledi16.wav").rf64);
+    }
+
+    #[test]
+    fn test_scan_header() {
+        let bytes = std::fs::read(format!("{}{}", SAMPLE_PATH, "infobexti16.wav")).unwrap();
+        let mut reader = &bytes[..];
+        let (metadata, header) = scan_header(&mut reader).unwrap();
+        let data = metadata.data.unwrap();
+        assert_eq!(header.len() as u64, data.offset);
+        assert_eq!(header, bytes[..header.len()]);
+        assert_eq!(reader.len(), bytes.len() - header.len());
+        // bext precedes the samples, LIST/INFO follows them and is not seen.
+        assert!(metadata.bext.is_some());
+        assert_eq!(metadata.info, InfoChunk::default());
+        assert_eq!(data, scan_sample("infobexti16.wav").data.unwrap());
+    }
+
+    #[test]
+    fn test_scan_not_wave() {
+        assert_eq!(scan_sample("477.webp"), WavMetadata::default());
+        let mut empty = Cursor::new(Vec::new());
+        assert_eq!(scan_metadata(&mut empty).unwrap(), WavMetadata::default());
+    }
+
+    #[test]
+    fn test_to_tags() {
+        let tags = scan_sample("infobexti16.wav").to_tags();
+        assert_eq!(tags.title.as_deref(), Some("Field Take 3"));
+        assert_eq!(tags.artist.as_deref(), Some("Sound Recordist"));
+        assert_eq!(tags.year.as_deref(), Some("2024"));
+        assert_eq!(tags.comment.as_deref(), Some("Dawn chorus, north ridge"));
+    }
+
+    #[test]
+    fn test_scan_cues() {
+        let metadata = scan_sample("cuesi16.wav");
+        assert_eq!(metadata.cues.len(), 3);
+        assert_eq!(
+            metadata.markers(),
+            vec![
+                Marker {
+                    frame: 0,
+                    label: Some("Intro".to_string())
+                },
+                Marker {
+                    frame: 28000,
+                    label: Some("Middle".to_string())
+                },
+                Marker {
+                    frame: 56000,
+                    label: Some("Outro".to_string())
+                },
+            ]
+        );
+        assert!(scan_sample("untitledi16.wav").markers().is_empty());
+    }
+
+    #[test]
+    fn test_bext_fallback() {
+        let metadata = WavMetadata {
+            bext: Some(BextChunk {
+                description: Some("desc".to_string()),
+                originator: Some("orig".to_string()),
+                origination_date: Some("1999-12-31".to_string()),
+                ..BextChunk::default()
+            }),
+            ..WavMetadata::default()
+        };
+        let tags = metadata.to_tags();
+        assert_eq!(tags.artist.as_deref(), Some("orig"));
+        assert_eq!(tags.comment.as_deref(), Some("desc"));
+        assert_eq!(tags.year.as_deref(), Some("1999"));
+    }
+
+    #[test]
+    fn test_text_latin1() {
+        assert_eq!(text(b"Caf\xe9\0\0").as_deref(), Some("Café"));
+        assert_eq!(text(b"\0\0\0"), None);
+    }
+}
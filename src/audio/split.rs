@@ -0,0 +1,228 @@
+use crate::audio::riff::Marker;
+use crate::error::WaveemapiError;
+
+/// Where to cut a recording into tracks.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SplitSpec {
+    /// In the middle of each gap below `threshold_dbfs` lasting at least
+    /// `min_gap` seconds. Silence at the very start and end is no gap.
+    Silence { threshold_dbfs: f64, min_gap: f64 },
+    /// At the cue points of a WAV.
+    Cue,
+}
+
+impl SplitSpec {
+    pub const DEFAULT_THRESHOLD_DBFS: f64 = -50.0;
+    pub const DEFAULT_MIN_GAP: f64 = 2.0;
+
+    /// `None` unless `split` is set. `threshold_dbfs` and `min_gap` only apply
+    /// to `silence`.
+    pub fn new(
+        split: Option<&str>,
+        threshold_dbfs: Option<f64>,
+        min_gap: Option<f64>,
+    ) -> Result<Option<Self>, WaveemapiError> {
+        let split = split.map(|s| s.trim().to_ascii_lowercase());
+        if split.as_deref() != Some("silence") && (threshold_dbfs.is_some() || min_gap.is_some()) {
+            return Err(WaveemapiError::InvalidOptions(
+                "split_threshold and split_min_gap are only valid with split=silence".to_string(),
+            ));
+        }
+        match split.as_deref() {
+            None => Ok(None),
+            Some("cue") => Ok(Some(SplitSpec::Cue)),
+            Some("silence") => {
+                let threshold_dbfs = threshold_dbfs.unwrap_or(Self::DEFAULT_THRESHOLD_DBFS);
+                if !(-120.0..=0.0).contains(&threshold_dbfs) {
+                    return Err(WaveemapiError::InvalidOptions(
+                        "split_threshold must be between -120 and 0 dBFS".to_string(),
+                    ));
+                }
+                let min_gap = min_gap.unwrap_or(Self::DEFAULT_MIN_GAP);
+                if !min_gap.is_finite() || min_gap <= 0.0 {
+                    return Err(WaveemapiError::InvalidOptions(
+                        "split_min_gap must be more than zero seconds".to_string(),
+                    ));
+                }
+                Ok(Some(SplitSpec::Silence {
+                    threshold_dbfs,
+                    min_gap,
+                }))
+            }
+            Some(other) => Err(WaveemapiError::InvalidOptions(format!(
+                "Unknown split '{}', expected silence or cue",
+                other
+            ))),
+        }
+    }
+}
+
+/// One track, the sample frames `start..end` of the input.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Segment {
+    pub start: u64,
+    pub end: u64,
+    pub title: Option<String>,
+}
+
+/// Tracks starting at each marker within the first `total_frames`, titled by
+/// its label. Audio before the first marker is a track of its own.
+pub fn cue_segments(markers: &[Marker], total_frames: u64) -> Vec<Segment> {
+    let mut segments: Vec<Segment> = Vec::new();
+    for marker in markers.iter().filter(|m| m.frame < total_frames) {
+        match segments.last_mut() {
+            Some(last) if last.start == marker.frame => {
+                last.title = last.title.take().or(marker.label.clone());
+                continue;
+            }
+            Some(last) => last.end = marker.frame,
+            None if marker.frame > 0 => segments.push(Segment {
+                start: 0,
+                end: marker.frame,
+                title: None,
+            }),
+            None => {}
+        }
+        segments.push(Segment {
+            start: marker.frame,
+            end: total_frames,
+            title: marker.label.clone(),
+        });
+    }
+    segments
+}
+
+/// Reads `samples` to the end and cuts them where `SplitSpec::Silence` says.
+pub fn silence_segments(
+    samples: impl Iterator<Item = Result<f32, WaveemapiError>>,
+    channels: usize,
+    sample_rate: u32,
+    threshold_dbfs: f64,
+    min_gap: f64,
+) -> Result<Vec<Segment>, WaveemapiError> {
+    let threshold = 10f64.powf(threshold_dbfs / 20.0) as f32;
+    let min_frames = ((min_gap * sample_rate as f64).round() as u64).max(1);
+    let mut cuts = vec![0];
+    let mut frames = 0u64;
+    // Start of the quiet run since the last loud frame, if one was seen.
+    let mut quiet_since = None;
+    let mut heard = false;
+    let mut frame = Vec::with_capacity(channels);
+    for sample in samples {
+        frame.push(sample?);
+        if frame.len() < channels {
+            continue;
+        }
+        if frame.iter().all(|s| s.abs() < threshold) {
+            quiet_since.get_or_insert(frames);
+        } else {
+            if let Some(start) = quiet_since.take()
+                && heard
+                && frames - start >= min_frames
+            {
+                cuts.push(start + (frames - start) / 2);
+            }
+            heard = true;
+        }
+        frame.clear();
+        frames += 1;
+    }
+    cuts.push(frames);
+    Ok(cuts
+        .windows(2)
+        .filter(|cut| cut[1] > cut[0])
+        .map(|cut| Segment {
+            start: cut[0],
+            end: cut[1],
+            title: None,
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn marker(frame: u64, label: Option<&str>) -> Marker {
+        Marker {
+            frame,
+            label: label.map(str::to_string),
+        }
+    }
+
+    fn segment(start: u64, end: u64, title: Option<&str>) -> Segment {
+        Segment {
+            start,
+            end,
+            title: title.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn test_spec() {
+        assert_eq!(SplitSpec::new(None, None, None).unwrap(), None);
+        assert_eq!(
+            SplitSpec::new(Some("Cue"), None, None).unwrap(),
+            Some(SplitSpec::Cue)
+        );
+        assert_eq!(
+            SplitSpec::new(Some("silence"), None, Some(1.0)).unwrap(),
+            Some(SplitSpec::Silence {
+                threshold_dbfs: -50.0,
+                min_gap: 1.0
+            })
+        );
+        assert!(SplitSpec::new(Some("chapters"), None, None).is_err());
+        assert!(SplitSpec::new(Some("cue"), Some(-40.0), None).is_err());
+        assert!(SplitSpec::new(None, None, Some(1.0)).is_err());
+        assert!(SplitSpec::new(Some("silence"), Some(3.0), None).is_err());
+        assert!(SplitSpec::new(Some("silence"), None, Some(0.0)).is_err());
+    }
+
+    #[test]
+    fn test_cue_segments() {
+        let markers = [
+            marker(10, Some("One")),
+            marker(10, Some("Again")),
+            marker(40, None),
+            marker(100, Some("Past the end")),
+        ];
+        assert_eq!(
+            cue_segments(&markers, 50),
+            vec![
+                segment(0, 10, None),
+                segment(10, 40, Some("One")),
+                segment(40, 50, None),
+            ]
+        );
+        assert_eq!(
+            cue_segments(&[marker(0, Some("All"))], 50),
+            vec![segment(0, 50, Some("All"))]
+        );
+        assert!(cue_segments(&[], 50).is_empty());
+    }
+
+    #[test]
+    fn test_silence_segments() {
+        let mut samples = vec![0.0; 2];
+        samples.extend([0.5; 3]);
+        samples.extend([0.0; 4]);
+        samples.extend([0.5; 2]);
+        samples.extend([0.0; 1]);
+        samples.extend([0.5; 2]);
+        samples.extend([0.0; 5]);
+        let split = |min_gap| {
+            silence_segments(samples.iter().map(|&s| Ok(s)), 1, 1, -50.0, min_gap).unwrap()
+        };
+        // Only the gap of 4 is long enough, the leading and trailing silence are no gaps.
+        assert_eq!(
+            split(3.0),
+            vec![segment(0, 7, None), segment(7, samples.len() as u64, None)]
+        );
+        assert_eq!(split(1.0).len(), 3);
+        assert_eq!(split(10.0), vec![segment(0, samples.len() as u64, None)]);
+        let stereo = [0.5, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.5];
+        let segments = silence_segments(stereo.iter().map(|&s| Ok(s)), 2, 1, -50.0, 2.0).unwrap();
+        assert_eq!(segments, vec![segment(0, 2, None), segment(2, 4, None)]);
+    }
+}
@@ -0,0 +1,583 @@
+use std::cell::Cell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+use crate::error::WaveemapiError;
+
+/// A point in or length of the input, in seconds, optionally written with an
+/// `s` suffix, or in sample frames with an `smp` suffix.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Position {
+    Seconds(f64),
+    Frames(u64),
+}
+
+impl Position {
+    fn parse(name: &str, value: &str) -> Result<Self, WaveemapiError> {
+        let value = value.trim();
+        let position = match value.strip_suffix("smp") {
+            Some(frames) => frames.parse().ok().map(Position::Frames),
+            None => value
+                .strip_suffix('s')
+                .unwrap_or(value)
+                .parse()
+                .ok()
+                .filter(|seconds: &f64| seconds.is_finite() && *seconds >= 0.0)
+                .map(Position::Seconds),
+        };
+        position.ok_or_else(|| {
+            WaveemapiError::InvalidOptions(format!(
+                "Invalid {} '{}', expected seconds like 1.5 or 1.5s, or samples like 72000smp",
+                name, value
+            ))
+        })
+    }
+
+    fn frames(&self, sample_rate: u32) -> u64 {
+        match *self {
+            Position::Seconds(seconds) => (seconds * sample_rate as f64).round() as u64,
+            Position::Frames(frames) => frames,
+        }
+    }
+}
+
+/// Silence below `threshold_dbfs` lasting at least `min_duration` seconds is
+/// removed from the start and end.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SilenceSpec {
+    pub threshold_dbfs: f64,
+    pub min_duration: f64,
+}
+
+impl SilenceSpec {
+    pub const DEFAULT_THRESHOLD_DBFS: f64 = -50.0;
+    pub const DEFAULT_MIN_DURATION: f64 = 0.5;
+    /// Longest quiet run held in memory, and so the most trailing silence
+    /// that can be removed. Also the longest accepted `min_duration`.
+    pub const MAX_HELD_SECONDS: f64 = 30.0;
+
+    /// `None` unless `trim_silence` is set, which the other options require.
+    pub fn new(
+        trim_silence: Option<bool>,
+        threshold_dbfs: Option<f64>,
+        min_duration: Option<f64>,
+    ) -> Result<Option<Self>, WaveemapiError> {
+        if !trim_silence.unwrap_or(false) {
+            if threshold_dbfs.is_some() || min_duration.is_some() {
+                return Err(WaveemapiError::InvalidOptions(
+                    "silence_threshold and silence_min_duration are only valid with trim_silence"
+                        .to_string(),
+                ));
+            }
+            return Ok(None);
+        }
+        let threshold_dbfs = threshold_dbfs.unwrap_or(Self::DEFAULT_THRESHOLD_DBFS);
+        if !(-120.0..=0.0).contains(&threshold_dbfs) {
+            return Err(WaveemapiError::InvalidOptions(
+                "silence_threshold must be between -120 and 0 dBFS".to_string(),
+            ));
+        }
+        let min_duration = min_duration.unwrap_or(Self::DEFAULT_MIN_DURATION);
+        if !(0.0..=Self::MAX_HELD_SECONDS).contains(&min_duration) {
+            return Err(WaveemapiError::InvalidOptions(format!(
+                "silence_min_duration must be between 0 and {} seconds",
+                Self::MAX_HELD_SECONDS
+            )));
+        }
+        Ok(Some(SilenceSpec {
+            threshold_dbfs,
+            min_duration,
+        }))
+    }
+}
+
+/// Frames removed by a [`SilenceTrim`] from the start and end, filled in as
+/// it runs and shared with whoever reads the result.
+#[derive(Debug, Clone, Default)]
+pub struct Trimmed(Rc<Cell<Option<(u64, u64)>>>);
+
+impl Trimmed {
+    /// Frames removed from the start and end, `None` before the input ended
+    /// or when no silence trimming was asked for.
+    pub fn frames(&self) -> Option<(u64, u64)> {
+        self.0.get()
+    }
+}
+
+/// Which part of the input to keep and how to fade it in and out.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct TrimSpec {
+    pub start: Option<Position>,
+    pub end: Option<Position>,
+    pub fade_in: Option<Position>,
+    pub fade_out: Option<Position>,
+}
+
+impl TrimSpec {
+    pub fn new(
+        start: Option<&str>,
+        end: Option<&str>,
+        fade_in: Option<&str>,
+        fade_out: Option<&str>,
+    ) -> Result<Self, WaveemapiError> {
+        let parse = |name, value: Option<&str>| value.map(|v| Position::parse(name, v)).transpose();
+        Ok(TrimSpec {
+            start: parse("start", start)?,
+            end: parse("end", end)?,
+            fade_in: parse("fade_in", fade_in)?,
+            fade_out: parse("fade_out", fade_out)?,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == TrimSpec::default()
+    }
+}
+
+/// Keeps the frames from `start` up to, not including, `end`, fading the
+/// first `fade_in` and last `fade_out` of them linearly.
+pub struct Trim<I> {
+    samples: I,
+    channels: usize,
+    /// Input frames read so far.
+    position: u64,
+    start: u64,
+    end: Option<u64>,
+    fade_in: u64,
+    fade_out: u64,
+    /// Output frames, when known up front.
+    length: Option<u64>,
+    /// The last `fade_out` frames read, held back when the length is not
+    /// known until the input ends.
+    tail: VecDeque<f32>,
+    out: VecDeque<f32>,
+    done: bool,
+}
+
+impl<I> Trim<I> {
+    /// `total_frames` is the length of the input, if known.
+    pub fn new(
+        samples: I,
+        channels: usize,
+        sample_rate: u32,
+        spec: &TrimSpec,
+        total_frames: Option<u64>,
+    ) -> Result<Self, WaveemapiError> {
+        let frames = |position: Option<Position>| position.map(|p| p.frames(sample_rate));
+        let start = frames(spec.start).unwrap_or(0);
+        let end = frames(spec.end);
+        if end.is_some_and(|end| end <= start) {
+            return Err(WaveemapiError::InvalidOptions(
+                "end must be after start".to_string(),
+            ));
+        }
+        let last = match (end, total_frames) {
+            (Some(end), Some(total)) => Some(end.min(total)),
+            (end, total) => end.or(total),
+        };
+        Ok(Trim {
+            samples,
+            channels,
+            position: 0,
+            start,
+            end,
+            fade_in: frames(spec.fade_in).unwrap_or(0),
+            fade_out: frames(spec.fade_out).unwrap_or(0),
+            length: last.map(|last| last.saturating_sub(start)),
+            tail: VecDeque::new(),
+            out: VecDeque::new(),
+            done: false,
+        })
+    }
+
+    /// Output frames, when known up front.
+    pub fn length(&self) -> Option<u64> {
+        self.length
+    }
+
+    fn fade(frames: u64, length: u64) -> f32 {
+        if frames >= length {
+            1.0
+        } else {
+            frames as f32 / length as f32
+        }
+    }
+
+    /// Fades out the held back tail, now known to end the output.
+    fn flush(&mut self) {
+        let frames = (self.tail.len() / self.channels) as u64;
+        let tail: Vec<f32> = self.tail.drain(..).collect();
+        for (index, frame) in tail.chunks(self.channels).enumerate() {
+            let gain = Self::fade(frames - 1 - index as u64, self.fade_out);
+            self.out.extend(frame.iter().map(|s| s * gain));
+        }
+        self.done = true;
+    }
+}
+
+impl<I: Iterator<Item = Result<f32, WaveemapiError>>> Trim<I> {
+    fn read_frame(&mut self) -> Result<Option<Vec<f32>>, WaveemapiError> {
+        let mut frame = Vec::with_capacity(self.channels);
+        for _ in 0..self.channels {
+            match self.samples.next() {
+                Some(sample) => frame.push(sample?),
+                // A partial trailing frame is dropped.
+                None => return Ok(None),
+            }
+        }
+        self.position += 1;
+        Ok(Some(frame))
+    }
+
+    fn step(&mut self) -> Result<(), WaveemapiError> {
+        while self.position < self.start {
+            if self.read_frame()?.is_none() {
+                return Err(WaveemapiError::InvalidOptions(
+                    "start is beyond the end of the audio".to_string(),
+                ));
+            }
+        }
+        let frame = match self.end {
+            Some(end) if self.position >= end => None,
+            _ => self.read_frame()?,
+        };
+        let Some(frame) = frame else {
+            self.flush();
+            return Ok(());
+        };
+        let index = self.position - 1 - self.start;
+        let mut gain = Self::fade(index, self.fade_in);
+        match self.length {
+            Some(length) => {
+                gain *= Self::fade(length.saturating_sub(index + 1), self.fade_out);
+                self.out.extend(frame.iter().map(|s| s * gain));
+            }
+            None => {
+                self.tail.extend(frame.iter().map(|s| s * gain));
+                if self.tail.len() > self.fade_out as usize * self.channels {
+                    self.out.extend(self.tail.drain(..self.channels));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<I: Iterator<Item = Result<f32, WaveemapiError>>> Iterator for Trim<I> {
+    type Item = Result<f32, WaveemapiError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.out.is_empty() {
+            if self.done {
+                return None;
+            }
+            if let Err(e) = self.step() {
+                self.done = true;
+                return Some(Err(e));
+            }
+        }
+        self.out.pop_front().map(Ok)
+    }
+}
+
+/// Drops leading and trailing silence. Quiet frames are held back until the
+/// next loud one shows whether they are part of the audio, and a quiet run at
+/// either end is removed if it lasts at least `min_duration`. At most
+/// `MAX_HELD_SECONDS` of a run are held, older frames are let through, so
+/// only that much of a long trailing run is removed.
+pub struct SilenceTrim<I> {
+    samples: I,
+    channels: usize,
+    threshold: f32,
+    min_frames: u64,
+    max_held_frames: u64,
+    /// The last quiet frames since the last loud one.
+    quiet: VecDeque<f32>,
+    quiet_frames: u64,
+    /// Whether no loud frame has been seen yet.
+    leading: bool,
+    /// Whether the leading silence is known to be long enough to remove.
+    dropping: bool,
+    removed_start: u64,
+    trimmed: Trimmed,
+    out: VecDeque<f32>,
+    done: bool,
+}
+
+impl<I> SilenceTrim<I> {
+    pub fn new(
+        samples: I,
+        channels: usize,
+        sample_rate: u32,
+        spec: &SilenceSpec,
+        trimmed: Trimmed,
+    ) -> Self {
+        SilenceTrim {
+            samples,
+            channels,
+            threshold: 10f64.powf(spec.threshold_dbfs / 20.0) as f32,
+            min_frames: (spec.min_duration * sample_rate as f64).round() as u64,
+            max_held_frames: (SilenceSpec::MAX_HELD_SECONDS * sample_rate as f64) as u64,
+            quiet: VecDeque::new(),
+            quiet_frames: 0,
+            leading: true,
+            dropping: false,
+            removed_start: 0,
+            trimmed,
+            out: VecDeque::new(),
+            done: false,
+        }
+    }
+
+    fn finish(&mut self) {
+        // A leading run got this far only if it is too short to go.
+        let removed_end = if !self.leading && self.quiet_frames >= self.min_frames {
+            let held = (self.quiet.len() / self.channels) as u64;
+            self.quiet.clear();
+            held
+        } else {
+            self.out.extend(self.quiet.drain(..));
+            0
+        };
+        self.trimmed.0.set(Some((self.removed_start, removed_end)));
+        self.done = true;
+    }
+}
+
+impl<I: Iterator<Item = Result<f32, WaveemapiError>>> SilenceTrim<I> {
+    fn step(&mut self) -> Result<(), WaveemapiError> {
+        let mut frame = Vec::with_capacity(self.channels);
+        for _ in 0..self.channels {
+            match self.samples.next() {
+                Some(sample) => frame.push(sample?),
+                // A partial trailing frame is dropped.
+                None => {
+                    self.finish();
+                    return Ok(());
+                }
+            }
+        }
+        if frame.iter().all(|s| s.abs() < self.threshold) {
+            self.quiet_frames += 1;
+            self.quiet.extend(frame);
+            if self.leading && (self.dropping || self.quiet_frames >= self.min_frames) {
+                // Long enough to go, so the rest of it need not be kept.
+                self.removed_start += self.quiet_frames;
+                self.quiet_frames = 0;
+                self.quiet.clear();
+                self.dropping = true;
+            } else if self.quiet.len() as u64 > self.max_held_frames * self.channels as u64 {
+                self.out.extend(self.quiet.drain(..self.channels));
+            }
+            return Ok(());
+        }
+        self.leading = false;
+        self.out.extend(self.quiet.drain(..));
+        self.quiet_frames = 0;
+        self.out.extend(frame);
+        Ok(())
+    }
+}
+
+impl<I: Iterator<Item = Result<f32, WaveemapiError>>> Iterator for SilenceTrim<I> {
+    type Item = Result<f32, WaveemapiError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.out.is_empty() {
+            if self.done {
+                return None;
+            }
+            if let Err(e) = self.step() {
+                self.done = true;
+                return Some(Err(e));
+            }
+        }
+        self.out.pop_front().map(Ok)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn input(samples: &[f32]) -> impl Iterator<Item = Result<f32, WaveemapiError>> + '_ {
+        samples.iter().map(|&s| Ok(s))
+    }
+
+    fn trim(samples: &[f32], channels: usize, spec: &TrimSpec, known: bool) -> Vec<f32> {
+        let total = known.then_some((samples.len() / channels) as u64);
+        Trim::new(input(samples), channels, 10, spec, total)
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap()
+    }
+
+    fn spec(
+        start: Option<&str>,
+        end: Option<&str>,
+        fade_in: Option<&str>,
+        fade_out: Option<&str>,
+    ) -> TrimSpec {
+        TrimSpec::new(start, end, fade_in, fade_out).unwrap()
+    }
+
+    #[test]
+    fn test_parse() {
+        let spec = spec(Some("1.5"), Some("72000smp"), Some("30s"), Some(" 2 "));
+        assert_eq!(spec.start, Some(Position::Seconds(1.5)));
+        assert_eq!(spec.end, Some(Position::Frames(72000)));
+        assert_eq!(spec.fade_in, Some(Position::Seconds(30.0)));
+        assert_eq!(spec.fade_out, Some(Position::Seconds(2.0)));
+        assert_eq!(
+            Position::parse("start", "1.5s").unwrap(),
+            Position::Seconds(1.5)
+        );
+        assert!(TrimSpec::default().is_empty());
+        for invalid in ["-1", "abc", "1.5smp", "-1s", "inf", "s", "smp", ""] {
+            assert!(
+                TrimSpec::new(Some(invalid), None, None, None).is_err(),
+                "{}",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn test_start_end() {
+        let samples: Vec<f32> = (0..20).map(|i| i as f32).collect();
+        for known in [true, false] {
+            // 10 frames per second in these tests.
+            let out = trim(
+                &samples,
+                2,
+                &spec(Some("3smp"), Some("0.5"), None, None),
+                known,
+            );
+            assert_eq!(out, vec![6.0, 7.0, 8.0, 9.0]);
+            let out = trim(
+                &samples,
+                2,
+                &spec(Some("8smp"), Some("100smp"), None, None),
+                known,
+            );
+            assert_eq!(out, vec![16.0, 17.0, 18.0, 19.0]);
+        }
+        assert!(
+            Trim::new(
+                input(&samples),
+                1,
+                10,
+                &spec(Some("5smp"), Some("5smp"), None, None),
+                None
+            )
+            .is_err()
+        );
+        let mut past_end = Trim::new(
+            input(&samples),
+            1,
+            10,
+            &spec(Some("30smp"), None, None, None),
+            None,
+        )
+        .unwrap();
+        assert!(matches!(
+            past_end.next(),
+            Some(Err(WaveemapiError::InvalidOptions(_)))
+        ));
+    }
+
+    #[test]
+    fn test_fades() {
+        let samples = vec![1.0; 10];
+        for known in [true, false] {
+            let out = trim(
+                &samples,
+                1,
+                &spec(None, None, Some("4smp"), Some("0.2")),
+                known,
+            );
+            assert_eq!(
+                out,
+                vec![0.0, 0.25, 0.5, 0.75, 1.0, 1.0, 1.0, 1.0, 0.5, 0.0]
+            );
+            let out = trim(
+                &samples,
+                1,
+                &spec(Some("2smp"), Some("6smp"), None, Some("2smp")),
+                known,
+            );
+            assert_eq!(out, vec![1.0, 1.0, 0.5, 0.0]);
+        }
+        // A fade longer than the audio.
+        let out = trim(&[1.0, 1.0], 1, &spec(None, None, None, Some("4smp")), false);
+        assert_eq!(out, vec![0.25, 0.0]);
+    }
+
+    fn trim_silence(
+        samples: &[f32],
+        channels: usize,
+        spec: &SilenceSpec,
+    ) -> (Vec<f32>, Option<(u64, u64)>) {
+        let trimmed = Trimmed::default();
+        let out = SilenceTrim::new(input(samples), channels, 10, spec, trimmed.clone())
+            .collect::<Result<_, _>>()
+            .unwrap();
+        (out, trimmed.frames())
+    }
+
+    #[test]
+    fn test_silence_spec() {
+        assert_eq!(SilenceSpec::new(None, None, None).unwrap(), None);
+        let spec = SilenceSpec::new(Some(true), None, None).unwrap().unwrap();
+        assert_eq!(spec.threshold_dbfs, -50.0);
+        assert_eq!(spec.min_duration, 0.5);
+        assert!(SilenceSpec::new(Some(false), Some(-40.0), None).is_err());
+        assert!(SilenceSpec::new(Some(true), Some(3.0), None).is_err());
+        assert!(SilenceSpec::new(Some(true), None, Some(-1.0)).is_err());
+    }
+
+    #[test]
+    fn test_silence_trim() {
+        // -20 dBFS threshold, 3 frames minimum at 10 frames per second.
+        let spec = SilenceSpec::new(Some(true), Some(-20.0), Some(0.3))
+            .unwrap()
+            .unwrap();
+        let samples = [
+            0.0, 0.01, -0.05, 0.0, 0.5, 0.0, 0.0, 0.0, 0.0, -0.5, 0.0, 0.0, 0.0, 0.01,
+        ];
+        let (out, trimmed) = trim_silence(&samples, 1, &spec);
+        assert_eq!(out, samples[4..10]);
+        assert_eq!(trimmed, Some((4, 4)));
+        // Runs shorter than the minimum stay.
+        let samples = [0.0, 0.0, 0.5, 0.0, 0.0];
+        let (out, trimmed) = trim_silence(&samples, 1, &spec);
+        assert_eq!(out, samples);
+        assert_eq!(trimmed, Some((0, 0)));
+        // A frame is loud when any channel is.
+        let samples = [0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.5, 0.0, 0.0];
+        let (out, trimmed) = trim_silence(&samples, 2, &spec);
+        assert_eq!(out, samples[6..]);
+        assert_eq!(trimmed, Some((3, 0)));
+        let (out, trimmed) = trim_silence(&[0.0; 8], 1, &spec);
+        assert!(out.is_empty());
+        assert_eq!(trimmed, Some((8, 0)));
+    }
+
+    #[test]
+    fn test_silence_trim_long_runs() {
+        let spec = SilenceSpec::new(Some(true), Some(-20.0), Some(0.3))
+            .unwrap()
+            .unwrap();
+        // 30 seconds are 300 frames, quiet runs beyond that are let through.
+        let mut samples = vec![0.5];
+        samples.extend([0.0; 400]);
+        samples.push(0.5);
+        let (out, trimmed) = trim_silence(&samples, 1, &spec);
+        assert_eq!(out, samples);
+        assert_eq!(trimmed, Some((0, 0)));
+        let (out, trimmed) = trim_silence(&samples[..401], 1, &spec);
+        assert_eq!(out, samples[..101]);
+        assert_eq!(trimmed, Some((0, 300)));
+        assert!(SilenceSpec::new(Some(true), None, Some(30.5)).is_err());
+    }
+}
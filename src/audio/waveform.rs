@@ -0,0 +1,220 @@
+use serde::Serialize;
+
+use crate::audio::AudioStream;
+use crate::error::WaveemapiError;
+
+/// Version of the audiowaveform formats written, the first with channels.
+const VERSION: u32 = 2;
+/// audiowaveform's default zoom level.
+const DEFAULT_SAMPLES_PER_PIXEL: u32 = 256;
+/// `.dat` header flag for 8-bit data.
+const FLAG_8_BIT: u32 = 1;
+
+/// How peaks are gathered, validated by [`WaveformOptions::new`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WaveformOptions {
+    /// Sample frames summarized by each min/max pair.
+    pub samples_per_pixel: u32,
+    /// 8 or 16.
+    pub bits: u8,
+    /// Keep a pair per channel instead of one for their average.
+    pub split_channels: bool,
+}
+
+impl WaveformOptions {
+    pub fn new(
+        samples_per_pixel: Option<u32>,
+        bits: Option<u8>,
+        split_channels: Option<bool>,
+    ) -> Result<Self, WaveemapiError> {
+        let samples_per_pixel = samples_per_pixel.unwrap_or(DEFAULT_SAMPLES_PER_PIXEL);
+        if samples_per_pixel < 2 {
+            return Err(WaveemapiError::InvalidOptions(
+                "samples_per_pixel must be at least 2".to_string(),
+            ));
+        }
+        let bits = bits.unwrap_or(16);
+        if bits != 8 && bits != 16 {
+            return Err(WaveemapiError::InvalidOptions(format!(
+                "Unsupported bits {}, expected 8 or 16",
+                bits
+            )));
+        }
+        Ok(WaveformOptions {
+            samples_per_pixel,
+            bits,
+            split_channels: split_channels.unwrap_or(false),
+        })
+    }
+}
+
+/// Min/max peaks in the audiowaveform JSON layout, which serializes as is.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Waveform {
+    pub version: u32,
+    pub channels: u32,
+    pub sample_rate: u32,
+    pub samples_per_pixel: u32,
+    pub bits: u8,
+    /// Number of pixels.
+    pub length: u32,
+    /// Per pixel, a min and max for each channel in turn.
+    pub data: Vec<i16>,
+}
+
+impl Waveform {
+    /// The audiowaveform binary `.dat` format, little endian.
+    pub fn to_dat(&self) -> Vec<u8> {
+        let value_bytes = if self.bits == 8 { 1 } else { 2 };
+        let mut dat = Vec::with_capacity(24 + self.data.len() * value_bytes);
+        dat.extend_from_slice(&self.version.to_le_bytes());
+        let flags = if self.bits == 8 { FLAG_8_BIT } else { 0 };
+        dat.extend_from_slice(&flags.to_le_bytes());
+        dat.extend_from_slice(&self.sample_rate.to_le_bytes());
+        dat.extend_from_slice(&self.samples_per_pixel.to_le_bytes());
+        dat.extend_from_slice(&self.length.to_le_bytes());
+        dat.extend_from_slice(&self.channels.to_le_bytes());
+        for &value in &self.data {
+            if self.bits == 8 {
+                dat.push(value as i8 as u8);
+            } else {
+                dat.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+        dat
+    }
+}
+
+/// Collects the peaks of `stream`. A last, partial pixel is kept.
+pub fn waveform(
+    stream: AudioStream<'_>,
+    options: &WaveformOptions,
+) -> Result<Waveform, WaveemapiError> {
+    let channels = stream.channels;
+    if channels == 0 {
+        return Err(WaveemapiError::Hound(hound::Error::Unsupported));
+    }
+    let outputs = if options.split_channels { channels } else { 1 };
+    let mut peaks = vec![(i16::MAX, i16::MIN); outputs];
+    let mut frame = Vec::with_capacity(channels);
+    let mut frames_in_pixel = 0;
+    let mut data = Vec::new();
+    let flush = |peaks: &mut [(i16, i16)], data: &mut Vec<i16>| {
+        for peak in peaks.iter_mut() {
+            data.extend([scale(peak.0, options.bits), scale(peak.1, options.bits)]);
+            *peak = (i16::MAX, i16::MIN);
+        }
+    };
+    for sample in stream.samples {
+        frame.push(sample?);
+        if frame.len() < channels {
+            continue;
+        }
+        if options.split_channels {
+            for (peak, &sample) in peaks.iter_mut().zip(&frame) {
+                add(peak, sample);
+            }
+        } else {
+            add(&mut peaks[0], frame.iter().sum::<f32>() / channels as f32);
+        }
+        frame.clear();
+        frames_in_pixel += 1;
+        if frames_in_pixel == options.samples_per_pixel {
+            flush(&mut peaks, &mut data);
+            frames_in_pixel = 0;
+        }
+    }
+    if frames_in_pixel > 0 {
+        flush(&mut peaks, &mut data);
+    }
+    Ok(Waveform {
+        version: VERSION,
+        channels: outputs as u32,
+        sample_rate: stream.sample_rate,
+        samples_per_pixel: options.samples_per_pixel,
+        bits: options.bits,
+        length: (data.len() / (2 * outputs)) as u32,
+        data,
+    })
+}
+
+fn add(peak: &mut (i16, i16), sample: f32) {
+    let value = (sample * 32768.0).round().clamp(-32768.0, 32767.0) as i16;
+    *peak = (peak.0.min(value), peak.1.max(value));
+}
+
+/// Reduces a 16-bit peak to `bits`, as audiowaveform does.
+fn scale(value: i16, bits: u8) -> i16 {
+    if bits == 8 { value >> 8 } else { value }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stream(samples: Vec<f32>, channels: usize) -> AudioStream<'static> {
+        AudioStream {
+            samples: Box::new(samples.into_iter().map(Ok)),
+            channels,
+            sample_rate: 8000,
+            channel_mask: None,
+            total_samples: None,
+        }
+    }
+
+    fn options(samples_per_pixel: u32, bits: u8, split_channels: bool) -> WaveformOptions {
+        WaveformOptions::new(Some(samples_per_pixel), Some(bits), Some(split_channels)).unwrap()
+    }
+
+    #[test]
+    fn test_options() {
+        let defaults = WaveformOptions::new(None, None, None).unwrap();
+        assert_eq!(defaults.samples_per_pixel, 256);
+        assert_eq!(defaults.bits, 16);
+        assert!(!defaults.split_channels);
+        assert!(WaveformOptions::new(Some(1), None, None).is_err());
+        assert!(WaveformOptions::new(None, Some(24), None).is_err());
+    }
+
+    #[test]
+    fn test_peaks() {
+        let samples = vec![0.5, -0.25, 0.0, 1.0, -1.0];
+        let waveform = waveform(stream(samples, 1), &options(2, 16, false)).unwrap();
+        assert_eq!(waveform.length, 3);
+        assert_eq!(waveform.data, vec![-8192, 16384, 0, 32767, -32768, -32768]);
+        assert_eq!(waveform.sample_rate, 8000);
+    }
+
+    #[test]
+    fn test_channels() {
+        let samples = vec![0.5, -0.5, 0.25, 0.0];
+        let merged = waveform(stream(samples.clone(), 2), &options(2, 16, false)).unwrap();
+        assert_eq!(merged.channels, 1);
+        assert_eq!(merged.data, vec![0, 4096]);
+        let split = waveform(stream(samples, 2), &options(2, 8, true)).unwrap();
+        assert_eq!(split.channels, 2);
+        assert_eq!(split.length, 1);
+        assert_eq!(split.data, vec![32, 64, -64, 0]);
+    }
+
+    #[test]
+    fn test_dat() {
+        let peaks = waveform(stream(vec![0.5, -0.5], 1), &options(2, 8, false)).unwrap();
+        assert_eq!(
+            peaks.to_dat(),
+            [
+                &2u32.to_le_bytes()[..],
+                &1u32.to_le_bytes(),
+                &8000u32.to_le_bytes(),
+                &2u32.to_le_bytes(),
+                &1u32.to_le_bytes(),
+                &1u32.to_le_bytes(),
+                &[(-64i8) as u8, 64],
+            ]
+            .concat()
+        );
+        let peaks = waveform(stream(vec![0.5, -0.5], 1), &options(2, 16, false)).unwrap();
+        assert_eq!(&peaks.to_dat()[4..8], &0u32.to_le_bytes());
+        assert_eq!(&peaks.to_dat()[24..], &[0, 192, 0, 64]);
+    }
+}
@@ -0,0 +1,299 @@
+use std::io::{self, Read, Write};
+
+use rocket::futures::stream::{self, BoxStream, StreamExt};
+use rocket::tokio::io::{AsyncRead, AsyncReadExt};
+use rocket::tokio::{self, sync::mpsc};
+
+use crate::error::WaveemapiError;
+
+pub type Chunk = Result<Vec<u8>, WaveemapiError>;
+
+/// Blocking `Write` that hands each write to an async receiver, so the
+/// encoder can run on a blocking thread while the response streams.
+pub struct ChannelWriter {
+    tx: mpsc::Sender<Chunk>,
+}
+
+impl ChannelWriter {
+    pub fn new(tx: mpsc::Sender<Chunk>) -> Self {
+        ChannelWriter { tx }
+    }
+
+    /// Passes an error on in place of further output.
+    pub fn fail(&self, error: WaveemapiError) {
+        self.tx.blocking_send(Err(error)).ok();
+    }
+}
+
+impl Write for ChannelWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.tx
+            .blocking_send(Ok(buf.to_vec()))
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "receiver closed"))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Reads `reader` as a stream of chunks of up to `chunk_bytes`.
+pub fn read_chunks<'r, R>(reader: R, chunk_bytes: usize) -> BoxStream<'r, io::Result<Vec<u8>>>
+where
+    R: AsyncRead + Unpin + Send + 'r,
+{
+    stream::unfold(Some(reader), move |reader| async move {
+        let mut reader = reader?;
+        let mut buf = vec![0u8; chunk_bytes];
+        match reader.read(&mut buf).await {
+            Ok(0) => None,
+            Ok(n) => {
+                buf.truncate(n);
+                Some((Ok(buf), Some(reader)))
+            }
+            Err(e) => Some((Err(e), None)),
+        }
+    })
+    .boxed()
+}
+
+/// Blocking `Read` over chunks sent from async code, so a decoder can consume
+/// a request body as it arrives.
+pub struct ChannelReader {
+    rx: mpsc::Receiver<Chunk>,
+    chunk: Vec<u8>,
+    pos: usize,
+}
+
+impl ChannelReader {
+    pub fn new(rx: mpsc::Receiver<Chunk>) -> Self {
+        ChannelReader {
+            rx,
+            chunk: Vec::new(),
+            pos: 0,
+        }
+    }
+}
+
+impl Read for ChannelReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.chunk.len() {
+            match self.rx.blocking_recv() {
+                Some(Ok(chunk)) => {
+                    self.chunk = chunk;
+                    self.pos = 0;
+                }
+                Some(Err(e)) => return Err(io::Error::other(e.to_string())),
+                None => return Ok(0),
+            }
+        }
+        let n = buf.len().min(self.chunk.len() - self.pos);
+        buf[..n].copy_from_slice(&self.chunk[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+/// Connects an async input stream to a blocking decoder and its output.
+/// Input is only pulled while the decoder has room for it, and output is
+/// handed back as soon as it is written, so neither side stalls the other.
+pub struct Pipeline<'r> {
+    input: BoxStream<'r, Chunk>,
+    tx: Option<mpsc::Sender<Chunk>>,
+    rx: mpsc::Receiver<Chunk>,
+}
+
+enum Fed {
+    Sent,
+    Done,
+    Failed(WaveemapiError),
+}
+
+impl<'r> Pipeline<'r> {
+    /// Returns the pipeline along with the reader and writer for the
+    /// blocking side, buffering up to `buffered` chunks each way.
+    pub fn new(
+        input: BoxStream<'r, Chunk>,
+        buffered: usize,
+    ) -> (Self, ChannelReader, ChannelWriter) {
+        let (in_tx, in_rx) = mpsc::channel(buffered);
+        let (out_tx, out_rx) = mpsc::channel(buffered);
+        let pipeline = Pipeline {
+            input,
+            tx: Some(in_tx),
+            rx: out_rx,
+        };
+        (
+            pipeline,
+            ChannelReader::new(in_rx),
+            ChannelWriter::new(out_tx),
+        )
+    }
+
+    /// The next output chunk, feeding input meanwhile. An input error is
+    /// returned as is and also ends the decoder's input with an error. `None`
+    /// once the writer has been dropped.
+    pub async fn next(&mut self) -> Option<Chunk> {
+        loop {
+            let Some(tx) = &self.tx else {
+                return self.rx.recv().await;
+            };
+            tokio::select! {
+                biased;
+                chunk = self.rx.recv() => return chunk,
+                fed = feed(&mut self.input, tx) => match fed {
+                    Fed::Sent => {}
+                    Fed::Done => self.tx = None,
+                    Fed::Failed(e) => {
+                        if let Some(tx) = self.tx.take() {
+                            tx.send(Err(WaveemapiError::InvalidOptions(
+                                "upload was interrupted".to_string(),
+                            )))
+                            .await
+                            .ok();
+                        }
+                        return Some(Err(e));
+                    }
+                },
+            }
+        }
+    }
+}
+
+/// Runs `read` on a blocking thread over `input` as it arrives, for work
+/// that produces a result rather than output. Input is no longer read once
+/// `read` returns.
+pub async fn read_blocking<T, F>(
+    input: BoxStream<'_, Chunk>,
+    buffered: usize,
+    read: F,
+) -> Result<T, WaveemapiError>
+where
+    T: Send + 'static,
+    F: FnOnce(ChannelReader) -> Result<T, WaveemapiError> + Send + 'static,
+{
+    let (mut pipeline, reader, writer) = Pipeline::new(input, buffered);
+    let task = tokio::task::spawn_blocking(move || {
+        // Held until `read` is done, which ends the pipeline.
+        let _writer = writer;
+        read(reader)
+    });
+    while let Some(chunk) = pipeline.next().await {
+        chunk?;
+    }
+    task.await?
+}
+
+/// Moves one input chunk to the decoder. Cancel safe: a chunk is only taken
+/// from `input` once there is room to send it without waiting.
+async fn feed(input: &mut BoxStream<'_, Chunk>, tx: &mpsc::Sender<Chunk>) -> Fed {
+    let Ok(permit) = tx.reserve().await else {
+        // The decoder stopped reading, its result explains why.
+        return Fed::Done;
+    };
+    match input.next().await {
+        Some(Ok(chunk)) => {
+            permit.send(Ok(chunk));
+            Fed::Sent
+        }
+        Some(Err(e)) => Fed::Failed(e),
+        None => Fed::Done,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_channel_writer() {
+        let (tx, mut rx) = mpsc::channel(4);
+        let mut writer = ChannelWriter::new(tx);
+        writer.write_all(b"ID3").unwrap();
+        drop(writer);
+        assert_eq!(rx.blocking_recv().unwrap().unwrap(), b"ID3");
+        assert!(rx.blocking_recv().is_none());
+    }
+
+    #[test]
+    fn test_channel_writer_closed() {
+        let (tx, rx) = mpsc::channel(4);
+        drop(rx);
+        let mut writer = ChannelWriter::new(tx);
+        let err = writer.write_all(b"ID3").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+    }
+
+    #[rocket::async_test]
+    async fn test_read_chunks() {
+        let data = [7u8; 10];
+        let chunks: Vec<_> = read_chunks(&data[..], 4)
+            .map(|chunk| chunk.unwrap().len())
+            .collect()
+            .await;
+        assert_eq!(chunks, vec![4, 4, 2]);
+    }
+
+    #[test]
+    fn test_channel_reader() {
+        let (tx, rx) = mpsc::channel(4);
+        tx.blocking_send(Ok(b"RIFF".to_vec())).unwrap();
+        tx.blocking_send(Ok(Vec::new())).unwrap();
+        tx.blocking_send(Ok(b"WAVE".to_vec())).unwrap();
+        drop(tx);
+        let mut data = Vec::new();
+        ChannelReader::new(rx).read_to_end(&mut data).unwrap();
+        assert_eq!(data, b"RIFFWAVE");
+    }
+
+    #[test]
+    fn test_channel_reader_error() {
+        let (tx, rx) = mpsc::channel(4);
+        tx.blocking_send(Ok(b"RIFF".to_vec())).unwrap();
+        tx.blocking_send(Err(WaveemapiError::InvalidOptions("cut".to_string())))
+            .unwrap();
+        let mut data = Vec::new();
+        assert!(ChannelReader::new(rx).read_to_end(&mut data).is_err());
+        assert_eq!(data, b"RIFF");
+    }
+
+    #[rocket::async_test]
+    async fn test_pipeline() {
+        // Far more input than the channels buffer, to catch a stall.
+        let input = rocket::futures::stream::iter((0..64u8).map(|i| Ok(vec![i; 1000]))).boxed();
+        let (mut pipeline, mut reader, mut writer) = Pipeline::new(input, 2);
+        let decoder = tokio::task::spawn_blocking(move || {
+            let mut buf = [0u8; 500];
+            while let Ok(n @ 1..) = reader.read(&mut buf) {
+                writer.write_all(&buf[..n]).unwrap();
+            }
+        });
+        let mut output = Vec::new();
+        while let Some(chunk) = pipeline.next().await {
+            output.extend(chunk.unwrap());
+        }
+        decoder.await.unwrap();
+        assert_eq!(output.len(), 64_000);
+        assert!(output.ends_with(&[63; 500]));
+    }
+
+    #[rocket::async_test]
+    async fn test_pipeline_input_error() {
+        let input = rocket::futures::stream::iter(vec![
+            Ok(b"RIFF".to_vec()),
+            Err(WaveemapiError::InvalidOptions("cut".to_string())),
+        ])
+        .boxed();
+        let (mut pipeline, mut reader, _writer) = Pipeline::new(input, 2);
+        let decoder = tokio::task::spawn_blocking(move || {
+            let mut data = Vec::new();
+            reader.read_to_end(&mut data)
+        });
+        assert!(matches!(
+            pipeline.next().await,
+            Some(Err(WaveemapiError::InvalidOptions(_)))
+        ));
+        assert!(decoder.await.unwrap().is_err());
+    }
+}
@@ -1,4 +1,9 @@
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::error::WaveemapiError;
+use crate::id3::MAX_COVER_BYTES;
 
 const ROOT: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/", "data");
 
@@ -9,6 +14,21 @@ pub struct Config {
     pub data_path: String,
     pub cleanup_interval_minutes: u64,
     pub file_expiry_minutes: u64,
+    pub cover_max_bytes: u64,
+    pub job_workers: usize,
+    /// HMAC secrets for signing webhooks, keyed by auth token.
+    pub webhook_secrets: HashMap<String, String>,
+    pub webhook_retries: u32,
+    /// Callback hosts that may resolve to loopback, link-local or private addresses.
+    pub webhook_allowed_hosts: Vec<String>,
+    /// Base URL used for download links in webhooks.
+    pub public_url: String,
+    /// Longest accepted input in seconds, 0 for no limit.
+    pub max_duration_seconds: u64,
+    /// Audio files for `intro` and `outro`, keyed by name.
+    pub assets: HashMap<String, String>,
+    /// Directory relative asset paths are resolved against, `data_path` when empty.
+    pub assets_path: String,
 }
 
 impl Default for Config {
@@ -19,10 +39,44 @@ impl Default for Config {
             data_path: ROOT.to_string(),
             cleanup_interval_minutes: 15,
             file_expiry_minutes: 60,
+            cover_max_bytes: 2 * 1024 * 1024,
+            job_workers: 2,
+            webhook_secrets: HashMap::new(),
+            webhook_retries: 3,
+            webhook_allowed_hosts: vec![],
+            public_url: String::new(),
+            max_duration_seconds: 12 * 60 * 60,
+            assets: HashMap::new(),
+            assets_path: String::new(),
         }
     }
 }
 
+impl Config {
+    /// `cover_max_bytes`, capped at what fits in an ID3v2 tag.
+    pub fn cover_max_bytes(&self) -> u64 {
+        self.cover_max_bytes.min(MAX_COVER_BYTES as u64)
+    }
+
+    pub fn max_duration(&self) -> Option<u64> {
+        (self.max_duration_seconds > 0).then_some(self.max_duration_seconds)
+    }
+
+    /// The path of the asset called `name`.
+    pub fn asset(&self, name: &str) -> Result<String, WaveemapiError> {
+        let file = self
+            .assets
+            .get(name)
+            .ok_or_else(|| WaveemapiError::InvalidOptions(format!("Unknown asset '{}'", name)))?;
+        let dir = if self.assets_path.is_empty() {
+            &self.data_path
+        } else {
+            &self.assets_path
+        };
+        Ok(Path::new(dir).join(file).to_string_lossy().to_string())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -35,5 +89,40 @@ mod tests {
         assert!(config.auth_tokens.is_empty());
         assert!(config.cleanup_interval_minutes == 15);
         assert!(config.file_expiry_minutes == 60);
+        assert!(config.cover_max_bytes == 2 * 1024 * 1024);
+        assert!(config.job_workers == 2);
+        assert!(config.webhook_secrets.is_empty());
+        assert!(config.webhook_retries == 3);
+        assert!(config.webhook_allowed_hosts.is_empty());
+        assert!(config.public_url.is_empty());
+        assert_eq!(config.max_duration(), Some(12 * 60 * 60));
+        let unlimited = Config {
+            max_duration_seconds: 0,
+            ..Config::default()
+        };
+        assert_eq!(unlimited.max_duration(), None);
+        assert!(config.assets.is_empty());
+        let huge_cover = Config {
+            cover_max_bytes: u64::MAX,
+            ..Config::default()
+        };
+        assert_eq!(huge_cover.cover_max_bytes(), MAX_COVER_BYTES as u64);
+    }
+
+    #[test]
+    fn test_asset() {
+        let mut config = Config {
+            data_path: "/data".to_string(),
+            assets: HashMap::from([
+                ("intro".to_string(), "jingles/intro.wav".to_string()),
+                ("outro".to_string(), "/srv/outro.flac".to_string()),
+            ]),
+            ..Config::default()
+        };
+        assert_eq!(config.asset("intro").unwrap(), "/data/jingles/intro.wav");
+        assert_eq!(config.asset("outro").unwrap(), "/srv/outro.flac");
+        assert!(config.asset("bumper").is_err());
+        config.assets_path = "/assets".to_string();
+        assert_eq!(config.asset("intro").unwrap(), "/assets/jingles/intro.wav");
     }
 }
@@ -12,6 +12,13 @@ pub enum WaveemapiError {
     Io(std::io::Error),
     Join(rocket::tokio::task::JoinError),
     Id3Tag(mp3lame_encoder::Id3TagError),
+    InvalidOptions(String),
+    NotFound(String),
+    NotReady(String),
+    Multipart(multer::Error),
+    TooLarge(String),
+    UnsupportedMediaType(String),
+    Zip(zip::result::ZipError),
 }
 
 impl fmt::Display for WaveemapiError {
@@ -23,6 +30,13 @@ impl fmt::Display for WaveemapiError {
             WaveemapiError::Io(e) => write!(f, "IO error: {}", e),
             WaveemapiError::Join(e) => write!(f, "Join error: {}", e),
             WaveemapiError::Id3Tag(_) => write!(f, "ID3 tag error"),
+            WaveemapiError::InvalidOptions(e) => write!(f, "Invalid options: {}", e),
+            WaveemapiError::NotFound(e) => write!(f, "Not found: {}", e),
+            WaveemapiError::NotReady(e) => write!(f, "Not ready: {}", e),
+            WaveemapiError::Multipart(e) => write!(f, "Multipart error: {}", e),
+            WaveemapiError::TooLarge(e) => write!(f, "Too large: {}", e),
+            WaveemapiError::UnsupportedMediaType(e) => write!(f, "Unsupported media type: {}", e),
+            WaveemapiError::Zip(e) => write!(f, "Zip error: {}", e),
         }
     }
 }
@@ -51,29 +65,76 @@ impl From<hound::Error> for WaveemapiError {
     }
 }
 
+impl From<multer::Error> for WaveemapiError {
+    fn from(value: multer::Error) -> Self {
+        match value {
+            multer::Error::FieldSizeExceeded { field_name, .. } => {
+                WaveemapiError::TooLarge(format!(
+                    "{} exceeds the upload limit",
+                    field_name.as_deref().unwrap_or("field")
+                ))
+            }
+            multer::Error::StreamSizeExceeded { .. } => {
+                WaveemapiError::TooLarge("request body exceeds the upload limit".to_string())
+            }
+            e => WaveemapiError::Multipart(e),
+        }
+    }
+}
+
 impl From<mp3lame_encoder::Id3TagError> for WaveemapiError {
     fn from(value: mp3lame_encoder::Id3TagError) -> Self {
         WaveemapiError::Id3Tag(value)
     }
 }
 
-#[rocket::async_trait]
-impl<'r> Responder<'r, 'static> for WaveemapiError {
-    fn respond_to(self, request: &'r rocket::Request<'_>) -> rocket::response::Result<'static> {
-        let status = match self {
+impl From<zip::result::ZipError> for WaveemapiError {
+    fn from(value: zip::result::ZipError) -> Self {
+        WaveemapiError::Zip(value)
+    }
+}
+
+impl WaveemapiError {
+    pub fn status(&self) -> Status {
+        match self {
             WaveemapiError::Hound(_) => Status::BadRequest,
             WaveemapiError::Io(_) => Status::InternalServerError,
             WaveemapiError::Build(_) => Status::BadRequest,
+            WaveemapiError::InvalidOptions(_) => Status::BadRequest,
+            WaveemapiError::NotFound(_) => Status::NotFound,
+            WaveemapiError::NotReady(_) => Status::Conflict,
+            WaveemapiError::Multipart(_) => Status::BadRequest,
+            WaveemapiError::TooLarge(_) => Status::PayloadTooLarge,
+            WaveemapiError::UnsupportedMediaType(_) => Status::UnsupportedMediaType,
             _ => Status::InternalServerError,
-        };
-        let message = match self {
+        }
+    }
+
+    /// The message shown to API clients, without internal details.
+    pub fn message(&self) -> String {
+        match self {
             WaveemapiError::Encoder(_) => "Failed to encode MP3".to_string(),
-            WaveemapiError::Hound(_) => "Invalid WAV file".to_string(),
+            WaveemapiError::Hound(_) => "Invalid audio file".to_string(),
             WaveemapiError::Io(_) => "Internal server error".to_string(),
             WaveemapiError::Build(_) => "Failed to build encoder".to_string(),
+            WaveemapiError::InvalidOptions(e) => e.clone(),
+            WaveemapiError::NotFound(e) => e.clone(),
+            WaveemapiError::NotReady(e) => e.clone(),
+            WaveemapiError::Multipart(_) => "Invalid multipart body".to_string(),
+            WaveemapiError::TooLarge(e) => e.clone(),
+            WaveemapiError::UnsupportedMediaType(e) => e.clone(),
             _ => "An error occurred".to_string(),
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> Responder<'r, 'static> for WaveemapiError {
+    fn respond_to(self, request: &'r rocket::Request<'_>) -> rocket::response::Result<'static> {
+        let status = self.status();
+        let error_resp = DefaultErrorResp {
+            error: self.message(),
         };
-        let error_resp = DefaultErrorResp { error: message };
         Json(error_resp).respond_to(request).map(|mut response| {
             response.set_status(status);
             response
@@ -95,4 +156,23 @@ mod tests {
             _ => panic!("Wrong variant"),
         }
     }
+
+    #[test]
+    fn test_status_and_message() {
+        let err = WaveemapiError::NotFound("job not found".to_string());
+        assert_eq!(err.status(), Status::NotFound);
+        assert_eq!(err.message(), "job not found");
+        let err = WaveemapiError::Io(io::Error::other("disk on fire"));
+        assert_eq!(err.status(), Status::InternalServerError);
+        assert_eq!(err.message(), "Internal server error");
+    }
+
+    #[test]
+    fn test_from_multer_error() {
+        let err = WaveemapiError::from(multer::Error::StreamSizeExceeded { limit: 10 });
+        assert_eq!(err.status(), Status::PayloadTooLarge);
+        let err = WaveemapiError::from(multer::Error::IncompleteStream);
+        assert_eq!(err.status(), Status::BadRequest);
+        assert_eq!(err.message(), "Invalid multipart body");
+    }
 }
@@ -1,4 +1,5 @@
-use std::path::Path;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
 
 use uuid::Uuid;
 
@@ -8,6 +9,7 @@ use std::time::{Duration, SystemTime};
 
 const MP3_EXT: &str = ".mp3";
 const WAV_EXT: &str = ".wav";
+const ZIP_EXT: &str = ".zip";
 const FNAME_LEN: usize = 40; // 36 (uuid) + 4 (.mp3)
 
 pub fn check_data_path(data_path: &str) -> io::Result<()> {
@@ -21,8 +23,13 @@ pub fn check_data_path(data_path: &str) -> io::Result<()> {
     Ok(())
 }
 
-/// Deletes all .wav and .mp3 files in `data_path` that are exactly 40 characters long (including extension).
-pub fn clear_data_path(data_path: &str, expiry: Duration) -> io::Result<()> {
+/// Deletes all .wav, .mp3 and .zip files in `data_path` that are exactly 40 characters long (including extension).
+/// Files in `in_use` are kept regardless of their age.
+pub fn clear_data_path(
+    data_path: &str,
+    expiry: Duration,
+    in_use: &HashSet<PathBuf>,
+) -> io::Result<()> {
     check_data_path(data_path)?;
     let dir = Path::new(data_path);
     for entry in fs::read_dir(dir)? {
@@ -40,7 +47,12 @@ pub fn clear_data_path(data_path: &str, expiry: Duration) -> io::Result<()> {
         if let Some(fname) = path.file_name().and_then(|n| n.to_str()) {
             let is_wav = fname.ends_with(WAV_EXT);
             let is_mp3 = fname.ends_with(MP3_EXT);
-            if (is_wav || is_mp3) && fname.len() == FNAME_LEN && old_enough {
+            let is_zip = fname.ends_with(ZIP_EXT);
+            if (is_wav || is_mp3 || is_zip)
+                && fname.len() == FNAME_LEN
+                && old_enough
+                && !in_use.contains(&path)
+            {
                 fs::remove_file(&path)?;
             }
         }
@@ -66,6 +78,15 @@ pub fn mp3_path(data_path: &str) -> String {
         .to_string()
 }
 
+pub fn zip_path(data_path: &str) -> String {
+    let id = Uuid::new_v4();
+    let filename = format!("{}{}", id, ZIP_EXT);
+    Path::new(data_path)
+        .join(filename)
+        .to_string_lossy()
+        .to_string()
+}
+
 #[allow(dead_code)]
 fn get_unique_data_path() -> String {
     let unique_id = Uuid::new_v4();
@@ -213,7 +234,12 @@ mod tests {
         let other_file = test_dir.join("not_to_delete.txt");
         fs::write(&other_file, b"keep me").unwrap();
         // duration 0 for this test
-        clear_data_path(test_dir.to_string_lossy().as_ref(), Duration::from_secs(0)).unwrap();
+        clear_data_path(
+            test_dir.to_string_lossy().as_ref(),
+            Duration::from_secs(0),
+            &HashSet::new(),
+        )
+        .unwrap();
         assert!(other_file.exists(), "Non-matching file should remain");
         for entry in fs::read_dir(test_dir).unwrap() {
             let entry = entry.unwrap();
@@ -244,14 +270,31 @@ mod tests {
         clear_data_path(
             test_dir.to_string_lossy().as_ref(),
             Duration::from_secs(60 * 60),
+            &HashSet::new(),
         )
         .unwrap();
 
         assert!(wav_file.exists(), "WAV file should not be deleted");
         assert!(mp3_file.exists(), "MP3 file should not be deleted");
 
+        // Files still in use survive a short expiry
+        let in_use = HashSet::from([wav_file.clone()]);
+        clear_data_path(
+            test_dir.to_string_lossy().as_ref(),
+            Duration::from_secs(0),
+            &in_use,
+        )
+        .unwrap();
+        assert!(wav_file.exists(), "WAV file in use should not be deleted");
+        assert!(!mp3_file.exists(), "MP3 file should be deleted");
+
         // Now use a short expiry duration to allow deletion
-        clear_data_path(test_dir.to_string_lossy().as_ref(), Duration::from_secs(0)).unwrap();
+        clear_data_path(
+            test_dir.to_string_lossy().as_ref(),
+            Duration::from_secs(0),
+            &HashSet::new(),
+        )
+        .unwrap();
 
         assert!(!wav_file.exists(), "WAV file should be deleted");
         assert!(!mp3_file.exists(), "MP3 file should be deleted");
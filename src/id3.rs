@@ -0,0 +1,348 @@
+use serde::Serialize;
+
+use crate::error::WaveemapiError;
+
+const ID3V2_HEADER: &[u8; 5] = b"ID3\x03\x00"; // ID3v2.3.0
+const ENCODING_LATIN1: u8 = 0x00;
+const ENCODING_UTF16: u8 = 0x01;
+const UTF16_BOM: [u8; 2] = [0xFF, 0xFE];
+const JPEG_MAGIC: &[u8] = &[0xFF, 0xD8, 0xFF];
+const PNG_MAGIC: &[u8] = &[0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+const PICTURE_FRONT_COVER: u8 = 0x03;
+/// Largest accepted cover. The tag size in the header is 28 bits, this leaves
+/// room for the text frames next to the picture.
+pub const MAX_COVER_BYTES: usize = 0x0F00_0000;
+
+/// Album art for the APIC frame. Only JPEG and PNG are accepted since those
+/// are the only formats players reliably display.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cover {
+    mime: &'static str,
+    data: Vec<u8>,
+}
+
+impl Cover {
+    /// Validates the image by its magic bytes rather than any client supplied content type.
+    pub fn new(data: Vec<u8>) -> Result<Self, WaveemapiError> {
+        let mime = if data.starts_with(JPEG_MAGIC) {
+            "image/jpeg"
+        } else if data.starts_with(PNG_MAGIC) {
+            "image/png"
+        } else {
+            return Err(WaveemapiError::InvalidOptions(
+                "cover must be a JPEG or PNG image".to_string(),
+            ));
+        };
+        if data.len() > MAX_COVER_BYTES {
+            return Err(WaveemapiError::InvalidOptions(format!(
+                "cover exceeds the {} byte limit",
+                MAX_COVER_BYTES
+            )));
+        }
+        Ok(Cover { mime, data })
+    }
+}
+
+/// Metadata written to the ID3v2.3 tag at the start of the MP3.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+pub struct TrackTags {
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub year: Option<String>,
+    pub comment: Option<String>,
+    /// Track number, either `N` or `N/TOTAL`.
+    pub track: Option<String>,
+    pub genre: Option<String>,
+    #[serde(skip)]
+    pub cover: Option<Cover>,
+}
+
+impl TrackTags {
+    /// Builds tags from client supplied values. Blank values are treated as
+    /// missing, `year` and `track` are validated since ID3v2.3 constrains them.
+    pub fn new(
+        title: Option<&str>,
+        artist: Option<&str>,
+        album: Option<&str>,
+        year: Option<&str>,
+        comment: Option<&str>,
+        track: Option<&str>,
+        genre: Option<&str>,
+    ) -> Result<Self, WaveemapiError> {
+        let tags = TrackTags {
+            title: clean(title),
+            artist: clean(artist),
+            album: clean(album),
+            year: clean(year),
+            comment: clean(comment),
+            track: clean(track),
+            genre: clean(genre),
+            cover: None,
+        };
+        if let Some(year) = &tags.year
+            && !is_year(year)
+        {
+            return Err(WaveemapiError::InvalidOptions(
+                "year must be a four digit year".to_string(),
+            ));
+        }
+        if let Some(track) = &tags.track
+            && !is_track(track)
+        {
+            return Err(WaveemapiError::InvalidOptions(
+                "track must be a number, optionally followed by /total".to_string(),
+            ));
+        }
+        Ok(tags)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames().is_empty()
+    }
+
+    fn has_text(&self) -> bool {
+        [
+            &self.title,
+            &self.artist,
+            &self.album,
+            &self.year,
+            &self.comment,
+            &self.track,
+            &self.genre,
+        ]
+        .iter()
+        .any(|v| v.is_some())
+    }
+
+    /// Falls back to tags embedded in the source file when the client did not
+    /// supply any text fields. A client supplied cover is always kept.
+    pub fn or_embedded(self, embedded: TrackTags) -> TrackTags {
+        if self.has_text() {
+            return self;
+        }
+        TrackTags {
+            cover: self.cover.or(embedded.cover),
+            ..embedded
+        }
+    }
+
+    /// Serializes the tag, or returns an empty buffer when there is nothing to write.
+    pub fn to_id3v2(&self) -> Vec<u8> {
+        let frames = self.frames();
+        if frames.is_empty() {
+            return Vec::new();
+        }
+        let body: Vec<u8> = frames.concat();
+        let mut out = Vec::with_capacity(10 + body.len());
+        out.extend_from_slice(ID3V2_HEADER);
+        out.push(0); // flags
+        out.extend_from_slice(&syncsafe(body.len() as u32));
+        out.extend_from_slice(&body);
+        out
+    }
+
+    fn frames(&self) -> Vec<Vec<u8>> {
+        let mut frames = Vec::new();
+        let text_frames = [
+            (b"TIT2", &self.title),
+            (b"TPE1", &self.artist),
+            (b"TALB", &self.album),
+            (b"TYER", &self.year),
+            (b"TRCK", &self.track),
+            (b"TCON", &self.genre),
+        ];
+        for (id, value) in text_frames {
+            if let Some(value) = value.as_deref().filter(|v| !v.is_empty()) {
+                frames.push(text_frame(id, value));
+            }
+        }
+        if let Some(comment) = self.comment.as_deref().filter(|v| !v.is_empty()) {
+            frames.push(comment_frame(comment));
+        }
+        if let Some(cover) = &self.cover {
+            frames.push(picture_frame(cover));
+        }
+        frames
+    }
+}
+
+fn clean(value: Option<&str>) -> Option<String> {
+    value
+        .map(str::trim)
+        .filter(|v| !v.is_empty())
+        .map(str::to_string)
+}
+
+pub(crate) fn is_year(value: &str) -> bool {
+    value.len() == 4 && value.bytes().all(|b| b.is_ascii_digit())
+}
+
+pub(crate) fn is_track(value: &str) -> bool {
+    let is_number = |v: &str| !v.is_empty() && v.bytes().all(|b| b.is_ascii_digit());
+    match value.split_once('/') {
+        Some((track, total)) => is_number(track) && is_number(total),
+        None => is_number(value),
+    }
+}
+
+/// ID3v2 sizes in the tag header use 7 bits per byte.
+fn syncsafe(size: u32) -> [u8; 4] {
+    [
+        ((size >> 21) & 0x7F) as u8,
+        ((size >> 14) & 0x7F) as u8,
+        ((size >> 7) & 0x7F) as u8,
+        (size & 0x7F) as u8,
+    ]
+}
+
+fn frame(id: &[u8; 4], body: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(10 + body.len());
+    out.extend_from_slice(id);
+    out.extend_from_slice(&(body.len() as u32).to_be_bytes());
+    out.extend_from_slice(&[0, 0]); // flags
+    out.extend_from_slice(body);
+    out
+}
+
+/// Picks ISO-8859-1 when the text fits, UTF-16 with BOM otherwise.
+fn encoding_for(text: &str) -> u8 {
+    if text.chars().all(|c| (c as u32) <= 0xFF) {
+        ENCODING_LATIN1
+    } else {
+        ENCODING_UTF16
+    }
+}
+
+fn encode_text(text: &str, encoding: u8, terminate: bool, out: &mut Vec<u8>) {
+    if encoding == ENCODING_LATIN1 {
+        out.extend(text.chars().map(|c| c as u32 as u8));
+        if terminate {
+            out.push(0);
+        }
+    } else {
+        out.extend_from_slice(&UTF16_BOM);
+        out.extend(text.encode_utf16().flat_map(u16::to_le_bytes));
+        if terminate {
+            out.extend_from_slice(&[0, 0]);
+        }
+    }
+}
+
+fn text_frame(id: &[u8; 4], text: &str) -> Vec<u8> {
+    let encoding = encoding_for(text);
+    let mut body = vec![encoding];
+    encode_text(text, encoding, false, &mut body);
+    frame(id, &body)
+}
+
+fn comment_frame(text: &str) -> Vec<u8> {
+    let encoding = encoding_for(text);
+    let mut body = vec![encoding];
+    body.extend_from_slice(b"eng");
+    encode_text("", encoding, true, &mut body); // short content description
+    encode_text(text, encoding, false, &mut body);
+    frame(b"COMM", &body)
+}
+
+fn picture_frame(cover: &Cover) -> Vec<u8> {
+    let mut body = Vec::with_capacity(cover.mime.len() + cover.data.len() + 4);
+    body.push(ENCODING_LATIN1);
+    encode_text(cover.mime, ENCODING_LATIN1, true, &mut body);
+    body.push(PICTURE_FRONT_COVER);
+    encode_text("", ENCODING_LATIN1, true, &mut body); // description
+    body.extend_from_slice(&cover.data);
+    frame(b"APIC", &body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_empty_tags() {
+        let tags = TrackTags::new(None, Some("  "), None, None, None, None, None).unwrap();
+        assert!(tags.is_empty());
+        assert!(tags.to_id3v2().is_empty());
+    }
+
+    #[test]
+    fn test_invalid_year_and_track() {
+        assert!(TrackTags::new(None, None, None, Some("24"), None, None, None).is_err());
+        assert!(TrackTags::new(None, None, None, None, None, Some("a/2"), None).is_err());
+        assert!(TrackTags::new(None, None, None, Some("2024"), None, Some("3/12"), None).is_ok());
+    }
+
+    #[test]
+    fn test_tag_layout() {
+        let tags = TrackTags::new(Some("Song"), None, None, None, None, Some("3"), None).unwrap();
+        let tag = tags.to_id3v2();
+        assert_eq!(&tag[..6], b"ID3\x03\x00\x00");
+        // TIT2: 10 byte header + encoding + "Song", TRCK: 10 + 1 + "3"
+        assert_eq!(&tag[6..10], &syncsafe(15 + 12));
+        assert_eq!(&tag[10..14], b"TIT2");
+        assert_eq!(&tag[14..18], &5u32.to_be_bytes());
+        assert_eq!(&tag[20..25], b"\x00Song");
+        assert_eq!(&tag[25..29], b"TRCK");
+    }
+
+    #[test]
+    fn test_utf16_text() {
+        let frame = text_frame(b"TPE1", "Björk ☃");
+        assert_eq!(frame[10], ENCODING_UTF16);
+        assert_eq!(&frame[11..13], &UTF16_BOM);
+        let latin = text_frame(b"TPE1", "Björk");
+        assert_eq!(latin[10], ENCODING_LATIN1);
+        assert_eq!(latin.len(), 10 + 1 + 5);
+    }
+
+    #[test]
+    fn test_syncsafe() {
+        assert_eq!(syncsafe(0x7F), [0, 0, 0, 0x7F]);
+        assert_eq!(syncsafe(0x80), [0, 0, 1, 0]);
+        assert_eq!(syncsafe(0x0FFF_FFFF), [0x7F, 0x7F, 0x7F, 0x7F]);
+    }
+
+    #[test]
+    fn test_cover_magic() {
+        let png = Cover::new(PNG_MAGIC.to_vec()).unwrap();
+        assert_eq!(png.mime, "image/png");
+        let jpeg = Cover::new(vec![0xFF, 0xD8, 0xFF, 0xE0]).unwrap();
+        assert_eq!(jpeg.mime, "image/jpeg");
+        assert!(Cover::new(b"RIFF\x00\x00\x00\x00WEBP".to_vec()).is_err());
+        assert!(Cover::new(Vec::new()).is_err());
+        let mut huge = PNG_MAGIC.to_vec();
+        huge.resize(MAX_COVER_BYTES + 1, 0);
+        assert!(Cover::new(huge).is_err());
+    }
+
+    #[test]
+    fn test_cover_only_tag() {
+        let tags = TrackTags {
+            cover: Some(Cover::new(PNG_MAGIC.to_vec()).unwrap()),
+            ..TrackTags::default()
+        };
+        assert!(!tags.is_empty());
+        let tag = tags.to_id3v2();
+        assert_eq!(&tag[10..14], b"APIC");
+        let body = &tag[20..];
+        assert_eq!(&body[..11], b"\x00image/png\x00");
+        assert_eq!(body[11], PICTURE_FRONT_COVER);
+        assert_eq!(body[12], 0);
+        assert_eq!(&body[13..], PNG_MAGIC);
+    }
+
+    #[test]
+    fn test_or_embedded() {
+        let embedded = TrackTags::new(Some("File"), None, None, None, None, None, None).unwrap();
+        let client = TrackTags::new(None, Some("Client"), None, None, None, None, None).unwrap();
+        assert_eq!(client.clone().or_embedded(embedded.clone()), client);
+        let cover_only = TrackTags {
+            cover: Some(Cover::new(PNG_MAGIC.to_vec()).unwrap()),
+            ..TrackTags::default()
+        };
+        let merged = cover_only.or_embedded(embedded);
+        assert_eq!(merged.title.as_deref(), Some("File"));
+        assert!(merged.cover.is_some());
+    }
+}
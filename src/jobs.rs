@@ -0,0 +1,287 @@
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use rocket::tokio::{self, sync::Semaphore};
+use uuid::Uuid;
+
+use crate::audio::Progress;
+use crate::error::WaveemapiError;
+use crate::webhook::Webhook;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum JobState {
+    Queued,
+    Running,
+    /// Path of the encoded MP3 in `data_path`.
+    Done(String),
+    /// Client facing error message.
+    Failed(String),
+}
+
+impl JobState {
+    pub fn name(&self) -> &'static str {
+        match self {
+            JobState::Queued => "queued",
+            JobState::Running => "running",
+            JobState::Done(_) => "done",
+            JobState::Failed(_) => "failed",
+        }
+    }
+}
+
+pub struct Job {
+    pub id: Uuid,
+    pub progress: Progress,
+    /// Bearer token the job was submitted with. Other tokens cannot see it.
+    owner: Option<String>,
+    /// Files in `data_path` the job reads, kept by the cleanup until it ends.
+    inputs: Vec<String>,
+    state: Mutex<JobState>,
+    started_at: Mutex<Option<Instant>>,
+    finished_at: Mutex<Option<Instant>>,
+}
+
+impl Job {
+    fn new(owner: Option<String>, inputs: Vec<String>) -> Self {
+        Job {
+            id: Uuid::new_v4(),
+            progress: Progress::default(),
+            owner,
+            inputs,
+            state: Mutex::new(JobState::Queued),
+            started_at: Mutex::new(None),
+            finished_at: Mutex::new(None),
+        }
+    }
+
+    pub fn state(&self) -> JobState {
+        self.state.lock().unwrap().clone()
+    }
+
+    /// Time spent running, once the job has finished.
+    pub fn duration(&self) -> Option<Duration> {
+        let started = (*self.started_at.lock().unwrap())?;
+        let finished = (*self.finished_at.lock().unwrap())?;
+        Some(finished.duration_since(started))
+    }
+
+    fn set_state(&self, state: JobState) {
+        if state == JobState::Running {
+            *self.started_at.lock().unwrap() = Some(Instant::now());
+        }
+        if matches!(state, JobState::Done(_) | JobState::Failed(_)) {
+            *self.finished_at.lock().unwrap() = Some(Instant::now());
+        }
+        if matches!(state, JobState::Done(_)) {
+            self.progress.set(100);
+        }
+        *self.state.lock().unwrap() = state;
+    }
+
+    fn finished_before(&self, cutoff: Instant) -> bool {
+        self.finished_at.lock().unwrap().is_some_and(|t| t < cutoff)
+    }
+
+    fn is_pending(&self) -> bool {
+        matches!(self.state(), JobState::Queued | JobState::Running)
+    }
+}
+
+/// Encode jobs running in the background, limited to `workers` at a time.
+#[derive(Clone)]
+pub struct Jobs {
+    jobs: Arc<Mutex<HashMap<Uuid, Arc<Job>>>>,
+    workers: Arc<Semaphore>,
+    client: reqwest::Client,
+}
+
+impl Jobs {
+    /// Jobs delivering their webhooks with `client`.
+    pub fn new(workers: usize, client: reqwest::Client) -> Self {
+        Jobs {
+            jobs: Arc::new(Mutex::new(HashMap::new())),
+            workers: Arc::new(Semaphore::new(workers.max(1))),
+            client,
+        }
+    }
+
+    /// Queues a blocking encode of `inputs` for `owner` that returns the path
+    /// of the finished MP3, calling `webhook` once it is done or has failed.
+    pub fn submit<F>(
+        &self,
+        owner: Option<String>,
+        inputs: Vec<String>,
+        task: F,
+        webhook: Option<Webhook>,
+    ) -> Arc<Job>
+    where
+        F: FnOnce(&Progress) -> Result<String, WaveemapiError> + Send + 'static,
+    {
+        let job = Arc::new(Job::new(owner, inputs));
+        self.jobs.lock().unwrap().insert(job.id, job.clone());
+        let workers = self.workers.clone();
+        let client = self.client.clone();
+        let running = job.clone();
+        tokio::spawn(async move {
+            // The semaphore is never closed, so acquiring only waits.
+            let _permit = workers.acquire_owned().await;
+            running.set_state(JobState::Running);
+            let progress = running.progress.clone();
+            let state = match tokio::task::spawn_blocking(move || task(&progress)).await {
+                Ok(Ok(path)) => JobState::Done(path),
+                Ok(Err(e)) => JobState::Failed(e.message()),
+                Err(e) => JobState::Failed(WaveemapiError::from(e).message()),
+            };
+            running.set_state(state);
+            if let Some(webhook) = webhook {
+                webhook.deliver(&client, &running).await;
+            }
+        });
+        job
+    }
+
+    /// The job with `id`, if it was submitted by `owner`.
+    pub fn get(&self, id: &str, owner: Option<&str>) -> Result<Arc<Job>, WaveemapiError> {
+        Uuid::parse_str(id)
+            .ok()
+            .and_then(|id| self.jobs.lock().unwrap().get(&id).cloned())
+            .filter(|job| job.owner.as_deref() == owner)
+            .ok_or_else(|| WaveemapiError::NotFound("job not found".to_string()))
+    }
+
+    /// Input files of jobs that are queued or running.
+    pub fn inputs_in_use(&self) -> HashSet<PathBuf> {
+        self.jobs
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|job| job.is_pending())
+            .flat_map(|job| job.inputs.iter().map(PathBuf::from))
+            .collect()
+    }
+
+    /// Forgets jobs that finished more than `expiry` ago. Their files are
+    /// removed by the data path cleanup.
+    pub fn prune(&self, expiry: Duration) {
+        let Some(cutoff) = Instant::now().checked_sub(expiry) else {
+            return;
+        };
+        self.jobs
+            .lock()
+            .unwrap()
+            .retain(|_, job| !job.finished_before(cutoff));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn wait(job: &Job) -> JobState {
+        for _ in 0..500 {
+            let state = job.state();
+            if matches!(state, JobState::Done(_) | JobState::Failed(_)) {
+                return state;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("job did not finish");
+    }
+
+    #[rocket::async_test]
+    async fn test_job_done() {
+        let jobs = Jobs::new(1, reqwest::Client::new());
+        let job = jobs.submit(
+            None,
+            Vec::new(),
+            |progress| {
+                progress.set(50);
+                Ok("out.mp3".to_string())
+            },
+            None,
+        );
+        assert_eq!(wait(&job).await, JobState::Done("out.mp3".to_string()));
+        assert_eq!(job.progress.get(), 100);
+        assert!(jobs.get(&job.id.to_string(), None).is_ok());
+    }
+
+    #[rocket::async_test]
+    async fn test_job_owner() {
+        let jobs = Jobs::new(1, reqwest::Client::new());
+        let job = jobs.submit(
+            Some("alice".to_string()),
+            Vec::new(),
+            |_| Ok("out.mp3".to_string()),
+            None,
+        );
+        let id = job.id.to_string();
+        assert!(jobs.get(&id, Some("alice")).is_ok());
+        assert!(matches!(
+            jobs.get(&id, Some("bob")),
+            Err(WaveemapiError::NotFound(_))
+        ));
+        assert!(jobs.get(&id, None).is_err());
+    }
+
+    #[rocket::async_test]
+    async fn test_job_failed() {
+        let jobs = Jobs::new(1, reqwest::Client::new());
+        let job = jobs.submit(
+            None,
+            Vec::new(),
+            |_| Err(WaveemapiError::InvalidOptions("bad".to_string())),
+            None,
+        );
+        assert_eq!(wait(&job).await, JobState::Failed("bad".to_string()));
+    }
+
+    #[rocket::async_test]
+    async fn test_jobs_queue_behind_workers() {
+        let jobs = Jobs::new(1, reqwest::Client::new());
+        let (tx, rx) = std::sync::mpsc::channel::<()>();
+        let first = jobs.submit(
+            None,
+            vec!["first.wav".to_string()],
+            move |_| {
+                rx.recv().ok();
+                Ok("first.mp3".to_string())
+            },
+            None,
+        );
+        let second = jobs.submit(
+            None,
+            vec!["second.wav".to_string()],
+            |_| Ok("second.mp3".to_string()),
+            None,
+        );
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(first.state(), JobState::Running);
+        assert_eq!(second.state(), JobState::Queued);
+        assert_eq!(
+            jobs.inputs_in_use(),
+            HashSet::from(["first.wav".into(), "second.wav".into()])
+        );
+        tx.send(()).unwrap();
+        assert!(matches!(wait(&second).await, JobState::Done(_)));
+        wait(&first).await;
+        assert!(jobs.inputs_in_use().is_empty());
+    }
+
+    #[rocket::async_test]
+    async fn test_unknown_and_pruned_jobs() {
+        let jobs = Jobs::new(1, reqwest::Client::new());
+        assert!(matches!(
+            jobs.get("not-a-uuid", None),
+            Err(WaveemapiError::NotFound(_))
+        ));
+        assert!(jobs.get(&Uuid::new_v4().to_string(), None).is_err());
+        let job = jobs.submit(None, Vec::new(), |_| Ok("out.mp3".to_string()), None);
+        wait(&job).await;
+        jobs.prune(Duration::from_secs(60));
+        assert!(jobs.get(&job.id.to_string(), None).is_ok());
+        jobs.prune(Duration::ZERO);
+        assert!(jobs.get(&job.id.to_string(), None).is_err());
+    }
+}
@@ -2,10 +2,11 @@ use figment::{
     Figment, Profile,
     providers::{Env, Format, Serialized, Toml},
 };
-use rocket::{fairing::AdHoc, tokio};
+use rocket::{Build, Rocket, fairing::AdHoc, tokio};
 use rocket_apitoken::ApiToken;
 
 use crate::helpers::{check_data_path, clear_data_path};
+use crate::jobs::Jobs;
 use std::time::Duration;
 
 #[macro_use]
@@ -13,9 +14,13 @@ extern crate rocket;
 
 mod api;
 mod audio;
+mod bridge;
 mod config;
 mod error;
 mod helpers;
+mod id3;
+mod jobs;
+mod webhook;
 
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 
@@ -25,12 +30,19 @@ fn rocket() -> _ {
     println!("WAVEEMAPI3 API v{}", VERSION);
     println!("----------------------------------");
 
-    let figment = Figment::from(rocket::Config::default())
+    build(figment())
+}
+
+/// Defaults, overridden by `waveemapi.toml` and then the environment.
+fn figment() -> Figment {
+    Figment::from(rocket::Config::default())
         .merge(Serialized::defaults(config::Config::default()))
         .merge(Toml::file("waveemapi.toml").nested())
         .merge(Env::prefixed("WAVEEMAPI_").global())
-        .select(Profile::from_env_or("WAVEEMAPI_PROFILE", "default"));
+        .select(Profile::from_env_or("WAVEEMAPI_PROFILE", "default"))
+}
 
+fn build(figment: Figment) -> Rocket<Build> {
     let auth_enabled: bool = figment.extract_inner("auth_enabled").expect("auth_enabled");
     println!("Auth bypass: {}", !auth_enabled);
     let auth_tokens: Vec<String> = figment.extract_inner("auth_tokens").expect("auth_tokens");
@@ -38,21 +50,34 @@ fn rocket() -> _ {
 
     let data_path: String = figment.extract_inner("data_path").expect("data_path");
     check_data_path(&data_path).expect("data_path");
+    let job_workers: usize = figment.extract_inner("job_workers").expect("job_workers");
+    let webhook_allowed_hosts: Vec<String> = figment
+        .extract_inner("webhook_allowed_hosts")
+        .expect("webhook_allowed_hosts");
 
     rocket::custom(figment)
         .manage(ApiToken::new(auth_tokens, auth_enabled))
+        .manage(Jobs::new(
+            job_workers,
+            webhook::client(&webhook_allowed_hosts),
+        ))
         .mount("/api/upload", api::upload_routes())
+        .mount("/api/jobs", api::jobs_routes())
+        .mount("/api/probe", api::probe_routes())
+        .mount("/api/analyze", api::analyze_routes())
+        .mount("/api/waveform", api::waveform_routes())
         .mount("/api/status", api::status_routes())
         .register("/api", api::catchers())
         .attach(AdHoc::config::<config::Config>())
         .attach(AdHoc::on_liftoff("cleanup-scheduler", |rocket| {
             Box::pin(async move {
-                setup_cleanup_scheduler(rocket.figment());
+                let jobs = rocket.state::<Jobs>().expect("jobs").clone();
+                setup_cleanup_scheduler(rocket.figment(), jobs);
             })
         }))
 }
 
-fn setup_cleanup_scheduler(figment: &Figment) {
+fn setup_cleanup_scheduler(figment: &Figment, jobs: Jobs) {
     let data_path: String = figment.extract_inner("data_path").expect("data_path");
 
     let cleanup_interval_minutes: u64 = figment
@@ -70,7 +95,12 @@ fn setup_cleanup_scheduler(figment: &Figment) {
         loop {
             interval.tick().await;
             println!("Running scheduled cleanup of data path: {}", data_path);
-            if let Err(e) = clear_data_path(&data_path, Duration::from_secs(file_expiry_seconds)) {
+            jobs.prune(Duration::from_secs(file_expiry_seconds));
+            if let Err(e) = clear_data_path(
+                &data_path,
+                Duration::from_secs(file_expiry_seconds),
+                &jobs.inputs_in_use(),
+            ) {
                 eprintln!("Error during scheduled cleanup: {}", e);
             } else {
                 println!("Scheduled cleanup completed successfully.");
@@ -78,3 +108,18 @@ fn setup_cleanup_scheduler(figment: &Figment) {
         }
     });
 }
+
+/// Bearer token accepted by `authorized_rocket`.
+#[cfg(test)]
+pub(crate) const TEST_TOKEN: &str = "test-token";
+
+/// A rocket that requires `TEST_TOKEN` and keeps its files in `data_path`.
+#[cfg(test)]
+pub(crate) fn authorized_rocket(data_path: &std::path::Path) -> Rocket<Build> {
+    build(
+        figment()
+            .merge(("auth_enabled", true))
+            .merge(("auth_tokens", [TEST_TOKEN]))
+            .merge(("data_path", data_path.to_string_lossy())),
+    )
+}
@@ -0,0 +1,438 @@
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use hmac::{Hmac, Mac};
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use reqwest::redirect;
+use rocket::serde::Serialize;
+use rocket::tokio;
+use sha2::Sha256;
+
+use crate::error::WaveemapiError;
+use crate::jobs::{Job, JobState};
+
+pub const SIGNATURE_HEADER: &str = "X-Waveemapi-Signature";
+pub const TIMESTAMP_HEADER: &str = "X-Waveemapi-Timestamp";
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_REDIRECTS: usize = 10;
+
+/// Where and how to report a finished job.
+#[derive(Debug, Clone)]
+pub struct Webhook {
+    pub url: String,
+    /// Signs the payload when the submitting token has a secret configured.
+    pub secret: Option<String>,
+    /// Base URL for the download link, relative when empty.
+    pub public_url: String,
+    pub retries: u32,
+    /// Delay before the first retry, doubled for each one after.
+    pub backoff: Duration,
+}
+
+#[derive(Debug, Serialize)]
+pub struct WebhookPayload {
+    pub id: String,
+    pub status: String,
+    /// Seconds from the job starting to run until it finished.
+    pub duration: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub download_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Only plain http(s) URLs are accepted as callbacks, and only when their host
+/// resolves to public addresses or is in `allowed_hosts`.
+pub async fn check_callback_url(
+    url: &str,
+    allowed_hosts: &[String],
+) -> Result<String, WaveemapiError> {
+    let invalid = |message: &str| WaveemapiError::InvalidOptions(message.to_string());
+    let parsed = match reqwest::Url::parse(url.trim()) {
+        Ok(parsed) if matches!(parsed.scheme(), "http" | "https") && parsed.has_host() => parsed,
+        _ => return Err(invalid("callback_url must be an http or https URL")),
+    };
+    let host = url_host(&parsed);
+    if !is_allowed(host, allowed_hosts) {
+        let addrs = lookup(host)
+            .await
+            .map_err(|_| invalid("callback_url host could not be resolved"))?;
+        if addrs.iter().any(|addr| !is_public(addr.ip())) {
+            return Err(invalid("callback_url must not point to a private network"));
+        }
+    }
+    Ok(parsed.to_string())
+}
+
+/// The host of `url`, with IPv6 addresses unbracketed.
+fn url_host(url: &reqwest::Url) -> &str {
+    let host = url.host_str().unwrap_or_default();
+    host.strip_prefix('[')
+        .and_then(|h| h.strip_suffix(']'))
+        .unwrap_or(host)
+}
+
+fn is_allowed(host: &str, allowed_hosts: &[String]) -> bool {
+    allowed_hosts
+        .iter()
+        .any(|allowed| allowed.eq_ignore_ascii_case(host))
+}
+
+async fn lookup(host: &str) -> io::Result<Vec<SocketAddr>> {
+    Ok(tokio::net::lookup_host((host, 0)).await?.collect())
+}
+
+/// Whether `ip` is reachable on the public internet, so not loopback,
+/// link-local, private, shared, documentation or otherwise reserved.
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_v4(ip),
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public_v4(ip),
+            None => is_public_v6(ip),
+        },
+    }
+}
+
+fn is_public_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, c, _] = ip.octets();
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_broadcast()
+        || ip.is_documentation()
+        || ip.is_multicast()
+        || a == 0
+        || (a == 100 && (64..128).contains(&b)) // shared address space
+        || (a == 192 && b == 0 && c == 0) // IETF protocol assignments
+        || (a == 198 && (18..20).contains(&b)) // benchmarking
+        || a >= 240)
+}
+
+fn is_public_v6(ip: Ipv6Addr) -> bool {
+    let first = ip.segments()[0];
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_multicast()
+        || (first & 0xfe00) == 0xfc00 // unique local
+        || (first & 0xffc0) == 0xfe80 // link-local
+        || (first == 0x2001 && ip.segments()[1] == 0x0db8)) // documentation
+}
+
+/// Resolves webhook hosts like the system resolver, failing for hosts that
+/// resolve to non-public addresses unless they are allowed.
+struct PublicResolver {
+    allowed_hosts: Vec<String>,
+}
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let host = name.as_str().to_string();
+        let allowed = is_allowed(&host, &self.allowed_hosts);
+        Box::pin(async move {
+            let addrs = lookup(&host).await?;
+            if !allowed && addrs.iter().any(|addr| !is_public(addr.ip())) {
+                return Err(format!("{} resolves to a private address", host).into());
+            }
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+/// The HTTP client webhooks are delivered with. Redirects and every
+/// connection are held to the same rules as `check_callback_url`.
+pub fn client(allowed_hosts: &[String]) -> reqwest::Client {
+    let redirect_hosts = allowed_hosts.to_vec();
+    let policy = redirect::Policy::custom(move |attempt| {
+        let host = url_host(attempt.url());
+        let private = host
+            .parse::<IpAddr>()
+            .is_ok_and(|ip| !is_public(ip) && !is_allowed(host, &redirect_hosts));
+        if attempt.previous().len() >= MAX_REDIRECTS {
+            attempt.error("too many redirects")
+        } else if private {
+            attempt.error("redirect to a private address")
+        } else {
+            attempt.follow()
+        }
+    });
+    reqwest::Client::builder()
+        .dns_resolver(Arc::new(PublicResolver {
+            allowed_hosts: allowed_hosts.to_vec(),
+        }))
+        .redirect(policy)
+        .build()
+        .expect("webhook client")
+}
+
+/// Hex encoded HMAC-SHA256 of `timestamp`, a dot and `body`.
+pub fn sign(secret: &str, timestamp: u64, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(format!("{}.", timestamp).as_bytes());
+    mac.update(body);
+    mac.finalize()
+        .into_bytes()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+impl Webhook {
+    pub fn payload(&self, job: &Job) -> WebhookPayload {
+        let state = job.state();
+        let (size, download_url, error) = match &state {
+            JobState::Done(path) => (
+                std::fs::metadata(path).map(|m| m.len()).ok(),
+                Some(format!(
+                    "{}/api/jobs/{}/result",
+                    self.public_url.trim_end_matches('/'),
+                    job.id
+                )),
+                None,
+            ),
+            JobState::Failed(error) => (None, None, Some(error.clone())),
+            _ => (None, None, None),
+        };
+        WebhookPayload {
+            id: job.id.to_string(),
+            status: state.name().to_string(),
+            duration: job.duration().map_or(0.0, |d| d.as_secs_f64()),
+            size,
+            download_url,
+            error,
+        }
+    }
+
+    /// Posts the payload, retrying failed deliveries with exponential backoff.
+    pub async fn deliver(&self, client: &reqwest::Client, job: &Job) {
+        let body = match rocket::serde::json::to_string(&self.payload(job)) {
+            Ok(body) => body.into_bytes(),
+            Err(e) => {
+                eprintln!("Failed to serialize webhook for job {}: {}", job.id, e);
+                return;
+            }
+        };
+        let mut delay = self.backoff;
+        for attempt in 0..=self.retries {
+            if attempt > 0 {
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+            }
+            let mut request = client
+                .post(&self.url)
+                .timeout(REQUEST_TIMEOUT)
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .body(body.clone());
+            let timestamp = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs());
+            request = request.header(TIMESTAMP_HEADER, timestamp);
+            if let Some(secret) = &self.secret {
+                let signature = sign(secret, timestamp, &body);
+                request = request.header(SIGNATURE_HEADER, format!("sha256={}", signature));
+            }
+            match request.send().await {
+                Ok(response) if response.status().is_success() => return,
+                Ok(response) => eprintln!(
+                    "Webhook for job {} returned {} (attempt {})",
+                    job.id,
+                    response.status(),
+                    attempt + 1
+                ),
+                Err(e) => eprintln!(
+                    "Webhook for job {} failed: {} (attempt {})",
+                    job.id,
+                    e,
+                    attempt + 1
+                ),
+            }
+        }
+        eprintln!("Giving up on webhook for job {}", job.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jobs::Jobs;
+    use rocket::tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use rocket::tokio::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn test_sign() {
+        // RFC 4231 test case 2, with the timestamp in front of the body
+        assert_eq!(
+            sign("Jefe", 1700000000, b"what do ya want for nothing?"),
+            "1cdd0650c8be1cb0974b1788d458b1e781206cfef59b85faafc582d2e182c57e"
+        );
+    }
+
+    #[rocket::async_test]
+    async fn test_check_callback_url() {
+        let allowed = ["10.0.0.1".to_string(), "localhost".to_string()];
+        assert!(
+            check_callback_url("https://93.184.215.14/hook", &[])
+                .await
+                .is_ok()
+        );
+        for url in [
+            "http://10.0.0.1:8080/",
+            "http://127.0.0.1/",
+            "http://localhost:8000/hook",
+            "http://169.254.169.254/latest/meta-data",
+            "http://192.168.1.1/",
+            "http://100.64.0.1/",
+            "http://[::1]/",
+            "http://[fd00::1]/",
+            "http://[::ffff:10.0.0.1]/",
+        ] {
+            assert!(check_callback_url(url, &[]).await.is_err(), "{}", url);
+        }
+        assert!(
+            check_callback_url("http://10.0.0.1:8080/", &allowed)
+                .await
+                .is_ok()
+        );
+        assert!(
+            check_callback_url("http://localhost:8000/", &allowed)
+                .await
+                .is_ok()
+        );
+        assert!(check_callback_url("ftp://example.com/", &[]).await.is_err());
+        assert!(check_callback_url("file:///etc/passwd", &[]).await.is_err());
+        assert!(check_callback_url("not a url", &[]).await.is_err());
+    }
+
+    #[test]
+    fn test_payload_serialization() {
+        let payload = WebhookPayload {
+            id: "id".to_string(),
+            status: "failed".to_string(),
+            duration: 1.5,
+            size: None,
+            download_url: None,
+            error: Some("Invalid WAV file".to_string()),
+        };
+        let json = rocket::serde::json::to_string(&payload).unwrap();
+        assert_eq!(
+            json,
+            r#"{"id":"id","status":"failed","duration":1.5,"error":"Invalid WAV file"}"#
+        );
+    }
+
+    async fn read_request(socket: &mut rocket::tokio::net::TcpStream) -> String {
+        let mut request = Vec::new();
+        let mut buf = [0u8; 4096];
+        loop {
+            let n = socket.read(&mut buf).await.unwrap();
+            request.extend_from_slice(&buf[..n]);
+            let text = String::from_utf8_lossy(&request).to_string();
+            if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                let length = head
+                    .lines()
+                    .find_map(|l| {
+                        l.to_lowercase()
+                            .strip_prefix("content-length:")
+                            .map(|v| v.trim().parse::<usize>().unwrap())
+                    })
+                    .unwrap_or(0);
+                if body.len() >= length {
+                    return text;
+                }
+            }
+            if n == 0 {
+                return text;
+            }
+        }
+    }
+
+    /// Answers each request with the next status, recording the requests.
+    async fn serve(statuses: Vec<u16>) -> (String, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let recorded = requests.clone();
+        tokio::spawn(async move {
+            for status in statuses {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let request = read_request(&mut socket).await;
+                recorded.lock().unwrap().push(request);
+                let response = format!(
+                    "HTTP/1.1 {} X\r\ncontent-length: 0\r\nconnection: close\r\n\r\n",
+                    status
+                );
+                socket.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+        (url, requests)
+    }
+
+    #[rocket::async_test]
+    async fn test_deliver_retries_and_signs() {
+        let (url, requests) = serve(vec![500, 200]).await;
+        let jobs = Jobs::new(1, reqwest::Client::new());
+        let webhook = Webhook {
+            url,
+            secret: Some("secret".to_string()),
+            public_url: "https://mp3.example.com/".to_string(),
+            retries: 2,
+            backoff: Duration::from_millis(10),
+        };
+        let job = jobs.submit(None, Vec::new(), |_| Ok("missing.mp3".to_string()), None);
+        while !matches!(job.state(), JobState::Done(_)) {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        webhook.deliver(&reqwest::Client::new(), &job).await;
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 2);
+        let request = &requests[1];
+        let body = request.split("\r\n\r\n").nth(1).unwrap();
+        assert!(body.contains(r#""status":"done""#));
+        let download_url = format!("https://mp3.example.com/api/jobs/{}/result", job.id);
+        assert!(body.contains(&download_url));
+        let timestamp = request
+            .lines()
+            .find_map(|l| {
+                l.to_lowercase()
+                    .strip_prefix("x-waveemapi-timestamp:")
+                    .map(|v| v.trim().parse::<u64>().unwrap())
+            })
+            .unwrap();
+        let signature = format!("sha256={}", sign("secret", timestamp, body.as_bytes()));
+        assert!(request.to_lowercase().contains(&signature));
+    }
+
+    #[rocket::async_test]
+    async fn test_client_refuses_private_hosts() {
+        let (url, requests) = serve(vec![200]).await;
+        let url = url.replace("127.0.0.1", "localhost");
+        let refused = client(&[]).post(&url).send().await;
+        assert!(refused.is_err());
+        assert!(requests.lock().unwrap().is_empty());
+        let allowed = client(&["localhost".to_string()]).post(&url).send().await;
+        assert!(allowed.unwrap().status().is_success());
+
+        // An allowed host cannot redirect to a private address.
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!(
+            "http://localhost:{}/",
+            listener.local_addr().unwrap().port()
+        );
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            read_request(&mut socket).await;
+            let response =
+                "HTTP/1.1 307 X\r\nlocation: http://10.0.0.1/\r\ncontent-length: 0\r\n\r\n";
+            socket.write_all(response.as_bytes()).await.unwrap();
+        });
+        let redirected = client(&["localhost".to_string()]).post(&url).send().await;
+        assert!(redirected.unwrap_err().is_redirect());
+    }
+}